@@ -5,15 +5,31 @@ use async_trait::async_trait;
 use crate::core::types::{CommandResult, CommandRegistry};
 use crate::core::variables::VariableManager;
 use crate::core::config::ShellConfig;
+use crate::core::pipeline::PipelineIo;
 
+/// A Shellce command: a built-in, or a function defined by the user.
+///
+/// `args` holds the parsed and variable-resolved arguments. `io` connects the
+/// command to its neighbours when it runs as a pipeline stage: records from the
+/// previous stage are read from it, and streaming producers emit their records
+/// through it while they run. Commands that only return a `CommandResult` have
+/// their result data forwarded downstream by the dispatcher.
 #[async_trait]
 pub trait Command: Send + Sync {
-    fn name(&self) -> &'static str;
-    fn description(&self) -> &'static str;
-    fn usage(&self) -> &'static str;
+    fn name(&self) -> &str;
+    fn description(&self) -> &str;
+    fn usage(&self) -> &str;
+
+    /// Returns `args` as they may appear in logs. Commands that receive
+    /// sensitive values as arguments (e.g. `remember --secret`) mask them here,
+    /// since the values are logged before the command runs.
+    fn redact_args(&self, args: &[String], _var_manager: &VariableManager) -> Vec<String> {
+        args.to_vec()
+    }
     async fn execute(
         &self,
         args: Vec<String>,
+        io: &mut PipelineIo,
         var_manager: &VariableManager,
         config: &ShellConfig,
         command_registry: &CommandRegistry,
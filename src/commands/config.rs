@@ -0,0 +1,257 @@
+// src/commands/config.rs
+// Implementation of the `config` command, which inspects and edits the
+// configuration in effect for the session.
+
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use serde_json::json;
+use toml::{Table, Value};
+use crate::commands::command::Command;
+use crate::core::config::ShellConfig;
+use crate::core::config_layers::{self, LayerStatus};
+use crate::core::config_validation::{self, ConfigProblem};
+use crate::core::live_config;
+use crate::core::pipeline::PipelineIo;
+use crate::core::types::{CommandResult, CommandRegistry};
+use crate::core::variables::VariableManager;
+use log::{debug, error, info};
+
+pub struct ConfigCommand;
+
+/// The configuration as a TOML table, keyed the way `config` names settings.
+fn to_table(config: &ShellConfig) -> Result<Table, String> {
+    match Value::try_from(config) {
+        Ok(Value::Table(table)) => Ok(table),
+        _ => Err("Failed to read the current configuration.".to_string()),
+    }
+}
+
+/// Whether one dotted key is the other or inside it, e.g. `aliases` and `aliases.ll`.
+fn overlaps(a: &str, b: &str) -> bool {
+    let inside = |inner: &str, outer: &str| inner.strip_prefix(outer).is_some_and(|rest| rest.is_empty() || rest.starts_with('.'));
+    inside(a, b) || inside(b, a)
+}
+
+/// Renders a setting for display: strings bare, anything else as TOML.
+fn display_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+impl ConfigCommand {
+    /// `config get KEY`: the value of one setting, or of a whole table such as `aliases`.
+    fn get(&self, key: &str, config: &ShellConfig) -> CommandResult {
+        let table = match to_table(config) {
+            Ok(table) => table,
+            Err(e) => return CommandResult::error(e),
+        };
+        match config_layers::get_key(&table, key) {
+            Some(value) => CommandResult::success(Some(display_value(value)), serde_json::to_value(value).ok()),
+            None => CommandResult::error(format!("Unknown config key '{}'.", key)),
+        }
+    }
+
+    /// `config list`: every setting and its value.
+    async fn list(&self, io: &mut PipelineIo, config: &ShellConfig) -> CommandResult {
+        let table = match to_table(config) {
+            Ok(table) => table,
+            Err(e) => return CommandResult::error(e),
+        };
+        let keys = config.sources.keys.keys();
+        if !io.is_piped() {
+            let lines: Vec<String> = keys
+                .filter_map(|key| Some(format!("{} = {}", key, config_layers::get_key(&table, key)?)))
+                .collect();
+            return CommandResult::success(Some(lines.join("\n")), None);
+        }
+        for key in keys {
+            let value = config_layers::get_key(&table, key).and_then(|v| serde_json::to_value(v).ok());
+            if !io.emit(json!({ "key": key, "value": value })).await {
+                debug!("Downstream of 'config list' closed, stopping listing");
+                break;
+            }
+        }
+        CommandResult::success(None, None)
+    }
+
+    /// `config set KEY VALUE`: changes a setting for the rest of the session.
+    /// The value is read as TOML if that gives the setting's type (`true`,
+    /// `30`, `["echo", "vars"]`) and as a string otherwise.
+    fn set(&self, key: &str, raw: &str, config: ShellConfig, registry: &CommandRegistry) -> CommandResult {
+        let value = match to_table(&config).and_then(|table| config_layers::typed_value(&table, key, raw)) {
+            Ok(value) => value,
+            Err(e) => return CommandResult::error(format!("Invalid value for '{}': {}", key, e)),
+        };
+        match self.apply(&config, |table| config_layers::set_key(table, key, value.clone())) {
+            Ok(mut updated) => {
+                updated.sources.record_session(key, &value);
+                self.commit(key, updated, registry, format!("{} = {}", key, value))
+            }
+            Err(e) => CommandResult::error(format!("Invalid value for '{}': {}", key, e)),
+        }
+    }
+
+    /// `config unset KEY`: removes an alias, or puts a setting back to its
+    /// built-in default.
+    fn unset(&self, key: &str, config: ShellConfig, registry: &CommandRegistry) -> CommandResult {
+        let mut removed = false;
+        let updated = self.apply(&config, |table| removed = config_layers::remove_key(table, key).is_some());
+        match updated {
+            Ok(_) if !removed => CommandResult::error(format!("Unknown config key '{}'.", key)),
+            Ok(mut updated) => {
+                let defaults = to_table(&ShellConfig::default()).unwrap_or_default();
+                updated.sources.record_default(key, config_layers::get_key(&defaults, key));
+                self.commit(key, updated, registry, format!("Unset {}", key))
+            }
+            Err(e) => CommandResult::error(format!("Cannot unset '{}': {}", key, e)),
+        }
+    }
+
+    /// Applies `edit` to `config` as a table, returning the result if it is
+    /// still a valid configuration. Settings removed by `edit` get their defaults.
+    fn apply(&self, config: &ShellConfig, edit: impl FnOnce(&mut Table)) -> Result<ShellConfig, String> {
+        let mut table = to_table(config)?;
+        edit(&mut table);
+        let mut updated: ShellConfig = Value::Table(table).try_into().map_err(|e: toml::de::Error| e.message().to_string())?;
+        updated.sources = config.sources.clone();
+        Ok(updated)
+    }
+
+    /// Makes `updated` the live configuration, unless validation finds an
+    /// error with `key`. Warnings are shown along with the change.
+    fn commit(&self, key: &str, updated: ShellConfig, registry: &CommandRegistry, message: String) -> CommandResult {
+        let (errors, warnings): (Vec<ConfigProblem>, Vec<ConfigProblem>) = config_validation::validate(&updated, registry)
+            .into_iter()
+            .filter(|p| p.key.as_deref().is_some_and(|problem_key| overlaps(problem_key, key)))
+            .partition(ConfigProblem::is_error);
+        if !errors.is_empty() {
+            let reasons: Vec<String> = errors.into_iter().map(|p| p.message).collect();
+            return CommandResult::error(format!("Invalid value for '{}': {}", key, reasons.join("; ")));
+        }
+        live_config::replace(updated);
+        info!("Config changed: {}", message);
+        let mut lines = vec![message];
+        lines.extend(warnings.into_iter().map(|p| format!("Warning: {}", p.message)));
+        CommandResult::success(Some(lines.join("\n")), None)
+    }
+
+    /// `config save [PATH]`: writes the settings of the file's own layer and
+    /// those changed in this session to a file, by default the project's
+    /// `.shellce.toml` if there is one, else the user config file.
+    async fn save(&self, path: Option<&str>, config: &ShellConfig) -> CommandResult {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => {
+                let [_, (_, user), (_, project)] = config_layers::layer_files();
+                match project.or(user) {
+                    Some(path) => path,
+                    None => return CommandResult::error("No config file to save to; give a path.".to_string()),
+                }
+            }
+        };
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            if let Err(e) = std::fs::create_dir_all(parent) {
+                return CommandResult::error(format!("Failed to create {}: {}", parent.display(), e));
+            }
+        }
+        match config.save(&path).await {
+            Ok(()) => CommandResult::success(
+                Some(format!("Configuration saved to {}", path.display())),
+                Some(json!({ "path": path.display().to_string() })),
+            ),
+            Err(e) => {
+                error!("Failed to save configuration: {:?}", e);
+                CommandResult::error(format!("Failed to save configuration: {:#}", e))
+            }
+        }
+    }
+
+    /// `config sources`: the layers that were considered, then each key with
+    /// its value and the layer that supplied it.
+    async fn sources(&self, io: &mut PipelineIo, config: &ShellConfig) -> CommandResult {
+        let values = match to_table(config) {
+            Ok(table) => table,
+            Err(e) => return CommandResult::error(e),
+        };
+        let sources = &config.sources;
+
+        if !io.is_piped() {
+            let mut lines = vec!["Layers, lowest precedence first:".to_string()];
+            for layer in &sources.layers {
+                let status = match &layer.status {
+                    LayerStatus::Loaded(count) => format!("{} key(s)", count),
+                    LayerStatus::NotFound => "not found".to_string(),
+                    LayerStatus::Failed { line: Some(line), reason, .. } => format!("ignored: line {}: {}", line, reason),
+                    LayerStatus::Failed { line: None, reason, .. } => format!("ignored: {}", reason),
+                };
+                let location = match (&layer.path, layer.name, &layer.status) {
+                    (Some(path), _, _) => format!(" {}", path.display()),
+                    (None, "env", LayerStatus::Failed { key: Some(key), .. }) => format!(" {}", config_layers::env_var_for(key)),
+                    (None, "env", _) => " SHELLCE_*".to_string(),
+                    (None, _, _) => String::new(),
+                };
+                lines.push(format!("  {:<8}{} ({})", layer.name, location, status));
+            }
+            lines.push(String::new());
+            lines.push("Keys:".to_string());
+            for key in sources.keys.keys() {
+                let value = config_layers::get_key(&values, key).map(|v| v.to_string()).unwrap_or_default();
+                let source = sources.source_of(key).map(|layer| layer.describe()).unwrap_or_default();
+                lines.push(format!("  {} = {}  [{}]", key, value, source));
+            }
+            return CommandResult::success(Some(lines.join("\n")), None);
+        }
+
+        for key in sources.keys.keys() {
+            let record = json!({
+                "key": key,
+                "value": config_layers::get_key(&values, key).and_then(|v| serde_json::to_value(v).ok()),
+                "source": sources.source_of(key).map(|layer| layer.name),
+                "path": sources.source_of(key).and_then(|layer| layer.path.as_ref()).map(|p| p.display().to_string()),
+            });
+            if !io.emit(record).await {
+                debug!("Downstream of 'config sources' closed, stopping listing");
+                break;
+            }
+        }
+        CommandResult::success(None, None)
+    }
+}
+
+#[async_trait]
+impl Command for ConfigCommand {
+    fn name(&self) -> &'static str { "config" }
+    fn description(&self) -> &'static str {
+        "Views and edits the configuration for this session. 'config save' writes it to a file; \
+         'config sources' shows which layer (default, system, user, project, env or session) set each key."
+    }
+    fn usage(&self) -> &'static str {
+        "config get <key> | set <key> <value> | unset <key> | list | save [path] | sources"
+    }
+    async fn execute(
+        &self,
+        args: Vec<String>,
+        io: &mut PipelineIo,
+        _var_manager: &VariableManager,
+        _config: &ShellConfig,
+        command_registry: &CommandRegistry,
+    ) -> CommandResult {
+        // The live configuration rather than the one this line started with,
+        // so `config set ...; config get ...` sees its own change.
+        let config = live_config::current();
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        match args.as_slice() {
+            ["get", key] => self.get(key, &config),
+            ["set", key, value @ ..] if !value.is_empty() => self.set(key, &value.join(" "), config, command_registry),
+            ["unset", key] => self.unset(key, config, command_registry),
+            ["list"] => self.list(io, &config).await,
+            ["save"] => self.save(None, &config).await,
+            ["save", path] => self.save(Some(path), &config).await,
+            ["sources"] => self.sources(io, &config).await,
+            _ => CommandResult::error(format!("Invalid usage. {}", self.usage())),
+        }
+    }
+}
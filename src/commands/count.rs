@@ -1,14 +1,14 @@
 // src/commands/count.rs
 // Implementation of the `count` command, designed to work with pipelines.
 
-use anyhow::Result;
 use async_trait::async_trait;
 use serde_json::json;
 use crate::commands::command::Command;
+use crate::core::pipeline::PipelineIo;
 use crate::core::types::{CommandResult, CommandRegistry};
 use crate::core::variables::VariableManager;
 use crate::core::config::ShellConfig;
-use log::{info, error, debug};
+use log::{info, debug};
 
 pub struct CountCommand;
 
@@ -23,59 +23,34 @@ impl Command for CountCommand {
     }
 
     fn usage(&self) -> &'static str {
-        "count [--input-data <json_string>]"
+        "<command> | count"
     }
 
     async fn execute(
         &self,
         args: Vec<String>,
+        io: &mut PipelineIo,
         _var_manager: &VariableManager,
         _config: &ShellConfig,
         _command_registry: &CommandRegistry,
     ) -> CommandResult {
-        let mut input_data: Option<serde_json::Value> = None;
-
-        // Look for the --input-data argument
-        let mut i = 0;
-        while i < args.len() {
-            if args[i] == "--input-data" {
-                if i + 1 < args.len() {
-                    let json_str = &args[i+1];
-                    match serde_json::from_str(json_str) {
-                        Ok(val) => {
-                            input_data = Some(val);
-                            debug!("Count command received piped input: {}", json_str);
-                        },
-                        Err(e) => {
-                            error!("Failed to parse input data JSON: {}", e);
-                            return CommandResult::error(format!("Invalid input data: {}", e));
-                        }
-                    }
-                } else {
-                    return CommandResult::error("Missing JSON string after --input-data.".to_string());
-                }
-                i += 2; // Skip both --input-data and the JSON string
-            } else {
-                // If we add more arguments to `count`, they would be processed here.
-                // For now, any other args are considered invalid.
-                return CommandResult::error(format!("Unknown argument: '{}'. Usage: {}", args[i], self.usage()));
-            }
+        if let Some(arg) = args.first() {
+            // `count` takes no arguments; its input arrives through the pipeline.
+            return CommandResult::error(format!("Unknown argument: '{}'. Usage: {}", arg, self.usage()));
+        }
+        // Records are counted as they stream in, so arbitrarily large inputs
+        // never need to be held in memory at once.
+        let mut count = 0usize;
+        while let Some(record) = io.next().await {
+            debug!("Count command received record: {}", record);
+            count += 1;
         }
-
-        let count = match input_data {
-            Some(serde_json::Value::Array(arr)) => arr.len(),
-            Some(serde_json::Value::Object(_)) => 1, // Treat a single object as 1 item
-            Some(_) => {
-                info!("Count command received non-array/object input, treating as 0 or 1.");
-                1 // Treat any other single value as 1 item for counting purposes
-            },
-            None => 0, // No input data
-        };
 
         info!("Counted {} items.", count);
         CommandResult::success(
             Some(format!("Counted {} items.", count)),
-            Some(json!({ "count": count })),
+            // A bare number, so `$(... | count)` substitutes just the count.
+            Some(json!(count)),
         )
     }
 }
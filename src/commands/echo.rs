@@ -1,13 +1,14 @@
 // src/commands/echo.rs
 // Implementation of the `echo` command.
 
-use anyhow::Result;
 use async_trait::async_trait;
 use serde_json::json;
 use crate::commands::command::Command;
+use crate::core::pipeline::PipelineIo;
 use crate::core::types::{CommandResult, CommandRegistry};
 use crate::core::variables::VariableManager;
 use crate::core::config::ShellConfig;
+use crate::util;
 use log::info;
 
 pub struct EchoCommand;
@@ -23,17 +24,28 @@ impl Command for EchoCommand {
     }
 
     fn usage(&self) -> &'static str {
-        "echo [text with {variables}]"
+        "echo [text with {variables}] | <command> | echo"
     }
 
     async fn execute(
         &self,
         args: Vec<String>,
+        io: &mut PipelineIo,
         _var_manager: &VariableManager, // Variables are resolved by the parser before execute
         _config: &ShellConfig,
         _command_registry: &CommandRegistry,
     ) -> CommandResult {
-        let text = args.join(" ");
+        // With no arguments, echo whatever the previous pipeline stage produced.
+        let text = if args.is_empty() && io.has_input() {
+            io.collect()
+                .await
+                .iter()
+                .map(util::value_to_text)
+                .collect::<Vec<_>>()
+                .join("\n")
+        } else {
+            args.join(" ")
+        };
         info!("Echoing: {}", text);
 
         CommandResult::success(
@@ -0,0 +1,110 @@
+// src/commands/env.rs
+// Implementation of the `env` and `export` commands.
+
+use async_trait::async_trait;
+use log::{debug, info};
+use serde_json::json;
+
+use crate::commands::command::Command;
+use crate::commands::remember::{parse_assignment, parse_value};
+use crate::core::config::ShellConfig;
+use crate::core::pipeline::PipelineIo;
+use crate::core::types::{CommandResult, CommandRegistry};
+use crate::core::variables::VariableManager;
+use crate::util;
+
+pub struct EnvCommand;
+
+#[async_trait]
+impl Command for EnvCommand {
+    fn name(&self) -> &'static str { "env" }
+    fn description(&self) -> &'static str { "Lists the environment that programs started with 'exec' receive." }
+    fn usage(&self) -> &'static str { "env [name]" }
+    async fn execute(
+        &self,
+        args: Vec<String>,
+        io: &mut PipelineIo,
+        var_manager: &VariableManager,
+        _config: &ShellConfig,
+        _command_registry: &CommandRegistry,
+    ) -> CommandResult {
+        if let Some(name) = args.first() {
+            return match var_manager.env_var(name) {
+                Some(_) if var_manager.is_secret(name) => CommandResult::success(
+                    Some(util::SECRET_MASK.to_string()),
+                    Some(json!({ "name": name, "value": util::SECRET_MASK })),
+                ),
+                Some(value) => CommandResult::success(
+                    Some(value.clone()),
+                    Some(json!({ "name": name, "value": value })),
+                ),
+                None => CommandResult::error(format!("Environment variable '{}' is not set.", name)),
+            };
+        }
+
+        // Piped consumers get one structured record per variable; the
+        // terminal gets `NAME=value` lines.
+        for (name, value, exported) in var_manager.effective_env() {
+            let value = if exported && var_manager.is_secret(&name) { util::SECRET_MASK.to_string() } else { value };
+            let record = if io.is_piped() {
+                json!({
+                    "name": name,
+                    "value": value,
+                    "source": if exported { "shell" } else { "process" },
+                })
+            } else {
+                json!(format!("{}={}", name, value))
+            };
+
+            if !io.emit(record).await {
+                debug!("Downstream of 'env' closed, stopping listing");
+                break;
+            }
+        }
+
+        info!("Listed {} environment variables", io.emitted());
+        CommandResult::success(None, None)
+    }
+}
+
+pub struct ExportCommand;
+
+#[async_trait]
+impl Command for ExportCommand {
+    fn name(&self) -> &'static str { "export" }
+    fn description(&self) -> &'static str { "Marks variables to be passed to programs started with 'exec'." }
+    fn usage(&self) -> &'static str { "export <key> [= <value>] | export <key>..." }
+    async fn execute(
+        &self,
+        args: Vec<String>,
+        _io: &mut PipelineIo,
+        var_manager: &VariableManager,
+        _config: &ShellConfig,
+        _command_registry: &CommandRegistry,
+    ) -> CommandResult {
+        if args.is_empty() {
+            return CommandResult::error(format!("Invalid usage. {}", self.usage()));
+        }
+
+        let names = if args.iter().any(|arg| arg == "=") {
+            let Some((key, value)) = parse_assignment(args) else {
+                return CommandResult::error(format!("Invalid usage. {}", self.usage()));
+            };
+            var_manager.set_value(key.clone(), parse_value(&value));
+            vec![key]
+        } else {
+            args
+        };
+
+        for name in &names {
+            if !var_manager.export(name) {
+                return CommandResult::error(format!("Cannot export '{}': variable not found.", name));
+            }
+        }
+
+        CommandResult::success(
+            Some(format!("Exported: {}", names.join(", "))),
+            Some(json!({ "exported": names })),
+        )
+    }
+}
@@ -1,32 +1,106 @@
-use std::process::Command;
+// src/commands/exec.rs
+// Implementation of the `exec` command, which runs an external program.
+
+use std::process::Stdio;
 
-use crate::core::types::{CommandResult, ShellCommand};
 use async_trait::async_trait;
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::Command as ProcessCommand;
+use crate::commands::command::Command;
+use crate::core::pipeline::PipelineIo;
+use crate::core::types::{CommandResult, CommandRegistry};
+use crate::core::variables::VariableManager;
+use crate::core::config::ShellConfig;
+use crate::util;
+use log::{info, debug, error};
 
 pub struct ExecCommand;
 
 #[async_trait]
-impl ShellCommand for ExecCommand {
-    async fn execute(&self, args: Vec<String>) -> CommandResult {
+impl Command for ExecCommand {
+    fn name(&self) -> &'static str {
+        "exec"
+    }
+
+    fn description(&self) -> &'static str {
+        "Runs an external program, streaming each line of its output."
+    }
+
+    fn usage(&self) -> &'static str {
+        "exec <program> [args...]"
+    }
+
+    async fn execute(
+        &self,
+        args: Vec<String>,
+        io: &mut PipelineIo,
+        var_manager: &VariableManager,
+        _config: &ShellConfig,
+        _command_registry: &CommandRegistry,
+    ) -> CommandResult {
         if args.is_empty() {
-            println!("Usage: exec <command>");
-            return Ok(());
+            return CommandResult::error(format!("Invalid usage. {}", self.usage()));
         }
 
-        let output = Command::new(&args[0])
+        let mut child = match ProcessCommand::new(&args[0])
             .args(&args[1..])
-            .output()?;
+            .envs(var_manager.exported_vars())
+            .stdin(if io.has_input() { Stdio::piped() } else { Stdio::inherit() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            // Cancelling the pipeline drops this future; make sure the child goes with it.
+            .kill_on_drop(true)
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(e) => {
+                error!("Failed to start '{}': {}", args[0], e);
+                return CommandResult::error(format!("Failed to start '{}': {}", args[0], e));
+            }
+        };
 
-        if !output.stdout.is_empty() {
-            let stdout_str = String::from_utf8_lossy(&output.stdout).to_string();
-            println!("{}", stdout_str);
-        }
+        // Records from the previous stage are fed to the child's stdin, one per
+        // line, while its stdout is streamed downstream as it is produced.
+        let stdin = child.stdin.take();
+        let input = io.take_input();
+        let stdout = child.stdout.take().expect("child stdout is piped");
+        let mut lines = BufReader::new(stdout).lines();
 
-        if !output.stderr.is_empty() {
-            let stderr_str = String::from_utf8_lossy(&output.stderr).to_string();
-            eprintln!("{}", stderr_str);
-        }
+        let feed_stdin = async move {
+            if let (Some(mut stdin), Some(mut input)) = (stdin, input) {
+                while let Some(record) = input.recv().await {
+                    let mut line = util::value_to_text(&record);
+                    line.push('\n');
+                    if stdin.write_all(line.as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+                // Dropping stdin here signals end of input to the child.
+            }
+        };
 
-        Ok(())
+        let stream_stdout = async {
+            while let Ok(Some(line)) = lines.next_line().await {
+                if !io.emit(json!(line)).await {
+                    debug!("Downstream of '{}' closed, discarding remaining output", args[0]);
+                    break;
+                }
+            }
+        };
+
+        tokio::join!(feed_stdin, stream_stdout);
+
+        match child.wait().await {
+            Ok(status) if status.success() => {
+                info!("'{}' exited successfully", args[0]);
+                CommandResult::success(None, None)
+            }
+            Ok(status) => {
+                debug!("'{}' exited with {}", args[0], status);
+                CommandResult::error(format!("'{}' exited with {}", args[0], status))
+            }
+            Err(e) => CommandResult::error(format!("Failed to wait for '{}': {}", args[0], e)),
+        }
     }
 }
@@ -1,9 +1,9 @@
 // src/commands/exit.rs
 // Implementation of the `exit` command.
 
-use anyhow::Result;
 use async_trait::async_trait;
 use crate::commands::command::Command;
+use crate::core::pipeline::PipelineIo;
 use crate::core::types::{CommandResult, CommandRegistry};
 use crate::core::variables::VariableManager;
 use crate::core::config::ShellConfig;
@@ -28,13 +28,14 @@ impl Command for ExitCommand {
     async fn execute(
         &self,
         _args: Vec<String>,
+        _io: &mut PipelineIo,
         _var_manager: &VariableManager,
         _config: &ShellConfig,
         _command_registry: &CommandRegistry,
     ) -> CommandResult {
         info!("Exit command received. Initiating shutdown.");
-        // This command will cause the main loop to break in `main.rs`
-        // We return a special result indicating the shell should exit.
-        CommandResult::success(Some("Exiting Shellce. Goodbye!".to_string()), None) // Updated message
+        // The main loop in `main.rs` stops once this result is reported,
+        // so autosave and history are written as on Ctrl-D.
+        CommandResult::exit(Some("Exiting Shellce. Goodbye!".to_string()))
     }
 }
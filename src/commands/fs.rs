@@ -1,11 +1,12 @@
-use std::path::Path;
-use anyhow::{Result, Context};
+use anyhow::Context;
 use async_trait::async_trait;
-use tokio::fs::{self, File};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use log::{debug, info};
+use serde_json::json;
+use tokio::fs;
 
-use crate::core::types::{CommandResult, CommandRegistry};
+use crate::core::types::CommandResult;
 use crate::commands::command::Command;
+use crate::core::pipeline::PipelineIo;
 
 pub struct FsCommand;
 
@@ -17,32 +18,56 @@ impl Command for FsCommand {
     async fn execute(
         &self,
         args: Vec<String>,
+        io: &mut PipelineIo,
         _var_manager: &crate::core::variables::VariableManager,
         _config: &crate::core::config::ShellConfig,
         _command_registry: &crate::core::types::CommandRegistry,
     ) -> crate::core::types::CommandResult {
         if args.is_empty() {
-            println!("Usage: ls <directory>");
-            return Err("No directory specified".to_string());
+            return CommandResult::error(format!("No directory specified. Usage: {}", self.usage()));
         }
 
         let dir_path = &args[0];
-        let mut entries = fs::read_dir(dir_path)
-            .await
-            .context("Failed to read directory")?;
+        let mut entries = match fs::read_dir(dir_path).await.context("Failed to read directory") {
+            Ok(entries) => entries,
+            Err(e) => return CommandResult::error(format!("{:#}", e)),
+        };
 
-        println!("Contents of {}:", dir_path);
-
-        while let Ok(Some(entry)) =
-            entries.next_entry().await.context("Failed to read directory entry")
-        {
+        // Entries are emitted one by one as they are read, so downstream stages
+        // can start working before the whole directory has been listed.
+        loop {
+            let entry = match entries.next_entry().await.context("Failed to read directory entry") {
+                Ok(Some(entry)) => entry,
+                Ok(None) => break,
+                Err(e) => return CommandResult::error(format!("{:#}", e)),
+            };
             let file_name = entry
                 .file_name()
                 .into_string()
                 .unwrap_or_else(|_| "<invalid utf-8>".to_string());
-            println!(" - {}", file_name);
+
+            let record = if io.is_piped() {
+                let metadata = entry.metadata().await.ok();
+                json!({
+                    "name": file_name,
+                    "type": match &metadata {
+                        Some(m) if m.is_dir() => "dir",
+                        Some(m) if m.is_symlink() => "symlink",
+                        _ => "file",
+                    },
+                    "size": metadata.map(|m| m.len()).unwrap_or(0),
+                })
+            } else {
+                json!(file_name)
+            };
+
+            if !io.emit(record).await {
+                debug!("Downstream of 'ls' closed, stopping listing of {}", dir_path);
+                break;
+            }
         }
 
-        Ok("Listed files".to_string())
+        info!("Listed {} entries in {}", io.emitted(), dir_path);
+        CommandResult::success(None, None)
     }
 }
@@ -0,0 +1,115 @@
+// src/commands/function.rs
+// User-defined functions, declared with `fn name(params) { ... }`.
+
+use async_trait::async_trait;
+use log::info;
+
+use crate::commands::command::Command;
+use crate::commands::get_command_registry;
+use crate::core::config::ShellConfig;
+use crate::core::dispatcher::CommandDispatcher;
+use crate::core::pipeline::PipelineIo;
+use crate::core::types::{CommandResult, CommandRegistry};
+use crate::core::variables::VariableManager;
+use crate::parser::ast::{CommandList, FunctionDef};
+
+/// A function defined at runtime. It is called like any other command: the
+/// records piped into it feed the first pipeline of its body, and its body's
+/// output becomes the output of its pipeline stage.
+pub struct FunctionCommand {
+    name: String,
+    params: Vec<String>,
+    body: CommandList,
+    usage: String,
+}
+
+impl FunctionCommand {
+    pub fn new(definition: FunctionDef) -> Self {
+        let mut usage = definition.name.clone();
+        for param in &definition.params {
+            usage.push_str(&format!(" <{}>", param));
+        }
+        FunctionCommand {
+            name: definition.name,
+            params: definition.params,
+            body: definition.body,
+            usage,
+        }
+    }
+}
+
+#[async_trait]
+impl Command for FunctionCommand {
+    fn name(&self) -> &str { &self.name }
+    fn description(&self) -> &str { "User-defined function." }
+    fn usage(&self) -> &str { &self.usage }
+
+    async fn execute(
+        &self,
+        args: Vec<String>,
+        io: &mut PipelineIo,
+        var_manager: &VariableManager,
+        config: &ShellConfig,
+        _command_registry: &CommandRegistry,
+    ) -> CommandResult {
+        if args.len() != self.params.len() {
+            return CommandResult::error(format!(
+                "Function '{}' expects {} argument(s), got {}. Usage: {}",
+                self.name,
+                self.params.len(),
+                args.len(),
+                self.usage
+            ));
+        }
+
+        info!("Calling function {} with args {:?}", self.name, args);
+        // Parameters and any variables the body sets are local to this call.
+        let var_manager = var_manager.new_local_scope();
+        for (param, arg) in self.params.iter().zip(args) {
+            var_manager.set(param.clone(), arg);
+        }
+
+        // Records piped into the call are read by the body's first pipeline.
+        let dispatcher = CommandDispatcher::new(get_command_registry()).with_input(io.take_input());
+        let result = dispatcher.run_list(&self.body, &var_manager, config, &io.output_sink()).await;
+
+        if result.success {
+            return CommandResult::success(None, None);
+        }
+        // Carry the reason along, so callers (`$(...)`, `if`, scripts) see why.
+        match result.error_message {
+            Some(message) => CommandResult::error(format!("Function '{}' failed: {}", self.name, message)),
+            None => CommandResult::error(format!("Function '{}' failed.", self.name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::get_command_registry;
+    use crate::core::config::ShellConfig;
+    use crate::core::dispatcher::CommandDispatcher;
+    use crate::core::output::OutputSink;
+    use crate::core::variables::VariableManager;
+    use crate::parser;
+
+    /// Runs `line` and returns the text it printed.
+    async fn run(line: &str) -> String {
+        let dispatcher = CommandDispatcher::new(get_command_registry());
+        let list = parser::parse_command_list(line).unwrap();
+        let (sink, captured) = OutputSink::capture();
+        let result = dispatcher.run_list(&list, &VariableManager::new(), &ShellConfig::default(), &sink).await;
+        assert!(result.success, "{:?}", result.error_message);
+        let text = captured.lock().unwrap().text();
+        text
+    }
+
+    #[tokio::test]
+    async fn piped_records_feed_the_body() {
+        run("fn count_piped() { count }").await;
+        assert_eq!(run("echo a | count_piped").await, "Counted 1 items.");
+        // Only the body's first pipeline reads them.
+        run("fn count_piped_twice() { count; count }").await;
+        assert_eq!(run("echo a | count_piped_twice").await, "Counted 1 items.\nCounted 0 items.");
+    }
+}
@@ -1,13 +1,14 @@
 // src/commands/greet.rs
 // Implementation of the `greet` command.
 
-use anyhow::Result;
 use async_trait::async_trait;
 use serde_json::json;
 use crate::commands::command::Command;
+use crate::core::pipeline::PipelineIo;
 use crate::core::types::{CommandResult, CommandRegistry};
 use crate::core::variables::VariableManager;
 use crate::core::config::ShellConfig;
+use crate::util;
 use log::info;
 
 pub struct GreetCommand;
@@ -29,14 +30,21 @@ impl Command for GreetCommand {
     async fn execute(
         &self,
         args: Vec<String>,
+        io: &mut PipelineIo,
         _var_manager: &VariableManager,
         _config: &ShellConfig,
         _command_registry: &CommandRegistry,
     ) -> CommandResult {
-        let name = if args.is_empty() {
-            "World".to_string() // Default name if none provided
-        } else {
+        let name = if !args.is_empty() {
             args.join(" ") // Join all arguments as the name
+        } else if let Some(data) = io.next().await {
+            // Greet whoever the previous pipeline stage named
+            match data.get("name").and_then(|n| n.as_str()) {
+                Some(name) => name.to_string(),
+                None => util::value_to_text(&data),
+            }
+        } else {
+            "World".to_string() // Default name if none provided
         };
 
         let message = format!("Hello, {}! Welcome to Shellce.", name); // Updated message
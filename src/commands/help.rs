@@ -1,13 +1,14 @@
 // src/commands/help.rs
 // Implementation of the `help` command.
 
-use anyhow::Result;
 use async_trait::async_trait;
 use serde_json::json;
-use crate::commands::command::Command;
+use crate::commands::{self, command::Command};
+use crate::core::pipeline::PipelineIo;
 use crate::core::types::{CommandResult, CommandRegistry};
 use crate::core::variables::VariableManager;
 use crate::core::config::ShellConfig;
+use crate::util;
 use log::info;
 
 pub struct HelpCommand;
@@ -19,7 +20,7 @@ impl Command for HelpCommand {
     }
 
     fn description(&self) -> &'static str {
-        "Displays help information for all commands or a specific command."
+        "Displays help information for all commands and functions, or a specific one."
     }
 
     fn usage(&self) -> &'static str {
@@ -29,6 +30,7 @@ impl Command for HelpCommand {
     async fn execute(
         &self,
         args: Vec<String>,
+        io: &mut PipelineIo,
         _var_manager: &VariableManager,
         _config: &ShellConfig,
         command_registry: &CommandRegistry,
@@ -36,7 +38,32 @@ impl Command for HelpCommand {
         let mut message = String::new();
         let mut commands_json = json!({});
 
-        if args.is_empty() {
+        // A command name may also arrive from the previous pipeline stage.
+        let requested = match args.first() {
+            Some(name) => Some(name.clone()),
+            None => io.next().await.map(|data| util::value_to_text(&data).trim().to_string()),
+        };
+
+        if let Some(cmd_name) = requested {
+            // Show help for a specific command or function
+            let function = commands::get_function(&cmd_name);
+            let cmd: Option<&dyn Command> = match command_registry.get(&cmd_name) {
+                Some(cmd) => Some(cmd.as_ref()),
+                None => function.as_deref().map(|f| f as &dyn Command),
+            };
+            if let Some(cmd) = cmd {
+                message.push_str(&format!("Help for '{}':\n", cmd.name()));
+                message.push_str(&format!("  Description: {}\n", cmd.description()));
+                message.push_str(&format!("  Usage:       {}\n", cmd.usage()));
+                commands_json[cmd.name()] = json!({
+                    "description": cmd.description(),
+                    "usage": cmd.usage()
+                });
+                info!("Displayed help for command: {}", cmd_name);
+            } else {
+                return CommandResult::error(format!("Command '{}' not found.", cmd_name));
+            }
+        } else {
             // List all commands
             message.push_str("Available commands:\n");
             let mut command_names: Vec<&String> = command_registry.keys().collect();
@@ -51,23 +78,20 @@ impl Command for HelpCommand {
                     });
                 }
             }
+            let mut function_names = commands::function_names();
+            if !function_names.is_empty() {
+                function_names.sort_unstable();
+                message.push_str("\nFunctions:\n");
+                for function in function_names.iter().filter_map(|name| commands::get_function(name)) {
+                    message.push_str(&format!("  {:<15} - {}\n", function.usage(), function.description()));
+                    commands_json[function.name()] = json!({
+                        "description": function.description(),
+                        "usage": function.usage()
+                    });
+                }
+            }
             message.push_str("\nType 'help <command_name>' for more details.");
             info!("Displayed general help.");
-        } else {
-            // Show help for a specific command
-            let cmd_name = &args[0];
-            if let Some(cmd) = command_registry.get(cmd_name) {
-                message.push_str(&format!("Help for '{}':\n", cmd.name()));
-                message.push_str(&format!("  Description: {}\n", cmd.description()));
-                message.push_str(&format!("  Usage:       {}\n", cmd.usage()));
-                commands_json[cmd.name()] = json!({
-                    "description": cmd.description(),
-                    "usage": cmd.usage()
-                });
-                info!("Displayed help for command: {}", cmd_name);
-            } else {
-                return CommandResult::error(format!("Command '{}' not found.", cmd_name));
-            }
         }
 
         CommandResult::success(
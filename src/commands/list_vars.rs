@@ -1,13 +1,14 @@
 // src/commands/list_vars.rs
 // Implementation of the `list-vars` command.
 
-use anyhow::Result;
 use async_trait::async_trait;
 use serde_json::json;
 use crate::commands::command::Command;
+use crate::core::pipeline::PipelineIo;
 use crate::core::types::{CommandResult, CommandRegistry};
-use crate::core::variables::VariableManager;
+use crate::core::variables::{Scope, VariableManager};
 use crate::core::config::ShellConfig;
+use crate::util;
 use log::info;
 
 pub struct ListVarsCommand;
@@ -29,6 +30,7 @@ impl Command for ListVarsCommand {
     async fn execute(
         &self,
         _args: Vec<String>,
+        _io: &mut PipelineIo,
         var_manager: &VariableManager,
         _config: &ShellConfig,
         _command_registry: &CommandRegistry,
@@ -42,8 +44,15 @@ impl Command for ListVarsCommand {
         } else {
             message.push_str("Remembered variables:\n");
             for (key, value) in &all_vars {
-                message.push_str(&format!("  {} = {}\n", key, value));
-                vars_json[key] = json!(value);
+                let secret = all_vars.is_secret(key);
+                let text = if secret { util::SECRET_MASK.to_string() } else { util::format_value(value) };
+                match var_manager.scope_of(key) {
+                    Some(scope) if scope != Scope::Session => {
+                        message.push_str(&format!("  {} = {} ({})\n", key, text, scope.label()));
+                    }
+                    _ => message.push_str(&format!("  {} = {}\n", key, text)),
+                }
+                vars_json[key] = if secret { json!(util::SECRET_MASK) } else { value.clone() };
             }
         }
         info!("Listed variables.");
@@ -1,16 +1,19 @@
 use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 
-use crate::core::types::{CommandResult, CommandRegistry};
+use lazy_static::lazy_static;
+
+use crate::core::types::{CommandRegistry, FunctionRegistry};
 use crate::commands::{
     greet::*, remember::*, echo::*, list_vars::*, save_load::*, help::*,
-    exit::*, source::*, ping::*, sleep::*, fs::*, count::*
+    exit::*, source::*, ping::*, sleep::*, fs::*, count::*, exec::*, scope::*, env::*,
+    snapshot::*, config::*
 };
 
 mod greet;
 mod remember;
 mod echo;
 mod list_vars;
-mod save_load;
 mod help;
 mod exit;
 mod source;
@@ -18,9 +21,49 @@ mod ping;
 mod sleep;
 mod fs;
 mod count;
+mod exec;
+mod scope;
+mod env;
+mod snapshot;
+mod config;
 pub mod command;
+pub mod function;
+pub mod save_load;
+
+lazy_static! {
+    // Built once so pipeline stages running on their own tasks can share it.
+    static ref COMMAND_REGISTRY: CommandRegistry = build_command_registry();
+    // Filled at runtime by `fn` definitions in scripts and at the prompt.
+    static ref FUNCTION_REGISTRY: RwLock<FunctionRegistry> = RwLock::new(HashMap::new());
+}
+
+/// Returns the process-wide registry of built-in commands.
+pub fn get_command_registry() -> &'static CommandRegistry {
+    &COMMAND_REGISTRY
+}
+
+/// Registers a user-defined function, replacing any earlier function of the
+/// same name. Built-in commands cannot be redefined.
+pub fn define_function(function: function::FunctionCommand) -> Result<(), String> {
+    let name = command::Command::name(&function).to_string();
+    if COMMAND_REGISTRY.contains_key(&name) {
+        return Err(format!("Cannot redefine built-in command '{}'.", name));
+    }
+    FUNCTION_REGISTRY.write().unwrap().insert(name, Arc::new(function));
+    Ok(())
+}
+
+/// Looks up a user-defined function by name.
+pub fn get_function(name: &str) -> Option<Arc<function::FunctionCommand>> {
+    FUNCTION_REGISTRY.read().unwrap().get(name).cloned()
+}
+
+/// Returns the names of all user-defined functions.
+pub fn function_names() -> Vec<String> {
+    FUNCTION_REGISTRY.read().unwrap().keys().cloned().collect()
+}
 
-pub fn get_command_registry() -> CommandRegistry {
+fn build_command_registry() -> CommandRegistry {
     let mut registry: CommandRegistry = HashMap::new();
 
     registry.insert("greet".to_string(), Box::new(GreetCommand));
@@ -36,6 +79,17 @@ pub fn get_command_registry() -> CommandRegistry {
     registry.insert("sleep".to_string(), Box::new(SleepCommand));
     registry.insert("ls".to_string(), Box::new(FsCommand));
     registry.insert("count".to_string(), Box::new(CountCommand));
+    registry.insert("exec".to_string(), Box::new(ExecCommand));
+    registry.insert("local".to_string(), Box::new(LocalCommand));
+    registry.insert("global".to_string(), Box::new(GlobalCommand));
+    registry.insert("unset".to_string(), Box::new(UnsetCommand));
+    registry.insert("env".to_string(), Box::new(EnvCommand));
+    registry.insert("export".to_string(), Box::new(ExportCommand));
+    registry.insert("snapshot".to_string(), Box::new(SnapshotCommand));
+    registry.insert("snapshots".to_string(), Box::new(SnapshotsCommand));
+    registry.insert("restore".to_string(), Box::new(RestoreCommand));
+    registry.insert("diff".to_string(), Box::new(DiffCommand));
+    registry.insert("config".to_string(), Box::new(ConfigCommand));
 
     registry
 }
@@ -1,10 +1,10 @@
 // src/commands/ping.rs
 // Implementation of the `ping` command.
 
-use anyhow::Result;
 use async_trait::async_trait;
 use serde_json::json;
 use crate::commands::command::Command;
+use crate::core::pipeline::PipelineIo;
 use crate::core::types::{CommandResult, CommandRegistry};
 use crate::core::variables::VariableManager;
 use crate::core::config::ShellConfig;
@@ -29,6 +29,7 @@ impl Command for PingCommand {
     async fn execute(
         &self,
         _args: Vec<String>,
+        _io: &mut PipelineIo,
         _var_manager: &VariableManager,
         _config: &ShellConfig,
         _command_registry: &CommandRegistry,
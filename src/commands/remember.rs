@@ -1,13 +1,14 @@
 // src/commands/remember.rs
 // Implementation of the `remember` command for variable storage.
 
-use anyhow::Result;
 use async_trait::async_trait;
-use serde_json::json;
+use serde_json::{json, Value as JsonValue};
 use crate::commands::command::Command;
+use crate::core::pipeline::PipelineIo;
 use crate::core::types::{CommandResult, CommandRegistry};
 use crate::core::variables::VariableManager;
 use crate::core::config::ShellConfig;
+use crate::util;
 use log::info;
 
 pub struct RememberCommand;
@@ -19,52 +20,132 @@ impl Command for RememberCommand {
     }
 
     fn description(&self) -> &'static str {
-        "Stores a key-value pair in memory. Usage: remember key = value"
+        "Stores a value in memory: `remember key = value`, or the records piped into it. \
+         `--secret` masks the value wherever it would be displayed."
     }
 
     fn usage(&self) -> &'static str {
-        "remember <key> = <value>"
+        "remember [--secret] <key> = <value> | <command> | remember [--secret] [--list] <key>"
+    }
+
+    fn redact_args(&self, args: &[String], var_manager: &VariableManager) -> Vec<String> {
+        let leading_flags = args.iter().take_while(|arg| arg.starts_with("--")).count();
+        let secret = args[..leading_flags].iter().any(|arg| arg == "--secret")
+            || args.get(leading_flags).is_some_and(|key| var_manager.is_secret(key));
+        if !secret {
+            return args.to_vec();
+        }
+        let mut after_equals = false;
+        args.iter()
+            .map(|arg| {
+                let masked = after_equals;
+                after_equals |= arg == "=";
+                if masked { util::SECRET_MASK.to_string() } else { arg.clone() }
+            })
+            .collect()
     }
 
     async fn execute(
         &self,
         args: Vec<String>,
+        io: &mut PipelineIo,
         var_manager: &VariableManager,
         _config: &ShellConfig,
         _command_registry: &CommandRegistry,
     ) -> CommandResult {
-        let mut key = String::new();
-        let mut value = String::new();
-        let mut found_equals = false;
+        let mut args = args;
+        let secret = take_flag(&mut args, "--secret");
+        let as_list = take_flag(&mut args, "--list");
+        let piped_key = match args.as_slice() {
+            [key] if io.has_input() => Some(key.clone()),
+            _ => None,
+        };
 
-        for arg in args {
-            if arg == "=" {
-                found_equals = true;
-                continue;
-            }
-            if !found_equals {
-                key.push_str(&arg);
-            } else {
-                if !value.is_empty() {
-                    value.push(' '); // Add space for multi-word values
+        let (key, value) = match piped_key {
+            // A single record is stored as-is, several as an array.
+            Some(key) => {
+                let mut records: Vec<JsonValue> = io.collect().await.into_iter().map(util::collapse_text).collect();
+                let value = if records.len() == 1 && !as_list {
+                    records.remove(0)
+                } else {
+                    JsonValue::Array(records)
+                };
+                (key, value)
+            },
+            None => match parse_assignment(args).filter(|(_, value)| !value.is_empty()) {
+                Some((key, value)) => (key, parse_value(&value)),
+                None => {
+                    return CommandResult::error(format!(
+                        "Invalid usage. {}",
+                        self.usage()
+                    ));
                 }
-                value.push_str(&arg);
-            }
-        }
+            },
+        };
 
-        if key.is_empty() || value.is_empty() || !found_equals {
-            return CommandResult::error(format!(
-                "Invalid usage. {}",
-                self.usage()
-            ));
+        if secret {
+            var_manager.set_secret(key.clone(), value.clone());
+        } else {
+            var_manager.set_value(key.clone(), value.clone());
         }
-
-        var_manager.set(key.clone(), value.clone());
-        info!("Remembered: {} = {}", key, value);
+        // Assigning to an existing secret keeps it secret.
+        let secret = var_manager.is_secret(&key);
+        let (text, shown) = if secret {
+            (util::SECRET_MASK.to_string(), json!(util::SECRET_MASK))
+        } else {
+            (util::format_value(&value), value)
+        };
+        info!("Remembered: {} = {}", key, text);
 
         CommandResult::success(
-            Some(format!("Remembered: {} = {}", key, value)),
-            Some(json!({ "key": key, "value": value })),
+            Some(format!("Remembered: {} = {}", key, text)),
+            Some(json!({ "key": key, "value": shown, "secret": secret })),
         )
     }
 }
+
+/// Removes `flag` from the leading arguments, returning whether it was there.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let leading_flags = args.iter().take_while(|arg| arg.starts_with("--")).count();
+    match args[..leading_flags].iter().position(|arg| arg == flag) {
+        Some(index) => {
+            args.remove(index);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Interprets a value typed on the command line: JSON numbers, booleans,
+/// `null`, arrays and objects keep their type, anything else is a string.
+pub(crate) fn parse_value(text: &str) -> JsonValue {
+    match serde_json::from_str::<JsonValue>(text) {
+        Ok(value) if !value.is_string() => value,
+        _ => JsonValue::String(text.to_string()),
+    }
+}
+
+/// Splits `key = value words...` into the key and the space-joined value.
+/// Returns `None` if there is no key or no `=`.
+pub(crate) fn parse_assignment(args: Vec<String>) -> Option<(String, String)> {
+    let mut key = String::new();
+    let mut value = String::new();
+    let mut found_equals = false;
+
+    for arg in args {
+        if arg == "=" && !found_equals {
+            found_equals = true;
+            continue;
+        }
+        if !found_equals {
+            key.push_str(&arg);
+        } else {
+            if !value.is_empty() {
+                value.push(' '); // Add space for multi-word values
+            }
+            value.push_str(&arg);
+        }
+    }
+
+    (!key.is_empty() && found_equals).then_some((key, value))
+}
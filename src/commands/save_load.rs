@@ -5,15 +5,18 @@ use anyhow::Result;
 use async_trait::async_trait;
 use std::path::PathBuf;
 use crate::commands::command::Command;
+use crate::core::pipeline::PipelineIo;
 use crate::core::types::{CommandResult, CommandRegistry};
 use crate::core::variables::VariableManager;
 use crate::core::config::ShellConfig;
-use crate::storage::MemoryStorage;
+use crate::core::types::VariableStore;
+use crate::storage::{MemoryStorage, StorageBackend};
+use crate::util;
 use log::{info, error};
+use serde_json::{json, Value as JsonValue};
 
 pub struct SaveMemoryCommand;
 
-#[async_trait]
 #[async_trait]
 impl Command for SaveMemoryCommand {
     fn name(&self) -> &'static str {
@@ -21,31 +24,46 @@ impl Command for SaveMemoryCommand {
     }
 
     fn description(&self) -> &'static str {
-        "Saves all current variables to a file."
+        "Saves all current variables to a file. Secret variables are left out unless --include-secrets is given; \
+         --encrypt protects the file with a passphrase."
     }
 
     fn usage(&self) -> &'static str {
-        "save-memory [path]"
+        "save-memory [--include-secrets] [--encrypt] [path]"
     }
 
     async fn execute(
         &self,
         args: Vec<String>,
+        _io: &mut PipelineIo,
         var_manager: &VariableManager,
         config: &ShellConfig,
-        command_registry: &CommandRegistry,
+        _command_registry: &CommandRegistry,
     ) -> CommandResult {
-        let path_str = if args.is_empty() {
-            "shellce_memory.json".to_string() // Updated default path
-        } else {
-            args[0].clone()
+        let (flags, args) = split_flags(&args);
+        if let Some(unknown) = flags.iter().find(|f| !["--include-secrets", "--encrypt"].contains(f)) {
+            return CommandResult::error(format!("Unknown option '{}'. Usage: {}", unknown, self.usage()));
+        }
+        let include_secrets = flags.contains(&"--include-secrets");
+        let encrypt = flags.contains(&"--encrypt");
+        let (path, backend) = match resolve_path(args.first(), config) {
+            Ok(resolved) => resolved,
+            Err(e) => return CommandResult::error(format!("Failed to save memory: {:#}", e)),
         };
-        let path = PathBuf::from(path_str.clone());
 
         info!("Attempting to save memory to: {:?}", path);
 
-        let store = var_manager.get_all();
-        match MemoryStorage::save(&store, &path).await {
+        let store = var_manager.persisted_store();
+        let store = if include_secrets { store } else { store.without_secrets() };
+        let passphrase = if encrypt {
+            match read_passphrase(true).await {
+                Ok(passphrase) => Some(passphrase),
+                Err(e) => return CommandResult::error(format!("Failed to save memory: {:#}", e)),
+            }
+        } else {
+            None
+        };
+        match backend.save(&store, &path, passphrase.as_deref()).await {
             Ok(_) => {
                 info!("Memory saved to: {:?}", path);
                 CommandResult::success(
@@ -55,7 +73,7 @@ impl Command for SaveMemoryCommand {
             },
             Err(e) => {
                 error!("Failed to save memory: {:?}", e);
-                CommandResult::error(format!("Failed to save memory: {}", e))
+                CommandResult::error(format!("Failed to save memory: {:#}", e))
             },
         }
     }
@@ -63,6 +81,168 @@ impl Command for SaveMemoryCommand {
 
 pub struct LoadMemoryCommand;
 
+/// Which side wins when `load-memory --merge` meets a key that exists both
+/// in the session and in the file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MergePolicy {
+    KeepExisting,
+    PreferFile,
+}
+
+/// Options of `load-memory`, parsed from its arguments.
+#[derive(Debug)]
+struct LoadOptions {
+    /// `None` replaces the whole session store, as plain `load-memory` does.
+    merge: Option<MergePolicy>,
+    only: Option<Vec<String>>,
+    prefix: String,
+    dry_run: bool,
+    path: Option<String>,
+}
+
+impl LoadOptions {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = LoadOptions {
+            merge: None,
+            only: None,
+            prefix: String::new(),
+            dry_run: false,
+            path: None,
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--merge" | "--merge=existing" => options.merge = Some(MergePolicy::KeepExisting),
+                "--merge=file" => options.merge = Some(MergePolicy::PreferFile),
+                "--dry-run" => options.dry_run = true,
+                "--only" => {
+                    let keys = args.next().ok_or("--only needs a comma-separated list of keys")?;
+                    options.only = Some(
+                        keys.split(',').map(str::trim).filter(|k| !k.is_empty()).map(String::from).collect(),
+                    );
+                }
+                "--prefix" => {
+                    options.prefix = args.next().ok_or("--prefix needs a namespace, e.g. 'ns.'")?.clone();
+                }
+                flag if flag.starts_with("--") => return Err(format!("Unknown option '{}'", flag)),
+                _ if options.path.is_some() => return Err("Only one path can be given".to_string()),
+                _ => options.path = Some(arg.clone()),
+            }
+        }
+        // Loading a subset or a namespace is only useful on top of what is there.
+        if options.merge.is_none() && (options.only.is_some() || !options.prefix.is_empty()) {
+            options.merge = Some(MergePolicy::PreferFile);
+        }
+        Ok(options)
+    }
+}
+
+/// One difference between the session store and the store a load produces.
+/// Secret values (on either side) are already masked.
+#[derive(Debug)]
+pub(crate) struct Change {
+    key: String,
+    /// "added", "updated", "removed", or "kept" (both have it and the
+    /// session value wins).
+    kind: &'static str,
+    old: JsonValue,
+    new: JsonValue,
+}
+
+impl Change {
+    fn new(kind: &'static str, key: &str, current: &VariableStore, incoming: &VariableStore) -> Self {
+        let secret = current.is_secret(key) || incoming.is_secret(key);
+        let show = |store: &VariableStore| match store.get(key) {
+            Some(_) if secret => json!(util::SECRET_MASK),
+            Some(value) => value.clone(),
+            None => JsonValue::Null,
+        };
+        Change { key: key.to_string(), kind, old: show(current), new: show(incoming) }
+    }
+
+    pub(crate) fn to_json(&self) -> JsonValue {
+        json!({ "key": self.key, "change": self.kind, "old": self.old, "new": self.new })
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        let (old, new) = (util::format_value(&self.old), util::format_value(&self.new));
+        match self.kind {
+            "added" => format!("  + {} = {}", self.key, new),
+            "removed" => format!("  - {} = {}", self.key, old),
+            "updated" => format!("  ~ {}: {} -> {}", self.key, old, new),
+            _ => format!("  = {}: keeping {} (file has {})", self.key, old, new),
+        }
+    }
+}
+
+/// Lists the keys added, updated and removed going from `old` to `new`,
+/// sorted by key.
+pub(crate) fn diff_stores(old: &VariableStore, new: &VariableStore) -> Vec<Change> {
+    let mut changes: Vec<Change> = new
+        .into_iter()
+        .filter_map(|(key, value)| match old.get(key) {
+            None => Some(Change::new("added", key, old, new)),
+            Some(existing) if existing != value => Some(Change::new("updated", key, old, new)),
+            Some(_) => None,
+        })
+        .collect();
+    changes.extend(old.keys().filter(|key| new.get(key).is_none()).map(|key| Change::new("removed", key, old, new)));
+    changes.sort_by(|a, b| a.key.cmp(&b.key));
+    changes
+}
+
+/// Copies `key` of `from` into `to` as `new_key`, along with its export,
+/// secret and global flags.
+fn copy_variable(from: &VariableStore, key: &str, to: &mut VariableStore, new_key: String) {
+    if let Some(value) = from.get(key) {
+        if from.is_exported(key) {
+            to.export(new_key.clone());
+        }
+        if from.is_secret(key) {
+            to.mark_secret(new_key.clone());
+        }
+        if from.is_global(key) {
+            to.mark_global(new_key.clone());
+        }
+        to.insert(new_key, value.clone());
+    }
+}
+
+/// Builds the session store that results from loading `file` with `options`,
+/// along with the list of changes against `current`, sorted by key.
+fn plan_load(current: &VariableStore, file: &VariableStore, options: &LoadOptions) -> (VariableStore, Vec<Change>) {
+    let mut incoming = VariableStore::new();
+    for key in file.keys() {
+        if options.only.as_ref().is_none_or(|only| only.contains(key)) {
+            copy_variable(file, key, &mut incoming, format!("{}{}", options.prefix, key));
+        }
+    }
+
+    let mut result = match options.merge {
+        Some(_) => current.clone(),
+        None => VariableStore::new(),
+    };
+    let mut changes = Vec::new();
+    for (key, value) in &incoming {
+        match current.get(key) {
+            None => changes.push(Change::new("added", key, current, &incoming)),
+            Some(existing) if existing == value => {}
+            Some(_) if options.merge == Some(MergePolicy::KeepExisting) => {
+                changes.push(Change::new("kept", key, current, &incoming));
+                continue;
+            }
+            Some(_) => changes.push(Change::new("updated", key, current, &incoming)),
+        }
+        copy_variable(&incoming, key, &mut result, key.clone());
+    }
+    if options.merge.is_none() {
+        let removed = current.keys().filter(|key| incoming.get(key).is_none());
+        changes.extend(removed.map(|key| Change::new("removed", key, current, &incoming)));
+    }
+    changes.sort_by(|a, b| a.key.cmp(&b.key));
+    (result, changes)
+}
+
 #[async_trait]
 impl Command for LoadMemoryCommand {
     fn name(&self) -> &'static str {
@@ -70,42 +250,271 @@ impl Command for LoadMemoryCommand {
     }
 
     fn description(&self) -> &'static str {
-        "Loads variables from a file, overwriting current ones."
+        "Loads variables from a file, replacing the session's variables. --merge keeps session variables \
+         (existing values win, or file values with --merge=file); --only and --prefix load a subset or \
+         under a namespace; --dry-run only shows what would change. Encrypted files ask for their passphrase."
     }
 
     fn usage(&self) -> &'static str {
-        "load-memory [path]"
+        "load-memory [--merge[=existing|file]] [--only key1,key2] [--prefix ns.] [--dry-run] [path]"
     }
 
     async fn execute(
         &self,
         args: Vec<String>,
+        _io: &mut PipelineIo,
         var_manager: &VariableManager,
-        _config: &ShellConfig,
+        config: &ShellConfig,
         _command_registry: &CommandRegistry,
     ) -> CommandResult {
-        let path_str = if args.is_empty() {
-            "shellce_memory.json".to_string() // Updated default path
-        } else {
-            args[0].clone()
+        let options = match LoadOptions::parse(&args) {
+            Ok(options) => options,
+            Err(e) => return CommandResult::error(format!("{}. Usage: {}", e, self.usage())),
+        };
+        let (path, backend) = match resolve_path(options.path.as_ref(), config) {
+            Ok(resolved) => resolved,
+            Err(e) => return CommandResult::error(format!("Failed to load memory: {:#}", e)),
         };
-        let path = PathBuf::from(path_str.clone());
 
-        info!("Attempting to load memory from: {:?}", path);
+        info!("Attempting to load memory from: {:?} ({:?})", path, options);
 
-        match MemoryStorage::load(&path).await {
-            Ok(loaded_store) => {
-                var_manager.set_all(loaded_store);
-                info!("Memory loaded from: {:?}", path);
-                CommandResult::success(
-                    Some(format!("Variables loaded from {:?}", path)),
-                    None,
-                )
+        let passphrase = match backend.is_encrypted(&path).await {
+            Ok(true) => match read_passphrase(false).await {
+                Ok(passphrase) => Some(passphrase),
+                Err(e) => return CommandResult::error(format!("Failed to load memory: {:#}", e)),
             },
+            _ => None,
+        };
+
+        let loaded = match &options.only {
+            Some(keys) => backend.load_keys(&path, keys, passphrase.as_deref()).await,
+            None => backend.load(&path, passphrase.as_deref()).await,
+        };
+        let loaded_store = match loaded {
+            Ok(store) => store,
             Err(e) => {
                 error!("Failed to load memory: {:?}", e);
-                CommandResult::error(format!("Failed to load memory: {}", e))
-            },
+                return CommandResult::error(format!("Failed to load memory: {:#}", e));
+            }
+        };
+
+        if let Some(only) = &options.only {
+            let missing: Vec<&str> = only.iter().filter(|k| loaded_store.get(k).is_none()).map(String::as_str).collect();
+            if !missing.is_empty() {
+                return CommandResult::error(format!("Not found in {:?}: {}", path, missing.join(", ")));
+            }
+        }
+
+        let current = var_manager.persisted_store();
+        let (new_store, changes) = plan_load(&current, &loaded_store, &options);
+
+        let data: Vec<JsonValue> = changes.iter().map(Change::to_json).collect();
+
+        if options.dry_run {
+            let mut lines = vec![format!("Loading {:?} would make {} change(s):", path, changes.len())];
+            lines.extend(changes.iter().map(Change::describe));
+            return CommandResult::success(Some(lines.join("\n")), Some(json!(data)));
+        }
+
+        var_manager.set_all(new_store);
+        info!("Memory loaded from: {:?} ({} change(s))", path, changes.len());
+        CommandResult::success(
+            Some(format!("Variables loaded from {:?} ({} change(s))", path, changes.len())),
+            Some(json!(data)),
+        )
+    }
+}
+
+/// The memory file to use, the given one or the configured default, and its backend.
+pub(crate) fn resolve_path(path: Option<&String>, config: &ShellConfig) -> anyhow::Result<(PathBuf, &'static dyn StorageBackend)> {
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => MemoryStorage::default_path(config)?,
+    };
+    let backend = MemoryStorage::backend_for(&path, config)?;
+    Ok((path, backend))
+}
+
+/// Splits leading `--flag` arguments from the positional ones.
+fn split_flags(args: &[String]) -> (Vec<&str>, &[String]) {
+    let count = args.iter().take_while(|arg| arg.starts_with("--")).count();
+    (args[..count].iter().map(String::as_str).collect(), &args[count..])
+}
+
+/// Environment variable that supplies the memory file passphrase, for
+/// scripts and sessions without a terminal.
+const PASSPHRASE_ENV: &str = "SHELLCE_PASSPHRASE";
+
+/// Reads the passphrase for an encrypted memory file from `SHELLCE_PASSPHRASE`,
+/// or else prompts for it on the terminal without echoing it. When
+/// `confirm` is set (i.e. when encrypting), the prompt asks twice.
+pub(crate) async fn read_passphrase(confirm: bool) -> anyhow::Result<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        if passphrase.is_empty() {
+            anyhow::bail!("{} is set but empty", PASSPHRASE_ENV);
+        }
+        return Ok(passphrase);
+    }
+
+    tokio::task::spawn_blocking(move || {
+        let passphrase = rpassword::prompt_password("Memory file passphrase: ")
+            .map_err(|e| anyhow::anyhow!("Cannot read passphrase ({}); set {} instead", e, PASSPHRASE_ENV))?;
+        if passphrase.is_empty() {
+            anyhow::bail!("The passphrase cannot be empty");
+        }
+        if confirm && rpassword::prompt_password("Repeat passphrase: ")? != passphrase {
+            anyhow::bail!("Passphrases do not match");
+        }
+        Ok(passphrase)
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::get_command_registry;
+
+    fn store(pairs: &[(&str, JsonValue)]) -> VariableStore {
+        let mut store = VariableStore::new();
+        for (key, value) in pairs {
+            store.insert(key.to_string(), value.clone());
+        }
+        store
+    }
+
+    fn options(args: &[&str]) -> LoadOptions {
+        LoadOptions::parse(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    /// The resulting store as sorted `key=value` pairs, and the changes as `kind key`.
+    fn plan(current: &VariableStore, file: &VariableStore, args: &[&str]) -> (Vec<String>, Vec<String>) {
+        let (result, changes) = plan_load(current, file, &options(args));
+        let mut values: Vec<String> = result.into_iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+        values.sort();
+        (values, changes.iter().map(|change| format!("{} {}", change.kind, change.key)).collect())
+    }
+
+    fn session() -> VariableStore {
+        store(&[("a", json!(1)), ("b", json!(2)), ("c", json!(3))])
+    }
+
+    fn file() -> VariableStore {
+        store(&[("b", json!(20)), ("c", json!(3)), ("d", json!(4))])
+    }
+
+    #[test]
+    fn plain_load_replaces_the_session() {
+        let (values, changes) = plan(&session(), &file(), &[]);
+        assert_eq!(values, vec!["b=20", "c=3", "d=4"]);
+        assert_eq!(changes, vec!["removed a", "updated b", "added d"]);
+    }
+
+    #[test]
+    fn merge_keeps_existing_values_by_default() {
+        for args in [&["--merge"][..], &["--merge=existing"]] {
+            let (values, changes) = plan(&session(), &file(), args);
+            assert_eq!(values, vec!["a=1", "b=2", "c=3", "d=4"]);
+            assert_eq!(changes, vec!["kept b", "added d"]);
+        }
+    }
+
+    #[test]
+    fn merge_file_prefers_the_file() {
+        let (values, changes) = plan(&session(), &file(), &["--merge=file"]);
+        assert_eq!(values, vec!["a=1", "b=20", "c=3", "d=4"]);
+        assert_eq!(changes, vec!["updated b", "added d"]);
+    }
+
+    #[test]
+    fn only_loads_the_given_keys_and_merges() {
+        let (values, changes) = plan(&session(), &file(), &["--only", "b, d"]);
+        assert_eq!(values, vec!["a=1", "b=20", "c=3", "d=4"]);
+        assert_eq!(changes, vec!["updated b", "added d"]);
+
+        let (values, changes) = plan(&session(), &file(), &["--only", "b,d", "--merge"]);
+        assert_eq!(values, vec!["a=1", "b=2", "c=3", "d=4"]);
+        assert_eq!(changes, vec!["kept b", "added d"]);
+    }
+
+    #[test]
+    fn prefix_loads_under_a_namespace_and_merges() {
+        let (values, changes) = plan(&session(), &file(), &["--prefix", "ns."]);
+        assert_eq!(values, vec!["a=1", "b=2", "c=3", "ns.b=20", "ns.c=3", "ns.d=4"]);
+        assert_eq!(changes, vec!["added ns.b", "added ns.c", "added ns.d"]);
+    }
+
+    #[test]
+    fn only_and_prefix_combine() {
+        let current = store(&[("ns.d", json!(40))]);
+        let (values, changes) = plan(&current, &file(), &["--only", "d", "--prefix", "ns.", "--merge"]);
+        assert_eq!(values, vec!["ns.d=40"]);
+        assert_eq!(changes, vec!["kept ns.d"]);
+
+        let (values, changes) = plan(&current, &file(), &["--only", "d", "--prefix", "ns."]);
+        assert_eq!(values, vec!["ns.d=4"]);
+        assert_eq!(changes, vec!["updated ns.d"]);
+    }
+
+    #[test]
+    fn flags_are_carried_over_and_secret_changes_masked() {
+        let mut file = file();
+        file.mark_secret("d".to_string());
+        file.export("b".to_string());
+        file.mark_global("c".to_string());
+
+        let (result, changes) = plan_load(&session(), &file, &options(&["--prefix", "ns."]));
+        assert!(result.is_secret("ns.d") && result.is_exported("ns.b") && result.is_global("ns.c"));
+        let added_d = changes.iter().find(|change| change.key == "ns.d").unwrap();
+        assert_eq!(added_d.new, json!(util::SECRET_MASK));
+    }
+
+    #[test]
+    fn options_are_parsed() {
+        let parsed = options(&["--dry-run", "--only", "x,y", "mem.json"]);
+        assert!(parsed.dry_run);
+        assert_eq!(parsed.only, Some(vec!["x".to_string(), "y".to_string()]));
+        assert_eq!(parsed.merge, Some(MergePolicy::PreferFile));
+        assert_eq!(parsed.path.as_deref(), Some("mem.json"));
+
+        let invalid = |args: &[&str]| {
+            LoadOptions::parse(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>()).unwrap_err()
+        };
+        assert_eq!(invalid(&["--only"]), "--only needs a comma-separated list of keys");
+        assert_eq!(invalid(&["--bogus"]), "Unknown option '--bogus'");
+        assert_eq!(invalid(&["a.json", "b.json"]), "Only one path can be given");
+    }
+
+    #[tokio::test]
+    async fn dry_run_changes_nothing() {
+        let dir = util::test_dir("dry-run");
+        let path = dir.join("memory.json");
+        let config = ShellConfig::default();
+        let registry = get_command_registry();
+
+        let vars = VariableManager::new();
+        vars.set_value("b".to_string(), json!(20));
+        vars.set_value("d".to_string(), json!(4));
+        let path_arg = path.display().to_string();
+        let mut io = PipelineIo::default();
+        let saved = SaveMemoryCommand.execute(vec![path_arg.clone()], &mut io, &vars, &config, registry).await;
+        assert!(saved.success, "{:?}", saved.error_message);
+
+        let vars = VariableManager::new();
+        vars.set_value("a".to_string(), json!(1));
+        vars.set_value("b".to_string(), json!(2));
+        let cases = [vec!["--dry-run"], vec!["--dry-run", "--merge=file"], vec!["--dry-run", "--only", "d", "--prefix", "ns."]];
+        for args in cases {
+            let mut args: Vec<String> = args.into_iter().map(String::from).collect();
+            args.push(path_arg.clone());
+            let result = LoadMemoryCommand.execute(args.clone(), &mut io, &vars, &config, registry).await;
+            assert!(result.success, "{:?}: {:?}", args, result.error_message);
+            assert!(result.output.and_then(|output| output.text).unwrap().contains("would make"));
+            assert_eq!(vars.get_value("a"), Some(json!(1)));
+            assert_eq!(vars.get_value("b"), Some(json!(2)));
+            assert_eq!(vars.get_value("d"), None);
+            assert_eq!(vars.get_value("ns.d"), None);
         }
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
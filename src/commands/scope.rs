@@ -0,0 +1,168 @@
+// src/commands/scope.rs
+// Implementation of the `local` and `global` variable declarations, and of
+// `unset`, which removes a variable from the current scope.
+
+use async_trait::async_trait;
+use serde_json::json;
+use crate::commands::command::Command;
+use crate::commands::remember::{parse_assignment, parse_value};
+use crate::core::pipeline::PipelineIo;
+use crate::core::types::{CommandResult, CommandRegistry};
+use crate::core::variables::VariableManager;
+use crate::core::config::ShellConfig;
+use log::info;
+
+pub struct LocalCommand;
+
+#[async_trait]
+impl Command for LocalCommand {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn description(&self) -> &'static str {
+        "Declares a variable in the current script or function, shadowing any outer one."
+    }
+
+    fn usage(&self) -> &'static str {
+        "local <key> [= <value>]"
+    }
+
+    async fn execute(
+        &self,
+        args: Vec<String>,
+        _io: &mut PipelineIo,
+        var_manager: &VariableManager,
+        _config: &ShellConfig,
+        _command_registry: &CommandRegistry,
+    ) -> CommandResult {
+        // `local name` declares the variable with an empty value.
+        let assignment = match args.as_slice() {
+            [name] => Some((name.clone(), String::new())),
+            _ => parse_assignment(args),
+        };
+        let Some((key, value)) = assignment else {
+            return CommandResult::error(format!("Invalid usage. {}", self.usage()));
+        };
+
+        let typed = parse_value(&value);
+        var_manager.set_value(key.clone(), typed.clone());
+        info!("Declared local: {} = {}", key, value);
+
+        CommandResult::success(
+            None,
+            Some(json!({ "key": key, "value": typed, "scope": "local" })),
+        )
+    }
+}
+
+pub struct GlobalCommand;
+
+#[async_trait]
+impl Command for GlobalCommand {
+    fn name(&self) -> &'static str {
+        "global"
+    }
+
+    fn description(&self) -> &'static str {
+        "Stores a variable in the global scope, visible to every script and function."
+    }
+
+    fn usage(&self) -> &'static str {
+        "global <key> = <value>"
+    }
+
+    async fn execute(
+        &self,
+        args: Vec<String>,
+        _io: &mut PipelineIo,
+        var_manager: &VariableManager,
+        _config: &ShellConfig,
+        _command_registry: &CommandRegistry,
+    ) -> CommandResult {
+        let Some((key, value)) = parse_assignment(args) else {
+            return CommandResult::error(format!("Invalid usage. {}", self.usage()));
+        };
+
+        let typed = parse_value(&value);
+        var_manager.set_global(key.clone(), typed.clone());
+        info!("Declared global: {} = {}", key, value);
+
+        CommandResult::success(
+            Some(format!("Remembered globally: {} = {}", key, value)),
+            Some(json!({ "key": key, "value": typed, "scope": "global" })),
+        )
+    }
+}
+
+pub struct UnsetCommand;
+
+#[async_trait]
+impl Command for UnsetCommand {
+    fn name(&self) -> &'static str {
+        "unset"
+    }
+
+    fn description(&self) -> &'static str {
+        "Removes variables from the current scope. Scripts and functions can only remove their own locals."
+    }
+
+    fn usage(&self) -> &'static str {
+        "unset <key>..."
+    }
+
+    async fn execute(
+        &self,
+        args: Vec<String>,
+        _io: &mut PipelineIo,
+        var_manager: &VariableManager,
+        _config: &ShellConfig,
+        _command_registry: &CommandRegistry,
+    ) -> CommandResult {
+        if args.is_empty() {
+            return CommandResult::error(format!("Invalid usage. {}", self.usage()));
+        }
+
+        let missing: Vec<&str> = args
+            .iter()
+            .filter(|key| var_manager.remove(key).is_none())
+            .map(String::as_str)
+            .collect();
+        if !missing.is_empty() {
+            return CommandResult::error(format!("Not set in this scope: {}", missing.join(", ")));
+        }
+        info!("Unset: {}", args.join(", "));
+
+        CommandResult::success(None, Some(json!({ "unset": args })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value as JsonValue;
+
+    /// Runs `command` with `args` and returns the data of its result.
+    async fn run(command: &dyn Command, args: &[&str], vars: &VariableManager) -> JsonValue {
+        let args = args.iter().map(|arg| arg.to_string()).collect();
+        let result = command
+            .execute(args, &mut PipelineIo::default(), vars, &ShellConfig::default(), crate::commands::get_command_registry())
+            .await;
+        result.output.and_then(|output| output.data).unwrap()
+    }
+
+    #[tokio::test]
+    async fn local_and_global_store_typed_values() {
+        let vars = VariableManager::new().new_local_scope();
+        let data = run(&LocalCommand, &["x", "=", "5"], &vars).await;
+        assert_eq!(data["value"], json!(5));
+        assert_eq!(vars.get_value("x"), Some(json!(5)));
+
+        let data = run(&GlobalCommand, &["flag", "=", "true"], &vars).await;
+        assert_eq!(data["value"], json!(true));
+        assert_eq!(vars.get_value("flag"), Some(json!(true)));
+
+        let data = run(&LocalCommand, &["name", "=", "a", "b"], &vars).await;
+        assert_eq!(data["value"], json!("a b"));
+    }
+}
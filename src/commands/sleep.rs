@@ -1,15 +1,15 @@
 // src/commands/sleep.rs
 // Implementation of the `sleep` command, demonstrating async behavior.
 
-use anyhow::Result;
 use async_trait::async_trait;
 use serde_json::json;
 use std::time::Duration;
 use crate::commands::command::Command;
+use crate::core::pipeline::PipelineIo;
 use crate::core::types::{CommandResult, CommandRegistry};
 use crate::core::variables::VariableManager;
 use crate::core::config::ShellConfig;
-use log::{info, error};
+use log::info;
 
 pub struct SleepCommand;
 
@@ -30,6 +30,7 @@ impl Command for SleepCommand {
     async fn execute(
         &self,
         args: Vec<String>,
+        _io: &mut PipelineIo,
         _var_manager: &VariableManager,
         _config: &ShellConfig,
         _command_registry: &CommandRegistry,
@@ -0,0 +1,234 @@
+// src/commands/snapshot.rs
+// Implementations of `snapshot`, `snapshots`, `restore` and `diff`: numbered
+// copies of the session's variables that can be compared and rolled back to.
+
+use async_trait::async_trait;
+use serde_json::{json, Value as JsonValue};
+use crate::commands::command::Command;
+use crate::commands::save_load::diff_stores;
+use crate::core::pipeline::PipelineIo;
+use crate::core::types::{CommandResult, CommandRegistry, VariableStore};
+use crate::core::variables::VariableManager;
+use crate::core::config::ShellConfig;
+use crate::storage::snapshots::Snapshots;
+use log::{debug, error, info};
+
+/// The session's and global variables as a snapshot holds them: without
+/// secrets, which are never written to snapshots.
+fn session_snapshot(var_manager: &VariableManager) -> VariableStore {
+    var_manager.persisted_store().without_secrets()
+}
+
+fn parse_id(arg: &str) -> Result<u32, String> {
+    arg.trim_start_matches('#').parse().map_err(|_| format!("Invalid snapshot id '{}'.", arg))
+}
+
+pub struct SnapshotCommand;
+
+#[async_trait]
+impl Command for SnapshotCommand {
+    fn name(&self) -> &'static str { "snapshot" }
+    fn description(&self) -> &'static str {
+        "Saves a numbered copy of the session's variables (secrets excluded) to roll back to with 'restore'."
+    }
+    fn usage(&self) -> &'static str { "snapshot [label...]" }
+    async fn execute(
+        &self,
+        args: Vec<String>,
+        _io: &mut PipelineIo,
+        var_manager: &VariableManager,
+        config: &ShellConfig,
+        _command_registry: &CommandRegistry,
+    ) -> CommandResult {
+        let label = (!args.is_empty()).then(|| args.join(" "));
+        let result = match Snapshots::open(config) {
+            Ok(snapshots) => snapshots.create(&session_snapshot(var_manager), label).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(info) => CommandResult::success(
+                Some(format!("Snapshot {} saved ({} variables).", info.id, info.count)),
+                Some(json!(info)),
+            ),
+            Err(e) => {
+                error!("Failed to create snapshot: {:?}", e);
+                CommandResult::error(format!("Failed to create snapshot: {:#}", e))
+            }
+        }
+    }
+}
+
+pub struct SnapshotsCommand;
+
+#[async_trait]
+impl Command for SnapshotsCommand {
+    fn name(&self) -> &'static str { "snapshots" }
+    fn description(&self) -> &'static str { "Lists the saved snapshots, oldest first." }
+    fn usage(&self) -> &'static str { "snapshots" }
+    async fn execute(
+        &self,
+        _args: Vec<String>,
+        io: &mut PipelineIo,
+        _var_manager: &VariableManager,
+        config: &ShellConfig,
+        _command_registry: &CommandRegistry,
+    ) -> CommandResult {
+        let snapshots = match Snapshots::open(config) {
+            Ok(snapshots) => snapshots,
+            Err(e) => return CommandResult::error(format!("Failed to list snapshots: {:#}", e)),
+        };
+        let list = match snapshots.list().await {
+            Ok(list) => list,
+            Err(e) => return CommandResult::error(format!("Failed to list snapshots: {:#}", e)),
+        };
+        if list.is_empty() {
+            return CommandResult::success(Some(format!("No snapshots in {:?}.", snapshots.dir())), None);
+        }
+
+        for info in list {
+            let record = if io.is_piped() {
+                json!(info)
+            } else {
+                json!(format!(
+                    "{:>4}  {}  {:>4} vars  {}",
+                    info.id,
+                    info.created_at,
+                    info.count,
+                    info.label.unwrap_or_default()
+                )
+                .trim_end())
+            };
+            if !io.emit(record).await {
+                debug!("Downstream of 'snapshots' closed, stopping listing");
+                break;
+            }
+        }
+        CommandResult::success(None, None)
+    }
+}
+
+pub struct RestoreCommand;
+
+#[async_trait]
+impl Command for RestoreCommand {
+    fn name(&self) -> &'static str { "restore" }
+    fn description(&self) -> &'static str {
+        "Replaces the session's variables with a snapshot. The current variables are snapshotted first, \
+         and secret variables are kept."
+    }
+    fn usage(&self) -> &'static str { "restore <id>" }
+    async fn execute(
+        &self,
+        args: Vec<String>,
+        _io: &mut PipelineIo,
+        var_manager: &VariableManager,
+        config: &ShellConfig,
+        _command_registry: &CommandRegistry,
+    ) -> CommandResult {
+        let [arg] = args.as_slice() else {
+            return CommandResult::error(format!("Invalid usage. {}", self.usage()));
+        };
+        let id = match parse_id(arg) {
+            Ok(id) => id,
+            Err(e) => return CommandResult::error(e),
+        };
+        let snapshots = match Snapshots::open(config) {
+            Ok(snapshots) => snapshots,
+            Err(e) => return CommandResult::error(format!("Failed to restore snapshot: {:#}", e)),
+        };
+        let (_, mut store) = match snapshots.load(id).await {
+            Ok(snapshot) => snapshot,
+            Err(e) => return CommandResult::error(format!("Failed to restore snapshot: {:#}", e)),
+        };
+
+        // Keep a way back, then restore.
+        let current = var_manager.persisted_store();
+        let backup = match snapshots.create(&current.without_secrets(), Some(format!("before restore {}", id))).await {
+            Ok(backup) => backup,
+            Err(e) => return CommandResult::error(format!("Failed to snapshot the current variables: {:#}", e)),
+        };
+        for (key, value) in &current {
+            if current.is_secret(key) {
+                store.mark_secret(key.clone());
+                if current.is_exported(key) {
+                    store.export(key.clone());
+                }
+                if current.is_global(key) {
+                    store.mark_global(key.clone());
+                }
+                store.insert(key.clone(), value.clone());
+            }
+        }
+        let changes = diff_stores(&current, &store);
+        var_manager.set_all(store);
+
+        info!("Restored snapshot {} ({} changes)", id, changes.len());
+        CommandResult::success(
+            Some(format!(
+                "Restored snapshot {} ({} change(s)). The previous variables are snapshot {}.",
+                id,
+                changes.len(),
+                backup.id
+            )),
+            Some(json!(changes.iter().map(|change| change.to_json()).collect::<Vec<JsonValue>>())),
+        )
+    }
+}
+
+pub struct DiffCommand;
+
+#[async_trait]
+impl Command for DiffCommand {
+    fn name(&self) -> &'static str { "diff" }
+    fn description(&self) -> &'static str {
+        "Shows the variables added, removed and changed between a snapshot and the session, or between two snapshots."
+    }
+    fn usage(&self) -> &'static str { "diff <id> [<id>]" }
+    async fn execute(
+        &self,
+        args: Vec<String>,
+        io: &mut PipelineIo,
+        var_manager: &VariableManager,
+        config: &ShellConfig,
+        _command_registry: &CommandRegistry,
+    ) -> CommandResult {
+        let ids: Result<Vec<u32>, String> = args.iter().map(|arg| parse_id(arg)).collect();
+        let ids = match ids {
+            Ok(ids) if (1..=2).contains(&ids.len()) => ids,
+            Ok(_) => return CommandResult::error(format!("Invalid usage. {}", self.usage())),
+            Err(e) => return CommandResult::error(e),
+        };
+        let snapshots = match Snapshots::open(config) {
+            Ok(snapshots) => snapshots,
+            Err(e) => return CommandResult::error(format!("Failed to diff: {:#}", e)),
+        };
+
+        let (old, old_name) = match snapshots.load(ids[0]).await {
+            Ok((_, store)) => (store, format!("snapshot {}", ids[0])),
+            Err(e) => return CommandResult::error(format!("Failed to diff: {:#}", e)),
+        };
+        let (new, new_name) = match ids.get(1) {
+            Some(&id) => match snapshots.load(id).await {
+                Ok((_, store)) => (store, format!("snapshot {}", id)),
+                Err(e) => return CommandResult::error(format!("Failed to diff: {:#}", e)),
+            },
+            None => (session_snapshot(var_manager), "the session".to_string()),
+        };
+
+        let changes = diff_stores(&old, &new);
+        if io.is_piped() {
+            for change in &changes {
+                if !io.emit(change.to_json()).await {
+                    break;
+                }
+            }
+            return CommandResult::success(None, None);
+        }
+        if changes.is_empty() {
+            return CommandResult::success(Some(format!("No differences between {} and {}.", old_name, new_name)), None);
+        }
+        let mut lines = vec![format!("{} change(s) from {} to {}:", changes.len(), old_name, new_name)];
+        lines.extend(changes.iter().map(|change| change.describe()));
+        CommandResult::success(Some(lines.join("\n")), None)
+    }
+}
@@ -1,48 +1,123 @@
-use std::path::Path;
+// src/commands/source.rs
+// Implementation of the `source` command, which runs a Shellce script.
 
-use anyhow::{Result, Context};
 use async_trait::async_trait;
-use tokio::fs::File;
-use tokio::io::{AsyncBufReadExt, BufReader};
+use log::{info, debug, error};
+use serde_json::json;
 
-use crate::core::types::{CommandResult, CommandRegistry};
 use crate::commands::command::Command;
+use crate::commands::get_command_registry;
+use crate::core::config::ShellConfig;
+use crate::core::dispatcher::CommandDispatcher;
+use crate::core::pipeline::PipelineIo;
+use crate::core::types::{CommandResult, CommandRegistry};
+use crate::core::variables::VariableManager;
+use crate::parser::{self, ast::ListOperator};
 
 pub struct SourceCommand;
 
 #[async_trait]
 impl Command for SourceCommand {
     fn name(&self) -> &'static str { "source" }
-    fn description(&self) -> &'static str { "Sources a script file." }
-    fn usage(&self) -> &'static str { "source <script_file>" }
+    fn description(&self) -> &'static str { "Runs the statements of a script file, stopping at the first failure." }
+    fn usage(&self) -> &'static str { "source [--keep-going] <script_file> [args...]" }
     async fn execute(
         &self,
         args: Vec<String>,
-        _var_manager: &crate::core::variables::VariableManager,
-        _config: &crate::core::config::ShellConfig,
-        _command_registry: &crate::core::types::CommandRegistry,
-    ) -> crate::core::types::CommandResult {
-        if args.is_empty() {
-            println!("Usage: source <script_file>");
-            return Err("No script file specified".to_string());
+        _io: &mut PipelineIo,
+        var_manager: &VariableManager,
+        config: &ShellConfig,
+        _command_registry: &CommandRegistry,
+    ) -> CommandResult {
+        let mut args = args.into_iter().peekable();
+        let keep_going = args.next_if(|arg| arg == "--keep-going").is_some();
+        let Some(script_path) = args.next() else {
+            return CommandResult::error(format!("No script file specified. Usage: {}", self.usage()));
+        };
+        let script_args: Vec<String> = args.collect();
+
+        let script = match tokio::fs::read_to_string(&script_path).await {
+            Ok(script) => script,
+            Err(e) => {
+                error!("Failed to open script file {}: {}", script_path, e);
+                return CommandResult::error(format!("Failed to open script file '{}': {}", script_path, e));
+            }
+        };
+
+        let list = match parser::parse_command_list(&script) {
+            Ok(list) => list,
+            Err(e) => {
+                error!("Failed to parse script file {}: {}", script_path, e);
+                return CommandResult::error(format!(
+                    "Parsing error in '{}': {}",
+                    script_path,
+                    e.render(&script)
+                ));
+            }
+        };
+
+        info!("Sourcing script {} with args {:?}", script_path, script_args);
+        // The script's variables, including its positionals, live in a local
+        // scope that is discarded when it returns.
+        let var_manager = &var_manager.new_local_scope();
+        for (name, value) in positionals(&script_path, &script_args) {
+            var_manager.set(name, value);
         }
 
-        let script_path = &args[0];
-        let file = File::open(script_path)
-            .await
-            .context("Failed to open script file")?;
+        let dispatcher = CommandDispatcher::new(get_command_registry());
+        let line_of = |offset: usize| script[..offset].matches('\n').count() + 1;
+        let mut executed = 0;
+        let mut failures = Vec::new();
+        let mut result = CommandResult::success(None, None);
+        let mut failed_line = 0;
 
-        let reader = BufReader::new(file);
-        let mut lines = reader.lines();
+        for (i, (op, statement)) in list.items.iter().enumerate() {
+            if op.should_run(result.success) {
+                let line_number = line_of(statement.span().start);
+                debug!("{}:{}: {:?}", script_path, line_number, statement);
+                result = dispatcher.run_statement(statement, var_manager, config).await;
+                executed += 1;
+                if !result.success {
+                    failed_line = line_number;
+                }
+            }
 
-        while let Ok(Some(line_result)) =
-            lines.next_line().await.context("Failed to read line from script")
-        {
-            println!("> {}", line_result); // Optional: echo command
-            // Here you would parse and dispatch the command
+            // A failure only stops the script once its `&&`/`||` chain is
+            // over, so `cmd || handle-failure` works as expected.
+            let chain_ends = list.items.get(i + 1).is_none_or(|(next, _)| *next == ListOperator::Sequence);
+            if chain_ends && !result.success {
+                error!("Script {} failed at line {}", script_path, failed_line);
+                failures.push(failed_line);
+                if !keep_going {
+                    break;
+                }
+                result = CommandResult::success(None, None);
+            }
         }
 
-        Ok("Sourced script".to_string())
+        match failures.first() {
+            None => CommandResult::success(
+                None,
+                Some(json!({ "script": script_path, "statements_executed": executed })),
+            ),
+            Some(&line_number) if !keep_going => CommandResult::error(format!(
+                "Script '{}' failed at line {}.",
+                script_path, line_number
+            )),
+            Some(_) => CommandResult::error(format!(
+                "Script '{}' finished with failures at line(s) {}.",
+                script_path,
+                failures.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(", ")
+            )),
+        }
     }
 }
 
+/// The positional variables of a script: `{0}` (the script path), `{1}`,
+/// `{2}`, ... and `{@}` (all arguments).
+fn positionals(script_path: &str, script_args: &[String]) -> Vec<(String, String)> {
+    let mut bindings = vec![("0".to_string(), script_path.to_string())];
+    bindings.extend(script_args.iter().enumerate().map(|(i, arg)| ((i + 1).to_string(), arg.clone())));
+    bindings.push(("@".to_string(), script_args.join(" ")));
+    bindings
+}
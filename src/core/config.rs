@@ -1,10 +1,17 @@
 // src/core/config.rs
-// Manages application configuration loaded from config.toml.
+// Manages application configuration loaded from config.toml files; see
+// `config_layers` for where they are looked for.
 
 use anyhow::{Result, Context};
 use serde::{Deserialize, Serialize};
 use std::fs;
-use log::{info, error};
+use std::io::ErrorKind;
+use std::path::Path;
+use log::info;
+use toml::{Table, Value};
+
+use crate::core::config_layers::ConfigSources;
+use crate::storage::write_atomic;
 
 /// Represents the structure of the `config.toml` file.
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -19,6 +26,11 @@ pub struct ShellConfig {
     pub history_file: String,
     #[serde(default)]
     pub theme: ThemeConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    /// Which layer supplied each setting; filled in by `load_layered`.
+    #[serde(skip)]
+    pub sources: ConfigSources,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -31,6 +43,33 @@ pub struct ThemeConfig {
     pub error_color: String,
 }
 
+/// Where `save-memory` and `load-memory` keep variables.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct StorageConfig {
+    /// The backend for files whose extension doesn't pick one: json, toml or sqlite.
+    #[serde(default = "default_storage_backend")]
+    pub backend: String,
+    /// The memory file used when no path is given; `shellce_memory.<ext>` if unset.
+    #[serde(default)]
+    pub memory_file: Option<String>,
+    /// Load the memory file at startup.
+    #[serde(default)]
+    pub autoload: bool,
+    /// Save the memory file on exit, and while the session runs once changes
+    /// have settled.
+    #[serde(default)]
+    pub autosave: bool,
+    /// The longest a change waits to be autosaved, in seconds; 0 saves on exit only.
+    #[serde(default = "default_autosave_interval")]
+    pub autosave_interval_secs: u64,
+    /// Where `snapshot` keeps its copies of the variables.
+    #[serde(default = "default_snapshot_dir")]
+    pub snapshot_dir: String,
+    /// How many snapshots to keep; the oldest are removed first.
+    #[serde(default = "default_max_snapshots")]
+    pub max_snapshots: usize,
+}
+
 impl Default for ShellConfig {
     fn default() -> Self {
         ShellConfig {
@@ -39,6 +78,8 @@ impl Default for ShellConfig {
             enabled_commands: Vec::new(), // By default, all registered commands are enabled
             history_file: default_history_file(),
             theme: ThemeConfig::default(),
+            storage: StorageConfig::default(),
+            sources: ConfigSources::default(),
         }
     }
 }
@@ -53,6 +94,20 @@ impl Default for ThemeConfig {
     }
 }
 
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            backend: default_storage_backend(),
+            memory_file: None,
+            autoload: false,
+            autosave: false,
+            autosave_interval_secs: default_autosave_interval(),
+            snapshot_dir: default_snapshot_dir(),
+            max_snapshots: default_max_snapshots(),
+        }
+    }
+}
+
 fn default_prompt() -> String {
     "sc> ".to_string() // Updated default prompt
 }
@@ -73,26 +128,48 @@ fn default_error_color() -> String {
     "red".to_string()
 }
 
+fn default_storage_backend() -> String {
+    "json".to_string()
+}
+
+fn default_autosave_interval() -> u64 {
+    30
+}
+
+fn default_snapshot_dir() -> String {
+    "shellce_snapshots".to_string()
+}
+
+fn default_max_snapshots() -> usize {
+    50
+}
+
 impl ShellConfig {
-    /// Loads the configuration from the specified TOML file.
-    pub fn load(path: &str) -> Result<Self> {
-        info!("Attempting to load configuration from: {}", path);
-        let content = fs::read_to_string(path)
-            .with_context(|| format!("Failed to read config file: {}", path))?;
-        let config: ShellConfig = toml::from_str(&content)
-            .with_context(|| format!("Failed to parse config file: {}", path))?;
-        info!("Configuration loaded successfully.");
-        Ok(config)
+    /// Loads the configuration from every layer (system, user, project and
+    /// environment) on top of the defaults.
+    pub fn load_layered() -> Result<Self> {
+        crate::core::config_layers::load_layered()
     }
 
-    /// Saves the current configuration to the specified TOML file.
-    pub fn save(&self, path: &str) -> Result<()> {
-        info!("Attempting to save configuration to: {}", path);
-        let content = toml::to_string_pretty(self)
+    /// Saves the settings that belong in the TOML file at `path` (see
+    /// `ConfigSources::saved_table`), replacing the file atomically.
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        info!("Attempting to save configuration to: {}", path.display());
+        let existing = match fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text)
+                .with_context(|| format!("{} is not valid TOML; fix or remove it first", path.display()))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Table::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read config file: {}", path.display())),
+        };
+        let Ok(Value::Table(merged)) = Value::try_from(self) else {
+            anyhow::bail!("Failed to serialize config to TOML");
+        };
+        let content = toml::to_string_pretty(&self.sources.saved_table(path, &merged, &existing))
             .context("Failed to serialize config to TOML")?;
-        fs::write(path, content)
-            .with_context(|| format!("Failed to write config file: {}", path))?;
-        info!("Configuration saved successfully to: {}", path);
+        write_atomic(path, content.as_bytes())
+            .await
+            .with_context(|| format!("Failed to write config file: {}", path.display()))?;
+        info!("Configuration saved successfully to: {}", path.display());
         Ok(())
     }
 }
@@ -0,0 +1,481 @@
+// src/core/config_layers.rs
+// Builds the `ShellConfig` from layered sources: defaults, system, user,
+// project and environment.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use toml::{Table, Value};
+
+use crate::core::config::ShellConfig;
+
+/// Prefix of environment variables that override config keys. Nested keys
+/// are separated by a double underscore, e.g. `SHELLCE_THEME__PROMPT_COLOR`.
+const ENV_PREFIX: &str = "SHELLCE_";
+const ENV_SEPARATOR: &str = "__";
+
+/// The system-wide config file.
+const SYSTEM_CONFIG: &str = "/etc/shellce/config.toml";
+/// The name of a project's config file, looked for in the working
+/// directory and each of its parents.
+const PROJECT_CONFIG: &str = ".shellce.toml";
+
+/// One layer of configuration, lowest precedence first.
+#[derive(Debug, Clone)]
+pub struct ConfigLayer {
+    /// "default", "system", "user", "project" or "env".
+    pub name: &'static str,
+    /// The file the layer was read from, if it is file-based.
+    pub path: Option<PathBuf>,
+    pub status: LayerStatus,
+}
+
+#[derive(Debug, Clone)]
+pub enum LayerStatus {
+    /// Applied, setting this many keys.
+    Loaded(usize),
+    /// The file does not exist (or there is no such directory to look in).
+    NotFound,
+    /// The file exists but could not be used; it was skipped.
+    Failed { line: Option<usize>, key: Option<String>, reason: String },
+}
+
+impl ConfigLayer {
+    /// How the layer is shown as the source of a key, e.g. `project (/src/app/.shellce.toml)`.
+    pub fn describe(&self) -> String {
+        match &self.path {
+            Some(path) => format!("{} ({})", self.name, path.display()),
+            None => self.name.to_string(),
+        }
+    }
+}
+
+/// Where the current configuration came from: every layer that was
+/// considered, and which of them supplied each key.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSources {
+    pub layers: Vec<ConfigLayer>,
+    /// Dotted key (e.g. `theme.prompt_color`) to the index of the layer in
+    /// `layers` that set it.
+    pub keys: BTreeMap<String, usize>,
+}
+
+impl ConfigSources {
+    /// The layer that supplied `key`.
+    pub fn source_of(&self, key: &str) -> Option<&ConfigLayer> {
+        self.keys.get(key).map(|&index| &self.layers[index])
+    }
+
+    /// Records `key` (and, for a table, every key inside it) as set during
+    /// the session by `config set`.
+    pub fn record_session(&mut self, key: &str, value: &Value) {
+        let index = match self.layers.iter().position(|layer| layer.name == "session") {
+            Some(index) => index,
+            None => {
+                self.layers.push(ConfigLayer { name: "session", path: None, status: LayerStatus::Loaded(0) });
+                self.layers.len() - 1
+            }
+        };
+        self.forget(key);
+        let count = record_keys(&nest(key, value.clone()), "", index, &mut self.keys);
+        if let LayerStatus::Loaded(total) = &mut self.layers[index].status {
+            *total += count;
+        }
+    }
+
+    /// Records `key` as back to its built-in default, after `config unset`.
+    pub fn record_default(&mut self, key: &str, default: Option<&Value>) {
+        self.forget(key);
+        if let Some(value) = default {
+            record_keys(&nest(key, value.clone()), "", 0, &mut self.keys);
+        }
+    }
+
+    /// The settings `config save` writes to the file at `target`: every key
+    /// that file's layer or the session set, with its value from `merged`.
+    /// Keys the file sets but a later layer overrides keep their value from
+    /// `existing`, the file's current contents; keys from earlier layers are
+    /// left out. A file that isn't one of the layers keeps all its known keys.
+    pub fn saved_table(&self, target: &Path, merged: &Table, existing: &Table) -> Table {
+        let same_file = |path: &Path| path == target || path.canonicalize().ok().is_some_and(|path| Some(path) == target.canonicalize().ok());
+        let target = self.layers.iter().position(|layer| layer.path.as_deref().is_some_and(same_file));
+        let mut table = Table::new();
+        for (key, &index) in &self.keys {
+            let value = if self.layers[index].name == "session" || Some(index) == target {
+                get_key(merged, key)
+            } else if target.is_none_or(|target| index > target) {
+                get_key(existing, key)
+            } else {
+                None
+            };
+            if let Some(value) = value {
+                set_key(&mut table, key, value.clone());
+            }
+        }
+        table
+    }
+
+    /// Drops `key` and every key inside it.
+    fn forget(&mut self, key: &str) {
+        let prefix = format!("{}.", key);
+        self.keys.retain(|existing, _| existing != key && !existing.starts_with(&prefix));
+    }
+}
+
+/// Loads the configuration from every layer, each overriding the ones
+/// before it:
+///
+/// 1. built-in defaults,
+/// 2. `/etc/shellce/config.toml`,
+/// 3. `$XDG_CONFIG_HOME/shellce/config.toml` (the platform config directory if unset),
+/// 4. the nearest `.shellce.toml` in the working directory or its parents,
+/// 5. `SHELLCE_*` environment variables.
+///
+/// Tables (such as `aliases`) are merged key by key. A file that can't be
+/// read or parsed is skipped and reported in the layer's status.
+pub fn load_layered() -> Result<ShellConfig> {
+    let files = layer_files();
+
+    let defaults = Value::try_from(ShellConfig::default()).context("Failed to serialize default config")?;
+    let Value::Table(mut merged) = defaults else {
+        anyhow::bail!("Default config is not a table");
+    };
+    let mut sources = ConfigSources::default();
+    record_keys(&merged, "", 0, &mut sources.keys);
+    sources.layers.push(ConfigLayer { name: "default", path: None, status: LayerStatus::Loaded(sources.keys.len()) });
+
+    for (name, path) in files {
+        let index = sources.layers.len();
+        let status = match &path {
+            Some(path) if path.is_file() => match read_table(path) {
+                Ok(table) => {
+                    let count = record_keys(&table, "", index, &mut sources.keys);
+                    merge(&mut merged, table);
+                    info!("Loaded {} config from {}", name, path.display());
+                    LayerStatus::Loaded(count)
+                }
+                Err(status) => {
+                    if let LayerStatus::Failed { reason, .. } = &status {
+                        warn!("Skipping {} config {}: {}", name, path.display(), reason);
+                    }
+                    status
+                }
+            },
+            _ => LayerStatus::NotFound,
+        };
+        sources.layers.push(ConfigLayer { name, path, status });
+    }
+
+    // Each variable is applied on its own, so one with a value of the wrong
+    // type is reported and skipped without losing the others.
+    let index = sources.layers.len();
+    let mut env_table = Table::new();
+    let mut rejected = Vec::new();
+    for (name, key, raw) in env_overrides(&merged, std::env::vars()) {
+        match typed_value(&merged, &key, &raw) {
+            Ok(value) => {
+                info!("Config override from environment: {}", name);
+                set_key(&mut merged, &key, value.clone());
+                set_key(&mut env_table, &key, value);
+            }
+            Err(reason) => {
+                warn!("Ignoring {}: {}", name, reason);
+                rejected.push(ConfigLayer {
+                    name: "env",
+                    path: None,
+                    status: LayerStatus::Failed { line: None, key: Some(key), reason },
+                });
+            }
+        }
+    }
+    let count = record_keys(&env_table, "", index, &mut sources.keys);
+    sources.layers.push(ConfigLayer {
+        name: "env",
+        path: None,
+        status: if count > 0 { LayerStatus::Loaded(count) } else { LayerStatus::NotFound },
+    });
+    sources.layers.extend(rejected);
+
+    let mut config: ShellConfig = Value::Table(merged)
+        .try_into()
+        .context("Invalid configuration after merging all layers")?;
+    config.sources = sources;
+    Ok(config)
+}
+
+/// The file of each file-based layer, lowest precedence first. The project
+/// layer has no file if there is no `.shellce.toml` above the working directory.
+pub fn layer_files() -> [(&'static str, Option<PathBuf>); 3] {
+    let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    [
+        ("system", Some(PathBuf::from(SYSTEM_CONFIG))),
+        ("user", user_config_path()),
+        ("project", find_project_config(&cwd)),
+    ]
+}
+
+pub fn user_config_path() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => dirs::config_dir()?,
+    };
+    Some(base.join("shellce").join("config.toml"))
+}
+
+/// Looks for `.shellce.toml` in `start` and each of its parents.
+fn find_project_config(start: &Path) -> Option<PathBuf> {
+    start.ancestors().map(|dir| dir.join(PROJECT_CONFIG)).find(|path| path.is_file())
+}
+
+/// Reads a config file, failing with the line and key at fault (where
+/// known) if it isn't valid TOML or a setting has the wrong type.
+fn read_table(path: &Path) -> std::result::Result<Table, LayerStatus> {
+    let text = std::fs::read_to_string(path).map_err(|e| LayerStatus::Failed {
+        line: None,
+        key: None,
+        reason: format!("Failed to read file: {}", e),
+    })?;
+    let describe = |e: toml::de::Error| {
+        let line = e.span().map(|span| line_of(&text, span.start));
+        let key = line.and_then(|line| key_lines(&text).find(|(at, _)| *at == line)).map(|(_, key)| key);
+        let reason = e.message().lines().map(str::trim).filter(|l| !l.is_empty()).collect::<Vec<_>>().join("; ");
+        LayerStatus::Failed { line, key, reason }
+    };
+    // Every setting has a default, so a partial file deserializes on its own;
+    // doing so catches type errors while their position is still known.
+    toml::from_str::<ShellConfig>(&text).map_err(describe)?;
+    toml::from_str(&text).map_err(describe)
+}
+
+/// The 1-based line of the byte `offset` in `text`.
+fn line_of(text: &str, offset: usize) -> usize {
+    text[..offset.min(text.len())].matches('\n').count() + 1
+}
+
+/// The 1-based line of the file `text` that sets the dotted `key` (such as
+/// `theme.prompt_color`), whether under a `[theme]` header or written out in full.
+pub fn find_key_line(text: &str, key: &str) -> Option<usize> {
+    key_lines(text).find(|(_, full)| full == key).map(|(line, _)| line)
+}
+
+/// Each line of `text` that sets a key, with its 1-based number and the key
+/// in dotted form.
+fn key_lines(text: &str) -> impl Iterator<Item = (usize, String)> + '_ {
+    let mut table = String::new();
+    text.lines().enumerate().filter_map(move |(index, line)| {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if let Some(header) = line.strip_prefix('[') {
+            table = header.trim_end_matches(']').trim().to_string();
+            return None;
+        }
+        let (name, _) = line.split_once('=')?;
+        let name: String = name.split('.').map(|part| part.trim().trim_matches('"')).collect::<Vec<_>>().join(".");
+        let full = if table.is_empty() { name } else { format!("{}.{}", table, name) };
+        Some((index + 1, full))
+    })
+}
+
+/// The `SHELLCE_*` variable that overrides the dotted `key`.
+pub fn env_var_for(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.replace('.', ENV_SEPARATOR).to_uppercase())
+}
+
+/// Records `index` as the source of every leaf key of `table`, returning how many there were.
+fn record_keys(table: &Table, prefix: &str, index: usize, keys: &mut BTreeMap<String, usize>) -> usize {
+    table
+        .iter()
+        .map(|(key, value)| {
+            let path = format!("{}{}", prefix, key);
+            match value {
+                Value::Table(inner) => record_keys(inner, &format!("{}.", path), index, keys),
+                _ => {
+                    keys.insert(path, index);
+                    1
+                }
+            }
+        })
+        .sum()
+}
+
+/// Merges `overlay` into `base`: tables recursively, anything else replaced.
+fn merge(base: &mut Table, overlay: Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base_table)), Value::Table(overlay_table)) => merge(base_table, overlay_table),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Picks out the `SHELLCE_*` variables that override config keys, as
+/// (variable, dotted key, raw value), sorted by variable. Only variables
+/// naming a top-level key of `known` are used, so unrelated ones (like
+/// `SHELLCE_PASSPHRASE`) are left alone.
+fn env_overrides(known: &Table, vars: impl Iterator<Item = (String, String)>) -> Vec<(String, String, String)> {
+    let mut overrides: Vec<_> = vars
+        .filter_map(|(name, raw)| {
+            let rest = name.strip_prefix(ENV_PREFIX)?;
+            let path: Vec<String> = rest.split(ENV_SEPARATOR).map(str::to_lowercase).collect();
+            if path.iter().any(String::is_empty) || !known.contains_key(&path[0]) {
+                return None;
+            }
+            Some((name, path.join("."), raw))
+        })
+        .collect();
+    overrides.sort();
+    overrides
+}
+
+/// Reads `raw` as the value of the dotted `key` in the config `table`: as
+/// TOML if that gives the setting's type (`true`, `30`, `["echo"]`), else as
+/// a plain string, so `208` works for a color and `yes` for a prompt. Fails
+/// if neither is valid for the setting.
+pub fn typed_value(table: &Table, key: &str, raw: &str) -> std::result::Result<Value, String> {
+    let parsed = parse_value(raw);
+    let mut candidates = vec![parsed.clone()];
+    if !parsed.is_str() {
+        candidates.push(Value::String(raw.to_string()));
+    }
+
+    let mut last_error = String::new();
+    for value in candidates {
+        let mut trial = table.clone();
+        set_key(&mut trial, key, value.clone());
+        match Value::Table(trial).try_into::<ShellConfig>() {
+            Ok(_) => return Ok(value),
+            Err(e) => last_error = e.message().to_string(),
+        }
+    }
+    Err(last_error)
+}
+
+/// Reads a setting's value as TOML where it parses (`true`, `30`,
+/// `["echo"]`) and as a plain string otherwise.
+pub fn parse_value(raw: &str) -> Value {
+    toml::from_str::<Table>(&format!("v = {}", raw))
+        .ok()
+        .and_then(|mut parsed| parsed.remove("v"))
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
+/// Wraps `value` in one table per segment of the dotted `key`.
+fn nest(key: &str, value: Value) -> Table {
+    let (leaf, parents) = match key.split_once('.') {
+        Some((first, rest)) => (first, Some(rest)),
+        None => (key, None),
+    };
+    let value = match parents {
+        Some(rest) => Value::Table(nest(rest, value)),
+        None => value,
+    };
+    let mut table = Table::new();
+    table.insert(leaf.to_string(), value);
+    table
+}
+
+/// Sets the dotted `key` in `table`, creating the tables on the way.
+pub fn set_key(table: &mut Table, key: &str, value: Value) {
+    // Merging would combine a table value with the one it replaces.
+    remove_key(table, key);
+    merge(table, nest(key, value));
+}
+
+/// Removes the dotted `key` from `table`, returning its value.
+pub fn remove_key(table: &mut Table, key: &str) -> Option<Value> {
+    match key.split_once('.') {
+        Some((first, rest)) => match table.get_mut(first)? {
+            Value::Table(inner) => remove_key(inner, rest),
+            _ => None,
+        },
+        None => table.remove(key),
+    }
+}
+
+/// Looks up the dotted `key` (such as `theme.prompt_color`) in `table`.
+pub fn get_key<'a>(table: &'a Table, key: &str) -> Option<&'a Value> {
+    match key.split_once('.') {
+        Some((first, rest)) => match table.get(first)? {
+            Value::Table(inner) => get_key(inner, rest),
+            _ => None,
+        },
+        None => table.get(key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_key_line_handles_headers_dotted_keys_and_comments() {
+        let text = "# prompt = \"no\"\nprompt = \"sc> \"\ntheme.error_color = \"red\"\n\n[theme]\nprompt_color = \"green\" # = x\n[aliases]\n\"ll\" = \"ls -l\"\n";
+        assert_eq!(find_key_line(text, "prompt"), Some(2));
+        assert_eq!(find_key_line(text, "theme.error_color"), Some(3));
+        assert_eq!(find_key_line(text, "theme.prompt_color"), Some(6));
+        assert_eq!(find_key_line(text, "aliases.ll"), Some(8));
+        assert_eq!(find_key_line(text, "history_file"), None);
+    }
+
+    #[test]
+    fn env_overrides_only_take_known_top_level_keys() {
+        let Value::Table(known) = Value::try_from(ShellConfig::default()).unwrap() else { unreachable!() };
+        let vars = [
+            ("SHELLCE_THEME__PROMPT_COLOR", "208"),
+            ("SHELLCE_PROMPT", "> "),
+            ("SHELLCE_PASSPHRASE", "secret"),
+            ("SHELLCE_THEME____X", "1"),
+            ("OTHER", "1"),
+        ]
+        .map(|(name, value)| (name.to_string(), value.to_string()));
+
+        let overrides = env_overrides(&known, vars.into_iter());
+        assert_eq!(
+            overrides,
+            vec![
+                ("SHELLCE_PROMPT".to_string(), "prompt".to_string(), "> ".to_string()),
+                ("SHELLCE_THEME__PROMPT_COLOR".to_string(), "theme.prompt_color".to_string(), "208".to_string()),
+            ]
+        );
+        assert_eq!(env_var_for("theme.prompt_color"), "SHELLCE_THEME__PROMPT_COLOR");
+    }
+
+    #[test]
+    fn typed_value_falls_back_to_a_string() {
+        let Value::Table(table) = Value::try_from(ShellConfig::default()).unwrap() else { unreachable!() };
+        assert_eq!(typed_value(&table, "theme.prompt_color", "208"), Ok(Value::String("208".to_string())));
+        assert_eq!(typed_value(&table, "storage.autosave", "true"), Ok(Value::Boolean(true)));
+        assert!(typed_value(&table, "storage.autosave", "maybe").is_err());
+    }
+
+    #[test]
+    fn saved_table_keeps_only_the_target_layer_and_session_keys() {
+        let layer = |name, path: Option<&str>| ConfigLayer { name, path: path.map(PathBuf::from), status: LayerStatus::Loaded(1) };
+        let mut sources = ConfigSources {
+            layers: vec![layer("default", None), layer("user", Some("/u.toml")), layer("project", Some("/p.toml")), layer("env", None)],
+            keys: BTreeMap::new(),
+        };
+        for (key, index) in [("prompt", 2), ("history_file", 3), ("theme.success_color", 1), ("theme.error_color", 0)] {
+            sources.keys.insert(key.to_string(), index);
+        }
+        sources.record_session("aliases.ll", &Value::String("ls -l".to_string()));
+        let merged: Table = toml::from_str(
+            "prompt = \"p> \"\nhistory_file = \"env.txt\"\naliases.ll = \"ls -l\"\n[theme]\nsuccess_color = \"cyan\"\nerror_color = \"red\"\n",
+        )
+        .unwrap();
+        let existing: Table = toml::from_str("prompt = \"old> \"\nhistory_file = \"file.txt\"\n").unwrap();
+
+        let saved = sources.saved_table(Path::new("/p.toml"), &merged, &existing);
+        let expected: Table =
+            toml::from_str("prompt = \"p> \"\nhistory_file = \"file.txt\"\naliases.ll = \"ls -l\"\n").unwrap();
+        assert_eq!(saved, expected);
+
+        // The user file gets the session's change but not the project's prompt.
+        let saved = sources.saved_table(Path::new("/u.toml"), &merged, &Table::new());
+        let expected: Table = toml::from_str("aliases.ll = \"ls -l\"\ntheme.success_color = \"cyan\"\n").unwrap();
+        assert_eq!(saved, expected);
+    }
+}
@@ -0,0 +1,400 @@
+// src/core/config_validation.rs
+// Checks a loaded `ShellConfig` for settings that can't work, and points at
+// the file and line each one came from.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::commands;
+use crate::core::config::ShellConfig;
+use crate::core::config_layers::{self, LayerStatus};
+use crate::core::prompt;
+use crate::core::theme::ThemeColor;
+use crate::core::types::CommandRegistry;
+use crate::parser::lexer;
+
+/// How serious a `ConfigProblem` is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The setting can't work; `--strict` refuses it.
+    Error,
+    /// The setting may work, depending on what is defined later (such as an
+    /// alias to a function a script defines).
+    Warning,
+}
+
+/// A setting that is invalid, or a config file that could not be used.
+#[derive(Debug, Clone)]
+pub struct ConfigProblem {
+    /// Where the setting came from: a file, a `SHELLCE_*` variable or the defaults.
+    pub origin: String,
+    pub line: Option<usize>,
+    /// The dotted key at fault, e.g. `theme.prompt_color`, if known.
+    pub key: Option<String>,
+    pub message: String,
+    pub severity: Severity,
+}
+
+impl ConfigProblem {
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    fn warning(self) -> Self {
+        ConfigProblem { severity: Severity::Warning, ..self }
+    }
+}
+
+impl fmt::Display for ConfigProblem {
+    /// Formats as `origin:line: key: message`, leaving out what isn't known.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.origin)?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        if let Some(key) = &self.key {
+            write!(f, ": {}", key)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Checks `config` and returns every problem found, in the order of:
+/// config files that were skipped, the prompt template, theme colors,
+/// aliases (unknown targets and cycles), `enabled_commands`, and the
+/// history file. An alias to a
+/// command that isn't built in is only a warning, as it may name a function
+/// that isn't defined yet.
+pub fn validate(config: &ShellConfig, registry: &CommandRegistry) -> Vec<ConfigProblem> {
+    let locator = Locator::new(config);
+    let mut problems = Vec::new();
+
+    for layer in &config.sources.layers {
+        if let LayerStatus::Failed { line, key, reason } = &layer.status {
+            let origin = match (&layer.path, key) {
+                (Some(path), _) => path.display().to_string(),
+                (None, Some(key)) if layer.name == "env" => format!("${}", config_layers::env_var_for(key)),
+                (None, _) => layer.name.to_string(),
+            };
+            problems.push(ConfigProblem {
+                origin,
+                line: *line,
+                key: key.clone(),
+                message: reason.clone(),
+                severity: Severity::Error,
+            });
+        }
+    }
+
+    if let Err(message) = prompt::check(&config.prompt) {
+        problems.push(locator.problem("prompt", None, message));
+    }
+
+    let theme = &config.theme;
+    for (key, value) in [
+        ("theme.prompt_color", &theme.prompt_color),
+        ("theme.success_color", &theme.success_color),
+        ("theme.error_color", &theme.error_color),
+    ] {
+        if let Err(message) = value.parse::<ThemeColor>() {
+            problems.push(locator.problem(key, None, message));
+        }
+    }
+
+    let functions = commands::function_names();
+    let mut aliases: Vec<&String> = config.aliases.keys().collect();
+    aliases.sort();
+    for alias in aliases {
+        let key = format!("aliases.{}", alias);
+        match resolve_alias_chain(alias, &config.aliases) {
+            AliasChain::Resolved(name) if !registry.contains_key(&name) && !functions.contains(&name) => {
+                let message = format!("'{}' is not a built-in command or a defined function", name);
+                problems.push(locator.problem(&key, None, message).warning());
+            }
+            AliasChain::Resolved(_) => {}
+            AliasChain::Empty => problems.push(locator.problem(&key, None, "alias target is empty".to_string())),
+            AliasChain::Invalid(reason) => {
+                problems.push(locator.problem(&key, None, format!("invalid alias target: {}", reason)));
+            }
+            AliasChain::Cycle(chain) => {
+                problems.push(locator.problem(&key, None, format!("alias cycle: {}", chain.join(" -> "))));
+            }
+        }
+    }
+
+    for name in &config.enabled_commands {
+        if !registry.contains_key(name) {
+            problems.push(locator.problem(
+                "enabled_commands",
+                Some(&format!("\"{}\"", name)),
+                format!("'{}' is not a built-in command", name),
+            ));
+        }
+    }
+
+    if let Err(message) = check_writable(Path::new(&config.history_file)) {
+        problems.push(locator.problem("history_file", None, message));
+    }
+
+    problems
+}
+
+/// `problem` as shown at startup and on reload: `Config problem: ...`, or
+/// `Config warning: ...` for a warning.
+pub fn describe(problem: &ConfigProblem) -> String {
+    match problem.severity {
+        Severity::Error => format!("Config problem: {}", problem),
+        Severity::Warning => format!("Config warning: {}", problem),
+    }
+}
+
+/// Where following an alias through other aliases ends up.
+enum AliasChain {
+    /// The command the alias finally runs.
+    Resolved(String),
+    /// Some alias on the way has an empty target.
+    Empty,
+    /// Some alias on the way has a target that doesn't tokenize, e.g. an
+    /// unterminated quote.
+    Invalid(String),
+    /// The aliases visited, ending with the one that repeats.
+    Cycle(Vec<String>),
+}
+
+/// Follows `alias` through the alias table the way the dispatcher does. An
+/// alias may name itself as its target (`ls = "ls -l"`) to shadow a
+/// built-in command; any other repeat is a cycle.
+fn resolve_alias_chain(alias: &str, aliases: &HashMap<String, String>) -> AliasChain {
+    let mut chain = vec![alias.to_string()];
+    let mut name = alias.to_string();
+    while let Some(target) = aliases.get(&name) {
+        let words = match lexer::split_words(target) {
+            Ok(words) => words,
+            Err(e) => return AliasChain::Invalid(e.to_string()),
+        };
+        let Some(next) = words.into_iter().next() else {
+            return AliasChain::Empty;
+        };
+        let next = next.as_str();
+        if next == name {
+            break;
+        }
+        let repeated = chain.iter().any(|seen| seen == next);
+        chain.push(next.to_string());
+        if repeated {
+            return AliasChain::Cycle(chain);
+        }
+        name = next.to_string();
+    }
+    AliasChain::Resolved(name)
+}
+
+/// Numbers the probe files of `check_writable`, so concurrent checks never share one.
+static PROBE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Checks that the file at `path` can be appended to, or created if missing.
+/// Permission bits don't tell (ACLs, read-only mounts, running as root), so
+/// this opens the file, or creates and removes a probe file next to it.
+fn check_writable(path: &Path) -> Result<(), String> {
+    if path.exists() {
+        return OpenOptions::new()
+            .append(true)
+            .open(path)
+            .map(|_| ())
+            .map_err(|e| format!("'{}' is not writable: {}", path.display(), e));
+    }
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let unique = PROBE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let probe = parent.join(format!(".shellce-probe.{}.{}", std::process::id(), unique));
+    match OpenOptions::new().write(true).create_new(true).open(&probe) {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&probe);
+            Ok(())
+        }
+        Err(e) => Err(format!("'{}' cannot be created: {}", path.display(), e)),
+    }
+}
+
+/// Finds where in the config files each key was set.
+struct Locator<'a> {
+    config: &'a ShellConfig,
+    /// Contents of the layer files, read once.
+    texts: HashMap<PathBuf, String>,
+}
+
+impl<'a> Locator<'a> {
+    fn new(config: &'a ShellConfig) -> Self {
+        let texts = config
+            .sources
+            .layers
+            .iter()
+            .filter_map(|layer| layer.path.clone())
+            .filter_map(|path| std::fs::read_to_string(&path).ok().map(|text| (path, text)))
+            .collect();
+        Locator { config, texts }
+    }
+
+    /// A problem with `key`, located in the layer that set it. `needle` narrows
+    /// the line down to the first one from the key onwards containing it, for
+    /// an element of a multi-line array.
+    fn problem(&self, key: &str, needle: Option<&str>, message: String) -> ConfigProblem {
+        let layer = self.config.sources.source_of(key);
+        let (origin, line) = match layer {
+            Some(layer) if layer.name == "env" => (format!("${}", config_layers::env_var_for(key)), None),
+            Some(layer) => match &layer.path {
+                Some(path) => {
+                    let line = self.texts.get(path).and_then(|text| {
+                        let start = config_layers::find_key_line(text, key)?;
+                        let found = needle.and_then(|needle| {
+                            text.lines().skip(start - 1).position(|line| line.contains(needle))
+                        });
+                        Some(start + found.unwrap_or(0))
+                    });
+                    (path.display().to_string(), line)
+                }
+                None => (layer.name.to_string(), None),
+            },
+            None => ("default".to_string(), None),
+        };
+        ConfigProblem { origin, line, key: Some(key.to_string()), message, severity: Severity::Error }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::config_layers::{ConfigLayer, ConfigSources};
+    use crate::util;
+
+    const PROJECT: &str = r#"prompt = "sc> "
+enabled_commands = [
+    "echo",
+    "bogus",
+]
+
+[theme]
+prompt_color = "nope"
+
+[aliases]
+a = "b"
+b = "a -x"
+echo = "echo -n"
+ll = "gone -l"
+e = ""
+q = '"open'
+"#;
+
+    /// Validates `PROJECT` as if read from a project file, with
+    /// `theme.error_color` set (badly) from the environment and a rejected
+    /// `SHELLCE_THEME__SUCCESS_COLOR`.
+    fn problems(test: &str) -> Vec<ConfigProblem> {
+        let dir = util::test_dir(&format!("validate-{}", test));
+        let path = dir.join(".shellce.toml");
+        std::fs::write(&path, PROJECT).unwrap();
+
+        let mut config: ShellConfig = toml::from_str(PROJECT).unwrap();
+        config.theme.error_color = "nope".to_string();
+        config.history_file = dir.join("history.txt").display().to_string();
+        let mut sources = ConfigSources::default();
+        sources.layers.push(ConfigLayer { name: "default", path: None, status: LayerStatus::Loaded(0) });
+        sources.layers.push(ConfigLayer { name: "project", path: Some(path), status: LayerStatus::Loaded(0) });
+        sources.layers.push(ConfigLayer { name: "env", path: None, status: LayerStatus::Loaded(1) });
+        sources.layers.push(ConfigLayer {
+            name: "env",
+            path: None,
+            status: LayerStatus::Failed {
+                line: None,
+                key: Some("theme.success_color".to_string()),
+                reason: "invalid type".to_string(),
+            },
+        });
+        for key in ["prompt", "enabled_commands", "theme.prompt_color"] {
+            sources.keys.insert(key.to_string(), 1);
+        }
+        for alias in config.aliases.keys() {
+            sources.keys.insert(format!("aliases.{}", alias), 1);
+        }
+        sources.keys.insert("theme.error_color".to_string(), 2);
+        config.sources = sources;
+
+        let problems = validate(&config, commands::get_command_registry());
+        let _ = std::fs::remove_dir_all(&dir);
+        problems
+    }
+
+    fn find<'a>(problems: &'a [ConfigProblem], key: &str) -> &'a ConfigProblem {
+        problems
+            .iter()
+            .find(|problem| problem.key.as_deref() == Some(key))
+            .unwrap_or_else(|| panic!("no problem with {}", key))
+    }
+
+    #[test]
+    fn problems_point_at_the_line_that_set_the_key() {
+        let problems = problems("lines");
+
+        let color = find(&problems, "theme.prompt_color");
+        assert!(color.origin.ends_with(".shellce.toml"));
+        assert_eq!(color.line, Some(8));
+        assert!(color.is_error());
+
+        // An element of a multi-line array is found on its own line.
+        let enabled = find(&problems, "enabled_commands");
+        assert_eq!(enabled.line, Some(4));
+        assert_eq!(enabled.message, "'bogus' is not a built-in command");
+        assert_eq!(enabled.to_string(), format!("{}:4: enabled_commands: {}", enabled.origin, enabled.message));
+    }
+
+    #[test]
+    fn aliases_report_cycles_and_bad_targets() {
+        let problems = problems("aliases");
+
+        assert_eq!(find(&problems, "aliases.a").message, "alias cycle: a -> b -> a");
+        assert_eq!(find(&problems, "aliases.b").message, "alias cycle: b -> a -> b");
+        assert_eq!(find(&problems, "aliases.a").line, Some(11));
+        assert_eq!(find(&problems, "aliases.e").message, "alias target is empty");
+        assert_eq!(find(&problems, "aliases.q").message, "invalid alias target: Unterminated double quote.");
+        // An alias may shadow the command it names.
+        assert!(problems.iter().all(|problem| problem.key.as_deref() != Some("aliases.echo")));
+    }
+
+    #[test]
+    fn an_alias_to_an_unknown_command_is_only_a_warning() {
+        let problems = problems("warning");
+
+        let unknown = find(&problems, "aliases.ll");
+        assert_eq!(unknown.severity, Severity::Warning);
+        assert_eq!(unknown.message, "'gone' is not a built-in command or a defined function");
+        assert!(describe(unknown).starts_with("Config warning: "));
+        assert!(describe(find(&problems, "aliases.e")).starts_with("Config problem: "));
+    }
+
+    #[test]
+    fn keys_from_the_environment_name_their_variable() {
+        let problems = problems("env");
+
+        let set = find(&problems, "theme.error_color");
+        assert_eq!(set.origin, "$SHELLCE_THEME__ERROR_COLOR");
+        assert_eq!(set.line, None);
+
+        let rejected = find(&problems, "theme.success_color");
+        assert_eq!(rejected.origin, "$SHELLCE_THEME__SUCCESS_COLOR");
+        assert_eq!(rejected.message, "invalid type");
+        assert_eq!(rejected.to_string(), "$SHELLCE_THEME__SUCCESS_COLOR: theme.success_color: invalid type");
+    }
+
+    #[test]
+    fn history_file_in_a_missing_directory_is_a_problem() {
+        assert!(check_writable(Path::new("/nonexistent-shellce-dir/history.txt")).is_err());
+        let dir = util::test_dir("validation-writable");
+        assert!(check_writable(&dir.join("history.txt")).is_ok());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,32 +1,61 @@
 // src/core/dispatcher.rs
 // Contains the main command dispatching logic, including pipeline execution.
 
+use std::future::Future;
+use std::ops::Deref;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
 use anyhow::{Result, Context};
+use serde_json::Value as JsonValue;
 use log::{info, error, debug};
-use crate::core::types::{CommandResult, CommandRegistry, PipelineCommand, CommandOutput};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use crate::commands::{self, command::Command, function::FunctionCommand};
+use crate::core::types::{CommandResult, CommandRegistry, PipelineCommand};
+use crate::core::output::{CapturedOutput, OutputSink};
+use crate::core::pipeline::{PipelineIo, PIPELINE_BUFFER};
 use crate::core::variables::VariableManager;
 use crate::core::config::ShellConfig;
-use crate::parser; // Import the parser module
-use serde_json::Value as JsonValue;
+use crate::parser::{self, lexer, variable_resolver}; // Import the parser module
+use crate::parser::ast::{CommandList, ElseBranch, ForLoop, IfStatement, Pipeline, Statement, WordPart};
+use crate::util;
 
 /// `CommandDispatcher` is responsible for parsing input, resolving aliases,
 /// and executing the appropriate command or pipeline.
 pub struct CommandDispatcher {
     command_registry: &'static CommandRegistry, // Changed to take a static reference
+    /// Records piped into a function call, handed to the first pipeline of
+    /// its body that runs.
+    input: Mutex<Option<mpsc::Receiver<JsonValue>>>,
 }
 
 impl CommandDispatcher {
     /// Creates a new `CommandDispatcher` with a given command registry.
     pub fn new(command_registry: &'static CommandRegistry) -> Self { // Changed signature
-        CommandDispatcher { command_registry }
+        CommandDispatcher { command_registry, input: Mutex::new(None) }
+    }
+
+    /// Feeds `input` to the first stage of the first pipeline this dispatcher
+    /// runs, as when a function body reads the records piped into the call.
+    pub fn with_input(self, input: Option<mpsc::Receiver<JsonValue>>) -> Self {
+        CommandDispatcher { input: Mutex::new(input), ..self }
     }
 
-    /// Dispatches a command or pipeline based on the input line.
+    /// Dispatches a command line: one or more statements joined by `;`, `&&`
+    /// and `||`, where a statement is a pipeline or an `if`, `while` or `for`.
     ///
     /// This method performs:
-    /// 1. Alias resolution.
-    /// 2. Pipeline parsing and variable resolution.
-    /// 3. Sequential command execution within the pipeline, passing output as input.
+    /// 1. Parsing of the whole input into a `CommandList`.
+    /// 2. Variable resolution and command substitution for each pipeline,
+    ///    right before it runs.
+    /// 3. Alias resolution for each command of the pipeline.
+    /// 4. Concurrent execution of the pipeline stages, streaming each stage's
+    ///    records to the next one.
+    ///
+    /// The result of every pipeline that runs is reported as soon as it
+    /// completes. `&&` and `||` decide whether the next pipeline runs based on
+    /// the success of the most recent result.
     ///
     /// # Arguments
     /// * `command_line` - The raw string input from the user.
@@ -34,7 +63,8 @@ impl CommandDispatcher {
     /// * `config` - Reference to the shell configuration.
     ///
     /// # Returns
-    /// A `CommandResult` indicating the outcome of the command execution.
+    /// The `CommandResult` of the last pipeline that ran, or a success if
+    /// there was nothing to run.
     pub async fn dispatch_command(
         &self,
         command_line: &str,
@@ -43,74 +73,478 @@ impl CommandDispatcher {
     ) -> CommandResult {
         debug!("Dispatching command: '{}'", command_line);
 
-        // 1. Alias Resolution
-        let mut effective_command_line = command_line.to_string();
-        for (alias, real_command) in &config.aliases {
-            if effective_command_line.starts_with(alias) {
-                effective_command_line = effective_command_line.replacen(alias, real_command, 1);
-                info!("Alias resolved: '{}' -> '{}'", alias, effective_command_line);
-                break;
+        // 1. Command List Parsing
+        let list = match parser::parse_command_list(command_line) {
+            Ok(list) => list,
+            Err(e) => {
+                error!("Command list parsing error: {}", e);
+                let result = CommandResult::error(format!("Parsing error: {}", e.render(command_line)));
+                OutputSink::Terminal.report(&result, &config.theme).await;
+                return result;
+            }
+        };
+
+        self.run_list(&list, var_manager, config, &OutputSink::Terminal).await
+    }
+
+    /// Runs the statements of a `CommandList` in order, honouring `&&` and
+    /// `||`, and reports each pipeline's result to `output` as soon as it
+    /// completes.
+    pub fn run_list<'a>(
+        &'a self,
+        list: &'a CommandList,
+        var_manager: &'a VariableManager,
+        config: &'a ShellConfig,
+        output: &'a OutputSink,
+    ) -> Pin<Box<dyn Future<Output = CommandResult> + Send + 'a>> {
+        Box::pin(async move {
+            let mut result = CommandResult::success(None, None);
+            for (i, (op, statement)) in list.items.iter().enumerate() {
+                if i > 0 && !op.should_run(result.success) {
+                    debug!("Skipping statement at {:?} after '{}'", statement.span(), op.symbol());
+                    continue;
+                }
+                result = self.run_statement_to(statement, var_manager, config, output).await;
+            }
+            result
+        })
+    }
+
+    /// Runs a single top-level statement, printing its output to the
+    /// terminal. Used by `source` to run a script statement by statement.
+    pub async fn run_statement(
+        &self,
+        statement: &Statement,
+        var_manager: &VariableManager,
+        config: &ShellConfig,
+    ) -> CommandResult {
+        self.run_statement_to(statement, var_manager, config, &OutputSink::Terminal).await
+    }
+
+    async fn run_statement_to(
+        &self,
+        statement: &Statement,
+        var_manager: &VariableManager,
+        config: &ShellConfig,
+        output: &OutputSink,
+    ) -> CommandResult {
+        match statement {
+            Statement::Pipeline(pipeline) => {
+                let result = self.dispatch_pipeline(pipeline, var_manager, config, output).await;
+                output.report(&result, &config.theme).await;
+                result
+            },
+            Statement::If(statement) => self.run_if(statement, var_manager, config, output).await,
+            Statement::While(statement) => {
+                let mut result = CommandResult::success(None, None);
+                while self.run_list(&statement.condition, var_manager, config, output).await.success {
+                    result = self.run_list(&statement.body, var_manager, config, output).await;
+                }
+                result
+            },
+            Statement::For(statement) => self.run_for(statement, var_manager, config, output).await,
+            Statement::Function(definition) => {
+                let result = match commands::define_function(FunctionCommand::new(definition.clone())) {
+                    Ok(()) => {
+                        info!("Defined function '{}' with parameters {:?}", definition.name, definition.params);
+                        CommandResult::success(None, None)
+                    },
+                    Err(message) => CommandResult::error(message),
+                };
+                output.report(&result, &config.theme).await;
+                result
+            },
+        }
+    }
+
+    /// Runs the first branch of an `if`/`else if` chain whose condition
+    /// succeeds, or the `else` block. Succeeds without output if no branch runs.
+    fn run_if<'a>(
+        &'a self,
+        statement: &'a IfStatement,
+        var_manager: &'a VariableManager,
+        config: &'a ShellConfig,
+        output: &'a OutputSink,
+    ) -> Pin<Box<dyn Future<Output = CommandResult> + Send + 'a>> {
+        Box::pin(async move {
+            let condition = self.run_list(&statement.condition, var_manager, config, output).await;
+            debug!("if condition at {:?} -> {}", statement.span, condition.success);
+            match (&statement.else_branch, condition.success) {
+                (_, true) => self.run_list(&statement.then_block, var_manager, config, output).await,
+                (Some(ElseBranch::Block(block)), false) => self.run_list(block, var_manager, config, output).await,
+                (Some(ElseBranch::If(nested)), false) => self.run_if(nested, var_manager, config, output).await,
+                (None, false) => CommandResult::success(None, None),
+            }
+        })
+    }
+
+    /// Runs the body of a `for` loop once per item, with the item bound to
+    /// the loop variable.
+    ///
+    /// The items are the expanded words after `in`, except that a single
+    /// `$(...)` word yields one item per record produced by the substituted
+    /// command (or per line, if it only produced text), and a single `{name}`
+    /// word holding an array yields one item per element. Structured items
+    /// keep their type, so the body can use `{item.field}`.
+    async fn run_for(
+        &self,
+        statement: &ForLoop,
+        var_manager: &VariableManager,
+        config: &ShellConfig,
+        output: &OutputSink,
+    ) -> CommandResult {
+        let items = match self.expand_for_items(statement, var_manager, config).await {
+            Ok(items) => items,
+            Err(e) => {
+                error!("for loop expansion error: {}", e);
+                let result = CommandResult::error(format!("Expansion error: {}", e));
+                output.report(&result, &config.theme).await;
+                return result;
+            }
+        };
+
+        let mut result = CommandResult::success(None, None);
+        for item in items {
+            var_manager.set_value(statement.variable.clone(), item);
+            result = self.run_list(&statement.body, var_manager, config, output).await;
+        }
+        result
+    }
+
+    async fn expand_for_items(
+        &self,
+        statement: &ForLoop,
+        var_manager: &VariableManager,
+        config: &ShellConfig,
+    ) -> Result<Vec<JsonValue>> {
+        if let [word] = statement.items.as_slice() {
+            if let [WordPart::Variable(expr)] = word.parts.as_slice() {
+                return Ok(match variable_resolver::resolve_value(expr, var_manager)? {
+                    JsonValue::Array(items) => items,
+                    other => vec![other],
+                });
             }
+            if let [WordPart::Substitution { list, path }] = word.parts.as_slice() {
+                let captured = self.substitute(list, var_manager, config).await?;
+                let data = match path {
+                    None => captured.data,
+                    Some(path) => Some(
+                        captured.data.as_ref()
+                            .and_then(|data| util::select_path(data, path))
+                            .cloned()
+                            .with_context(|| format!("Command substitution has no field '{}'", path))?,
+                    ),
+                };
+                return Ok(match data {
+                    Some(JsonValue::Array(records)) => records.into_iter().map(util::collapse_text).collect(),
+                    Some(record) => vec![util::collapse_text(record)],
+                    None => captured.lines.into_iter().map(JsonValue::String).collect(),
+                });
+            }
+        }
+
+        let mut items = Vec::with_capacity(statement.items.len());
+        for word in &statement.items {
+            let mut value = String::new();
+            self.expand_parts(&word.parts, var_manager, config, &mut value).await?;
+            items.push(JsonValue::String(value));
         }
+        Ok(items)
+    }
 
-        // 2. Pipeline Parsing and Variable Resolution
-        let pipeline_commands = match parser::parse_pipeline(&effective_command_line, var_manager) {
+    /// Expands variables, command substitutions and aliases in, and runs, a
+    /// single pipeline.
+    async fn dispatch_pipeline(
+        &self,
+        pipeline: &Pipeline,
+        var_manager: &VariableManager,
+        config: &ShellConfig,
+        output: &OutputSink,
+    ) -> CommandResult {
+        // 2. Variable Resolution and Command Substitution
+        let pipeline_commands = match self.expand_pipeline(pipeline, var_manager, config).await {
             Ok(cmds) => cmds,
             Err(e) => {
-                error!("Pipeline parsing error: {}", e);
-                return CommandResult::error(format!("Parsing error: {}", e));
+                error!("Pipeline expansion error: {}", e);
+                return CommandResult::error(format!("Expansion error: {}", e));
             }
         };
 
-        let mut last_output_data: Option<JsonValue> = None;
-        let pipeline_len = pipeline_commands.len(); // Get length before moving
-
-        for (i, p_cmd) in pipeline_commands.into_iter().enumerate() { // `pipeline_commands` is moved here
-            let cmd_name = p_cmd.name;
-            let mut args = p_cmd.args;
-
-            // If there's previous output, pass it as a special argument (e.g., `--input-data`)
-            // This is a common pattern for structured pipelines.
-            if let Some(prev_data) = last_output_data.take() {
-                // Here, we could decide how to pass structured data.
-                // For simplicity, we'll stringify it and add it as an argument.
-                // A more advanced system might use a dedicated input stream or context.
-                args.push("--input-data".to_string());
-                args.push(prev_data.to_string()); // Pass JSON string
-                debug!("Piped data from previous command to '{}': {}", cmd_name, prev_data);
+        // 3. Alias Resolution
+        let mut resolved = Vec::with_capacity(pipeline_commands.len());
+        for p_cmd in pipeline_commands {
+            match self.resolve_alias(p_cmd, var_manager, config).await {
+                Ok(p_cmd) => resolved.push(p_cmd),
+                Err(e) => {
+                    error!("Alias expansion error: {}", e);
+                    return CommandResult::error(format!("Expansion error: {}", e));
+                }
+            }
+        }
+        let pipeline_commands = resolved;
+
+        // 4. Command Execution
+        self.run_pipeline(pipeline_commands, var_manager, config, output).await
+    }
+
+    /// Replaces a command name with its alias target, if it has one. Alias
+    /// targets may carry leading arguments of their own (e.g. `ll = "ls -l"`),
+    /// quoted and expanded as on the command line, and may themselves be
+    /// aliases; resolution stops at the first name seen twice, so an alias
+    /// can shadow the command it names.
+    async fn resolve_alias(
+        &self,
+        mut p_cmd: PipelineCommand,
+        var_manager: &VariableManager,
+        config: &ShellConfig,
+    ) -> Result<PipelineCommand> {
+        let mut seen = Vec::new();
+        while let Some(real_command) = config.aliases.get(&p_cmd.name) {
+            if seen.contains(&p_cmd.name) {
+                break;
+            }
+            let words = match lexer::words(real_command) {
+                Ok(words) => words,
+                Err(e) => {
+                    error!("Alias '{}' has an invalid target: {}", p_cmd.name, e);
+                    break;
+                }
+            };
+            let mut target = Vec::with_capacity(words.len());
+            for word in &words {
+                let mut value = String::new();
+                self.expand_parts(&word.parts, var_manager, config, &mut value).await?;
+                target.push(value);
+            }
+            let mut target = target.into_iter();
+            let Some(name) = target.next() else {
+                break;
+            };
+            let args: Vec<String> = target.chain(p_cmd.args).collect();
+            info!("Alias resolved: '{}' -> '{}'", p_cmd.name, name);
+            seen.push(std::mem::replace(&mut p_cmd.name, name));
+            p_cmd.args = args;
+        }
+        Ok(p_cmd)
+    }
+
+    /// Expands the words of a pipeline into `PipelineCommand`s.
+    async fn expand_pipeline(
+        &self,
+        pipeline: &Pipeline,
+        var_manager: &VariableManager,
+        config: &ShellConfig,
+    ) -> Result<Vec<PipelineCommand>> {
+        let mut commands = Vec::with_capacity(pipeline.commands.len());
+        for command in &pipeline.commands {
+            let mut words = Vec::with_capacity(command.words.len());
+            for word in &command.words {
+                let mut value = String::new();
+                self.expand_parts(&word.parts, var_manager, config, &mut value).await?;
+                words.push(value);
             }
+            let name = words.remove(0);
+            commands.push(PipelineCommand { name, args: words });
+        }
+        Ok(commands)
+    }
 
-            // 3. Command Execution
-            if let Some(command) = self.command_registry.get(&cmd_name) {
-                // Check if command is enabled by config (if `enabled_commands` is not empty)
-                if !config.enabled_commands.is_empty() && !config.enabled_commands.contains(&cmd_name) {
-                    error!("Command '{}' is disabled by configuration.", cmd_name);
-                    return CommandResult::error(format!("Command '{}' is disabled.", cmd_name));
+    /// Appends the expansion of a word's parts to `out`.
+    ///
+    /// Command substitutions run the inner command list to completion, with
+    /// its output captured, before the outer command starts. Their result is
+    /// inserted as a single argument, without word splitting.
+    fn expand_parts<'a>(
+        &'a self,
+        parts: &'a [WordPart],
+        var_manager: &'a VariableManager,
+        config: &'a ShellConfig,
+        out: &'a mut String,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>> {
+        Box::pin(async move {
+            for part in parts {
+                match part {
+                    WordPart::Literal(text) => out.push_str(text),
+                    WordPart::Quoted(inner) => self.expand_parts(inner, var_manager, config, out).await?,
+                    WordPart::Variable(expr) => {
+                        out.push_str(&variable_resolver::resolve_variable(expr, var_manager)?);
+                    },
+                    WordPart::Substitution { list, path } => {
+                        let captured = self.substitute(list, var_manager, config).await?;
+                        match path {
+                            // A number or boolean result (e.g. from `count`) stands for
+                            // itself rather than the summary text that came with it.
+                            None => match &captured.data {
+                                Some(data) if data.is_number() || data.is_boolean() => out.push_str(&data.to_string()),
+                                _ => out.push_str(&captured.text()),
+                            },
+                            Some(path) => {
+                                let value = captured.data.as_ref()
+                                    .and_then(|data| util::select_path(data, path))
+                                    .with_context(|| format!("Command substitution has no field '{}'", path))?;
+                                out.push_str(&util::value_to_text(value));
+                            },
+                        }
+                    },
                 }
+            }
+            Ok(())
+        })
+    }
+
+    /// Runs the command list of a `$(...)` substitution to completion and
+    /// returns its captured output.
+    async fn substitute(
+        &self,
+        list: &CommandList,
+        var_manager: &VariableManager,
+        config: &ShellConfig,
+    ) -> Result<CapturedOutput> {
+        let (sink, captured) = OutputSink::capture();
+        let result = self.run_list(list, var_manager, config, &sink).await;
+        if !result.success {
+            anyhow::bail!(
+                "Command substitution failed: {}",
+                result.error_message.unwrap_or_default()
+            );
+        }
+        let captured = std::mem::take(&mut *captured.lock().unwrap());
+        Ok(captured)
+    }
+
+    /// Runs every stage of a pipeline concurrently, each on its own task,
+    /// connected by bounded channels.
+    ///
+    /// Records flow downstream as soon as they are emitted, and the records of
+    /// the last stage are passed to `output` as they arrive. Dropping the
+    /// returned future (e.g. on Ctrl-C) aborts every stage that is still running.
+    async fn run_pipeline(
+        &self,
+        pipeline_commands: Vec<PipelineCommand>,
+        var_manager: &VariableManager,
+        config: &ShellConfig,
+        output: &OutputSink,
+    ) -> CommandResult {
+        // Resolve every stage first, so an unknown or disabled command fails
+        // before any other stage has started.
+        let mut stages = Vec::with_capacity(pipeline_commands.len());
+        for p_cmd in pipeline_commands {
+            let command = match self.command_registry.get(&p_cmd.name) {
+                Some(command) => {
+                    // Check if command is enabled by config (if `enabled_commands` is not empty)
+                    if !config.enabled_commands.is_empty() && !config.enabled_commands.contains(&p_cmd.name) {
+                        error!("Command '{}' is disabled by configuration.", p_cmd.name);
+                        return CommandResult::error(format!("Command '{}' is disabled.", p_cmd.name));
+                    }
+                    StageCommand::Builtin(command.as_ref())
+                },
+                None => match commands::get_function(&p_cmd.name) {
+                    Some(function) => StageCommand::Function(function),
+                    None => {
+                        error!("Unknown command in pipeline: '{}'", p_cmd.name);
+                        return CommandResult::error(format!("Unknown command in pipeline: '{}'.", p_cmd.name));
+                    },
+                },
+            };
+            stages.push((command, p_cmd));
+        }
+
+        let stage_count = stages.len();
+        let mut tasks = JoinSet::new();
+        let mut upstream = self.input.lock().unwrap().take();
+
+        for (i, (command, p_cmd)) in stages.into_iter().enumerate() {
+            let (tx, rx) = mpsc::channel(PIPELINE_BUFFER);
+            let mut io = PipelineIo::new(upstream.take(), Some(tx), i + 1 < stage_count);
+            upstream = Some(rx);
 
-                info!("Executing command: '{}' with args: {:?}", cmd_name, args);
-                let result = command.execute(args, var_manager, config, &self.command_registry).await;
+            let var_manager = var_manager.clone();
+            let config = config.clone();
+            let command_registry = self.command_registry;
+            tasks.spawn(async move {
+                info!("Executing command: '{}' with args: {:?}", p_cmd.name, command.redact_args(&p_cmd.args, &var_manager));
+                let result = command.execute(p_cmd.args, &mut io, &var_manager, &config, command_registry).await;
 
-                if result.success {
-                    // Capture output data for the next command in the pipeline
-                    last_output_data = result.output.clone().and_then(|o| o.data); // Cloned output for next use
-                    // If it's the last command, return its full result
-                    if i == pipeline_len - 1 { // Use stored length
-                        return result;
+                // Commands that don't stream still feed the next stage with their result data.
+                if result.success && io.is_piped() {
+                    if let Some(data) = result.output.as_ref().and_then(|o| o.data.clone()) {
+                        debug!("Forwarding data from '{}' to the next stage: {}", p_cmd.name, data);
+                        io.forward(data).await;
                     }
-                } else {
-                    // If any command in the pipeline fails, the whole pipeline fails
-                    error!("Pipeline command '{}' failed: {:?}", cmd_name, result.error_message);
-                    return result;
                 }
-            } else {
-                error!("Unknown command in pipeline: '{}'", cmd_name);
-                return CommandResult::error(format!("Unknown command in pipeline: '{}'.", cmd_name));
+                (i, p_cmd.name, result)
+            });
+        }
+
+        let mut terminal = upstream.expect("pipeline has at least one stage");
+        let print_records = async {
+            while let Some(record) = terminal.recv().await {
+                output.record(record).await;
+            }
+        };
+
+        let collect_results = async {
+            let mut results: Vec<Option<(String, CommandResult)>> = vec![None; stage_count];
+            while let Some(joined) = tasks.join_next().await {
+                match joined {
+                    Ok((i, name, result)) => results[i] = Some((name, result)),
+                    Err(e) => error!("Pipeline stage panicked or was aborted: {}", e),
+                }
+            }
+            results
+        };
+
+        let (_, results) = tokio::join!(print_records, collect_results);
+
+        // If any command in the pipeline fails, the whole pipeline fails
+        let mut last_result = None;
+        for (name, result) in results.into_iter().flatten() {
+            if !result.success {
+                error!("Pipeline command '{}' failed: {:?}", name, result.error_message);
+                return result;
             }
+            last_result = Some(result);
         }
 
-        // If the pipeline ran successfully but the last command had no output, return success.
-        CommandResult::success(Some("Pipeline executed successfully.".to_string()), last_output_data)
+        last_result.unwrap_or_else(|| CommandResult::error("Pipeline did not complete.".to_string()))
     }
 }
 
+/// The implementation of a pipeline stage: a built-in command or a
+/// user-defined function.
+enum StageCommand {
+    Builtin(&'static dyn Command),
+    Function(Arc<FunctionCommand>),
+}
+
+impl Deref for StageCommand {
+    type Target = dyn Command;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            StageCommand::Builtin(command) => *command,
+            StageCommand::Function(function) => function.as_ref(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn alias_targets_are_expanded_when_used() {
+        let dispatcher = CommandDispatcher::new(commands::get_command_registry());
+        let mut config = ShellConfig::default();
+        config.aliases.insert("hello".to_string(), "echo 'hi  there' {name} $(echo sub)".to_string());
+        let vars = VariableManager::new();
+        vars.set("name".to_string(), "dev".to_string());
+
+        let list = parser::parse_command_list("hello !").unwrap();
+        let (sink, captured) = OutputSink::capture();
+        let result = dispatcher.run_list(&list, &vars, &config, &sink).await;
+        assert!(result.success, "{:?}", result.error_message);
+        assert_eq!(captured.lock().unwrap().text(), "hi  there dev sub !");
+    }
+}
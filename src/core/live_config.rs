@@ -0,0 +1,114 @@
+// src/core/live_config.rs
+// Holds the configuration in effect for the session, which `config set`
+// changes and which is reloaded when a config file changes on disk.
+
+use std::path::PathBuf;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+use colored::Colorize;
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+use tokio::task::JoinHandle;
+
+use crate::core::config::ShellConfig;
+use crate::core::config_layers;
+use crate::core::config_validation;
+use crate::core::theme::Stream;
+use crate::core::types::CommandRegistry;
+
+/// How often the config files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    // Installed by `main` once loaded; read afresh for every line entered.
+    static ref LIVE_CONFIG: RwLock<ShellConfig> = RwLock::new(ShellConfig::default());
+    // Messages from reloads, held back so they don't overwrite the prompt
+    // while a line is being edited.
+    static ref PENDING_MESSAGES: Mutex<Vec<(Stream, String)>> = Mutex::new(Vec::new());
+}
+
+/// Returns a copy of the configuration currently in effect.
+pub fn current() -> ShellConfig {
+    LIVE_CONFIG.read().unwrap().clone()
+}
+
+/// Makes `config` the configuration in effect from the next command on.
+pub fn replace(config: ShellConfig) {
+    *LIVE_CONFIG.write().unwrap() = config;
+}
+
+/// Prints the messages of reloads that happened since the last call. Called
+/// before each prompt is shown.
+pub fn print_pending_messages() {
+    for (stream, message) in PENDING_MESSAGES.lock().unwrap().drain(..) {
+        match stream {
+            Stream::Stdout => println!("{}", message),
+            Stream::Stderr => eprintln!("{}", message),
+        }
+    }
+}
+
+fn queue_message(stream: Stream, message: String) {
+    PENDING_MESSAGES.lock().unwrap().push((stream, message));
+}
+
+/// Reloads the configuration whenever one of its files (system, user or
+/// project) is created, changed or removed, until the returned task is
+/// aborted. Changes made with `config set` and not saved are discarded by a
+/// reload. With `strict`, a reloaded configuration that has problems is
+/// reported and not applied. What a reload reports is shown by
+/// `print_pending_messages`.
+pub fn watch(registry: &'static CommandRegistry, strict: bool) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut seen = file_states();
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let states = file_states();
+            if states == seen {
+                continue;
+            }
+            seen = states;
+            reload(registry, strict);
+        }
+    })
+}
+
+/// The modification time of each layer file, `None` where it doesn't exist.
+fn file_states() -> Vec<(PathBuf, Option<SystemTime>)> {
+    config_layers::layer_files()
+        .into_iter()
+        .filter_map(|(_, path)| path)
+        .map(|path| {
+            let modified = path.metadata().and_then(|meta| meta.modified()).ok();
+            (path, modified)
+        })
+        .collect()
+}
+
+fn reload(registry: &CommandRegistry, strict: bool) {
+    let config = match config_layers::load_layered() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Failed to reload config: {:#}", e);
+            queue_message(Stream::Stderr, format!("Failed to reload config, keeping the current one: {:#}", e).red().to_string());
+            return;
+        }
+    };
+    let problems = config_validation::validate(&config, registry);
+    for problem in &problems {
+        let message = config_validation::describe(problem);
+        match problem.is_error() {
+            true => error!("{}", message),
+            false => warn!("{}", message),
+        }
+        queue_message(Stream::Stderr, message.yellow().to_string());
+    }
+    if strict && problems.iter().any(|problem| problem.is_error()) {
+        queue_message(Stream::Stderr, "Not applying the reloaded config in --strict mode.".red().to_string());
+        return;
+    }
+    replace(config);
+    info!("Configuration reloaded.");
+    queue_message(Stream::Stdout, "Configuration reloaded.".yellow().to_string());
+}
@@ -4,4 +4,12 @@
 pub mod types;
 pub mod variables;
 pub mod config;
+pub mod config_layers;
+pub mod config_validation;
+pub mod live_config;
 pub mod dispatcher;
+pub mod pipeline;
+pub mod output;
+pub mod prompt;
+pub mod session;
+pub mod theme;
@@ -0,0 +1,111 @@
+// src/core/output.rs
+// Routes the output of executed commands to the terminal or into a buffer.
+
+use std::sync::{Arc, Mutex};
+
+use log::error;
+use serde_json::Value as JsonValue;
+use tokio::sync::mpsc;
+
+use crate::core::config::ThemeConfig;
+use crate::core::theme::{self, Stream};
+use crate::core::types::CommandResult;
+use crate::util;
+
+/// Output collected from a command list run in capture mode, e.g. for a
+/// `$(...)` command substitution.
+#[derive(Debug, Default)]
+pub struct CapturedOutput {
+    /// Text lines, in the order they were produced.
+    pub lines: Vec<String>,
+    /// Structured output of the most recently completed pipeline: the records
+    /// it streamed, or else its result data.
+    pub data: Option<JsonValue>,
+    pending_records: Vec<JsonValue>,
+}
+
+impl CapturedOutput {
+    /// All captured text, one line per record or result.
+    pub fn text(&self) -> String {
+        self.lines.join("\n")
+    }
+}
+
+/// Where the records and results of executed pipelines go.
+#[derive(Debug, Clone, Default)]
+pub enum OutputSink {
+    /// Printed as they are produced.
+    #[default]
+    Terminal,
+    /// Collected for the caller. Errors are still printed.
+    Capture(Arc<Mutex<CapturedOutput>>),
+    /// Sent on as records of an enclosing pipeline stage, e.g. the output of
+    /// the body of a user-defined function. Text is sent as string records.
+    /// Errors are still printed.
+    Stream(mpsc::Sender<JsonValue>),
+}
+
+impl OutputSink {
+    /// Creates a capturing sink together with a handle to read the captured output.
+    pub fn capture() -> (Self, Arc<Mutex<CapturedOutput>>) {
+        let captured = Arc::new(Mutex::new(CapturedOutput::default()));
+        (OutputSink::Capture(captured.clone()), captured)
+    }
+
+    /// Handles a record streamed by the last stage of a pipeline.
+    pub async fn record(&self, record: JsonValue) {
+        match self {
+            OutputSink::Terminal => println!("{}", util::value_to_text(&record)),
+            OutputSink::Capture(captured) => {
+                let mut captured = captured.lock().unwrap();
+                captured.lines.push(util::value_to_text(&record));
+                captured.pending_records.push(record);
+            }
+            OutputSink::Stream(tx) => {
+                // A closed channel means the consumer stopped reading; the
+                // output is simply dropped, as with `PipelineIo::emit`.
+                let _ = tx.send(record).await;
+            }
+        }
+    }
+
+    /// Handles the outcome of a finished pipeline: its text on success, or
+    /// its error message on failure. On the terminal these are shown in the
+    /// theme's success and error colors.
+    pub async fn report(&self, result: &CommandResult, theme: &ThemeConfig) {
+        if !result.success {
+            if let Some(message) = &result.error_message {
+                error!("Command error: {}", message);
+                eprintln!("{}", theme::paint(message, &theme.error_color, Stream::Stderr));
+            }
+            return;
+        }
+
+        let output = result.output.as_ref();
+        let text = output.and_then(|o| o.text.as_ref());
+        match self {
+            OutputSink::Terminal => {
+                if let Some(text) = text {
+                    println!("{}", theme::paint(text, &theme.success_color, Stream::Stdout));
+                }
+            }
+            OutputSink::Capture(captured) => {
+                let mut captured = captured.lock().unwrap();
+                if let Some(text) = text {
+                    captured.lines.push(text.clone());
+                }
+                let mut records = std::mem::take(&mut captured.pending_records);
+                captured.data = match records.len() {
+                    0 => output.and_then(|o| o.data.clone()),
+                    1 => records.pop(),
+                    _ => Some(JsonValue::Array(records)),
+                };
+            }
+            OutputSink::Stream(tx) => {
+                if let Some(text) = text {
+                    let _ = tx.send(JsonValue::String(text.clone())).await;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,122 @@
+// src/core/pipeline.rs
+// Channels connecting the concurrently running stages of a pipeline.
+
+use serde_json::Value as JsonValue;
+use tokio::sync::mpsc;
+
+use crate::core::output::OutputSink;
+
+/// Number of records a stage may buffer ahead of its consumer before `emit`
+/// starts waiting, which is what provides back-pressure between stages.
+pub const PIPELINE_BUFFER: usize = 64;
+
+/// The streaming input and output of a single pipeline stage.
+///
+/// Records are arbitrary JSON values. A stage reads the records produced by
+/// the previous stage with `next`/`collect` and hands records downstream with
+/// `emit` as soon as they are available.
+#[derive(Debug, Default)]
+pub struct PipelineIo {
+    input: Option<mpsc::Receiver<JsonValue>>,
+    output: Option<mpsc::Sender<JsonValue>>,
+    piped: bool,
+    emitted: usize,
+}
+
+impl PipelineIo {
+    /// Creates the I/O for a pipeline stage.
+    ///
+    /// `piped` tells whether `output` feeds another command (as opposed to
+    /// the terminal), so producers can choose a richer record shape.
+    pub fn new(
+        input: Option<mpsc::Receiver<JsonValue>>,
+        output: Option<mpsc::Sender<JsonValue>>,
+        piped: bool,
+    ) -> Self {
+        PipelineIo { input, output, piped, emitted: 0 }
+    }
+
+    /// Returns `true` if this stage receives records from a previous stage.
+    pub fn has_input(&self) -> bool {
+        self.input.is_some()
+    }
+
+    /// Returns `true` if this stage's records are consumed by another command.
+    pub fn is_piped(&self) -> bool {
+        self.piped
+    }
+
+    /// Waits for the next record from the previous stage.
+    /// Returns `None` once the previous stage has finished.
+    pub async fn next(&mut self) -> Option<JsonValue> {
+        match self.input.as_mut() {
+            Some(rx) => rx.recv().await,
+            None => None,
+        }
+    }
+
+    /// Takes the receiving end of the input channel, for commands that need to
+    /// consume input and emit output concurrently.
+    pub fn take_input(&mut self) -> Option<mpsc::Receiver<JsonValue>> {
+        self.input.take()
+    }
+
+    /// Reads every remaining record from the previous stage.
+    pub async fn collect(&mut self) -> Vec<JsonValue> {
+        let mut records = Vec::new();
+        while let Some(record) = self.next().await {
+            records.push(record);
+        }
+        records
+    }
+
+    /// Sends a record downstream, waiting if the consumer is behind.
+    ///
+    /// Returns `false` if nothing is listening anymore, in which case the
+    /// producer should stop generating records.
+    pub async fn emit(&mut self, record: JsonValue) -> bool {
+        let Some(tx) = self.output.as_ref() else {
+            return false;
+        };
+        if tx.send(record).await.is_err() {
+            return false;
+        }
+        self.emitted += 1;
+        true
+    }
+
+    /// An `OutputSink` that sends everything it is given downstream, for
+    /// commands that run other commands on this stage's behalf.
+    pub fn output_sink(&self) -> OutputSink {
+        match &self.output {
+            Some(tx) => OutputSink::Stream(tx.clone()),
+            None => OutputSink::Terminal,
+        }
+    }
+
+    /// Number of records this stage has emitted so far.
+    pub fn emitted(&self) -> usize {
+        self.emitted
+    }
+
+    /// Forwards a finished command's result data downstream, unless the
+    /// command already streamed its records. Arrays are split into one record
+    /// per element so consumers see the same shape as from a streaming producer.
+    pub async fn forward(&mut self, data: JsonValue) {
+        if self.emitted > 0 {
+            return;
+        }
+        match data {
+            JsonValue::Array(items) => {
+                for item in items {
+                    if !self.emit(item).await {
+                        break;
+                    }
+                }
+            }
+            other => {
+                self.emit(other).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,330 @@
+// src/core/prompt.rs
+// Expands the prompt template of `ShellConfig::prompt` before each line is read.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::core::variables::VariableManager;
+use crate::parser::variable_resolver;
+use crate::util;
+
+/// What the prompt knows about the line entered before it.
+#[derive(Debug, Clone, Default)]
+pub struct PromptState {
+    /// 0 if the last line succeeded, 1 if it failed, 130 if it was interrupted.
+    pub last_status: Option<i32>,
+    pub last_duration: Option<Duration>,
+}
+
+/// A piece of a parsed prompt template.
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Text(String),
+    /// `{name}`: a built-in segment, or else a variable reference, possibly
+    /// with a field path (`{cfg.db.host}`) or to the environment (`{env:NAME}`).
+    Placeholder(String),
+    /// `{?name:body}` shows `body` when `name` is set to something other
+    /// than an empty string, `0` or `false`; `{!name:body}` when it isn't.
+    Conditional { name: String, negate: bool, body: Vec<Segment> },
+}
+
+/// Checks that `template` is a well-formed prompt template.
+pub fn check(template: &str) -> Result<(), String> {
+    parse(template).map(|_| ())
+}
+
+/// Expands a prompt template. The built-in segments are:
+///
+/// * `{cwd}` - the working directory, with the home directory shown as `~`,
+/// * `{user}` and `{host}`,
+/// * `{time}` - the local time as `HH:MM:SS`,
+/// * `{last_status}` - 0, 1 or 130 for the last line (success, failure, interrupted),
+/// * `{last_duration}` - how long the last line took to run,
+/// * `{git_branch}` - the branch checked out in the enclosing git repository,
+///   or the abbreviated commit when detached.
+///
+/// Any other name is looked up as a variable; secret values are masked.
+/// Modifiers such as `{name:-default}` are not expanded, since rendering the
+/// prompt must not assign variables or fail.
+/// Write `{{` and `}}` for literal braces. A template that doesn't parse is
+/// shown as it is.
+pub fn render(template: &str, var_manager: &VariableManager, state: &PromptState) -> String {
+    match parse(template) {
+        Ok(segments) => render_segments(&segments, var_manager, state),
+        Err(_) => template.to_string(),
+    }
+}
+
+fn render_segments(segments: &[Segment], var_manager: &VariableManager, state: &PromptState) -> String {
+    let mut out = String::new();
+    for segment in segments {
+        match segment {
+            Segment::Text(text) => out.push_str(text),
+            Segment::Placeholder(expr) => out.push_str(&value_of(expr, var_manager, state)),
+            Segment::Conditional { name, negate, body } => {
+                let value = value_of(name, var_manager, state);
+                let set = !matches!(value.as_str(), "" | "0" | "false");
+                if set != *negate {
+                    out.push_str(&render_segments(body, var_manager, state));
+                }
+            }
+        }
+    }
+    out
+}
+
+/// The value of a placeholder; anything that can't be resolved is empty.
+fn value_of(expr: &str, var_manager: &VariableManager, state: &PromptState) -> String {
+    if let Some(value) = builtin(expr, state) {
+        return value;
+    }
+    variable_resolver::peek(expr, var_manager)
+        .map(|value| util::redact(&util::format_value(&value)))
+        .unwrap_or_default()
+}
+
+fn builtin(name: &str, state: &PromptState) -> Option<String> {
+    let value = match name {
+        "cwd" => current_dir(),
+        "user" => ["USER", "USERNAME", "LOGNAME"].iter().find_map(|name| std::env::var(name).ok()).unwrap_or_default(),
+        "host" => hostname(),
+        "time" => chrono::Local::now().format("%H:%M:%S").to_string(),
+        "last_status" => state.last_status.map(|status| status.to_string()).unwrap_or_default(),
+        "last_duration" => state.last_duration.map(format_duration).unwrap_or_default(),
+        "git_branch" => std::env::current_dir().ok().and_then(|cwd| git_branch(&cwd)).unwrap_or_default(),
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn current_dir() -> String {
+    let Ok(cwd) = std::env::current_dir() else {
+        return String::new();
+    };
+    match dirs::home_dir().and_then(|home| cwd.strip_prefix(&home).ok().map(Path::to_path_buf)) {
+        Some(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Some(rest) => format!("~/{}", rest.display()),
+        None => cwd.display().to_string(),
+    }
+}
+
+fn hostname() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/proc/sys/kernel/hostname").ok())
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .map(|name| name.trim().to_string())
+        .unwrap_or_default()
+}
+
+/// `350ms`, `2.4s` or `3m05s`.
+fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    match millis {
+        0..=999 => format!("{}ms", millis),
+        1000..=59_999 => format!("{:.1}s", duration.as_secs_f64()),
+        _ => format!("{}m{:02}s", duration.as_secs() / 60, duration.as_secs() % 60),
+    }
+}
+
+/// Reads the checked-out branch from `.git/HEAD` in `start` or the nearest
+/// parent that has one. `.git` may also be a file pointing at the real git
+/// directory, as in worktrees and submodules.
+fn git_branch(start: &Path) -> Option<String> {
+    let dot_git = start.ancestors().map(|dir| dir.join(".git")).find(|path| path.exists())?;
+    let git_dir = if dot_git.is_file() {
+        let pointer = std::fs::read_to_string(&dot_git).ok()?;
+        let target = PathBuf::from(pointer.trim().strip_prefix("gitdir:")?.trim());
+        match target.is_absolute() {
+            true => target,
+            false => dot_git.parent()?.join(target),
+        }
+    } else {
+        dot_git
+    };
+    let head = std::fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref:") {
+        Some(reference) => {
+            let reference = reference.trim();
+            Some(reference.strip_prefix("refs/heads/").unwrap_or(reference).to_string())
+        }
+        None => Some(head.chars().take(7).collect()),
+    }
+}
+
+fn parse(template: &str) -> Result<Vec<Segment>, String> {
+    let mut segments = Vec::new();
+    let mut text = String::new();
+    let mut rest = template;
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix("{{").or_else(|| rest.strip_prefix("}}")) {
+            text.push(c);
+            rest = after;
+            continue;
+        }
+        if c != '{' {
+            text.push(c);
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+
+        let end = closing_brace(rest).ok_or_else(|| format!("unclosed '{{' in prompt: {}", rest))?;
+        let inner = &rest[1..end];
+        rest = &rest[end + 1..];
+        if !text.is_empty() {
+            segments.push(Segment::Text(std::mem::take(&mut text)));
+        }
+
+        let conditional = inner.strip_prefix('?').map(|c| (c, false)).or_else(|| inner.strip_prefix('!').map(|c| (c, true)));
+        segments.push(match conditional {
+            Some((condition, negate)) => {
+                let (name, body) = condition
+                    .split_once(':')
+                    .ok_or_else(|| format!("expected '{{{}name:text}}' in prompt, found '{{{}}}'", &inner[..1], inner))?;
+                Segment::Conditional { name: name.trim().to_string(), negate, body: parse(body)? }
+            }
+            None if inner.trim().is_empty() => return Err("empty '{}' in prompt".to_string()),
+            None => Segment::Placeholder(inner.to_string()),
+        });
+    }
+    if !text.is_empty() {
+        segments.push(Segment::Text(text));
+    }
+    Ok(segments)
+}
+
+/// The byte offset of the `}` matching the `{` that `text` starts with.
+fn closing_brace(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (offset, c) in text.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(offset);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Segment {
+        Segment::Text(text.to_string())
+    }
+
+    #[test]
+    fn parse_keeps_doubled_braces_literal() {
+        assert_eq!(parse("{{x}} }}{{").unwrap(), vec![text("{x} }{")]);
+        assert_eq!(
+            parse("a{{{user}}}b").unwrap(),
+            vec![text("a{"), Segment::Placeholder("user".to_string()), text("}b")]
+        );
+    }
+
+    #[test]
+    fn parse_reads_conditionals_with_nested_placeholders() {
+        assert_eq!(
+            parse("{?last_status:[{last_status}]}{!quiet: > }").unwrap(),
+            vec![
+                Segment::Conditional {
+                    name: "last_status".to_string(),
+                    negate: false,
+                    body: vec![text("["), Segment::Placeholder("last_status".to_string()), text("]")],
+                },
+                Segment::Conditional { name: "quiet".to_string(), negate: true, body: vec![text(" > ")] },
+            ]
+        );
+        assert_eq!(parse("{name:-none}").unwrap(), vec![Segment::Placeholder("name:-none".to_string())]);
+    }
+
+    #[test]
+    fn parse_rejects_malformed_templates() {
+        assert_eq!(parse("sc {cwd").unwrap_err(), "unclosed '{' in prompt: {cwd");
+        assert_eq!(parse("{?x:{y}").unwrap_err(), "unclosed '{' in prompt: {?x:{y}");
+        assert_eq!(parse("{?flag}").unwrap_err(), "expected '{?name:text}' in prompt, found '{?flag}'");
+        assert_eq!(parse("{ }").unwrap_err(), "empty '{}' in prompt");
+        assert!(check("{cwd} $ ").is_ok());
+    }
+
+    #[test]
+    fn closing_brace_matches_nesting() {
+        assert_eq!(closing_brace("{a}b}"), Some(2));
+        assert_eq!(closing_brace("{a{b}c}d"), Some(6));
+        assert_eq!(closing_brace("{é}"), Some(3));
+        assert_eq!(closing_brace("{a{b}"), None);
+    }
+
+    #[test]
+    fn render_fills_in_variables_and_conditionals() {
+        let vars = VariableManager::new();
+        vars.set("name".to_string(), "dev".to_string());
+        let state = PromptState { last_status: Some(1), last_duration: Some(Duration::from_millis(2400)) };
+
+        let prompt = render("{name}{?last_status: [{last_status}]}{!missing: ok} {last_duration}> ", &vars, &state);
+        assert_eq!(prompt, "dev [1] ok 2.4s> ");
+        assert_eq!(render("{?last_status:x}", &vars, &PromptState::default()), "");
+        assert_eq!(render("{broken", &vars, &state), "{broken");
+    }
+
+    #[test]
+    fn render_looks_variables_up_without_side_effects() {
+        let vars = VariableManager::new();
+        vars.set_value("cfg".to_string(), serde_json::json!({ "db": { "host": "h" } }));
+
+        assert_eq!(render("{cfg.db.host}|{missing}|{x:=d}|{y:?required}", &vars, &PromptState::default()), "h|||");
+        assert_eq!(vars.get_value("x"), None);
+    }
+
+    #[test]
+    fn git_branch_reads_head_from_the_nearest_repository() {
+        let dir = util::test_dir("prompt-branch");
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+        std::fs::create_dir_all(dir.join("src/deep")).unwrap();
+        std::fs::write(dir.join(".git/HEAD"), "ref: refs/heads/feature/x\n").unwrap();
+
+        assert_eq!(git_branch(&dir.join("src/deep")).as_deref(), Some("feature/x"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn git_branch_shows_a_detached_head_as_a_short_commit() {
+        let dir = util::test_dir("prompt-detached");
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+        std::fs::write(dir.join(".git/HEAD"), "0123456789abcdef0123456789abcdef01234567\n").unwrap();
+
+        assert_eq!(git_branch(&dir).as_deref(), Some("0123456"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn git_branch_follows_a_gitdir_file() {
+        let dir = util::test_dir("prompt-worktree");
+        let real = dir.join("main/.git/worktrees/wt");
+        std::fs::create_dir_all(&real).unwrap();
+        std::fs::write(real.join("HEAD"), "ref: refs/heads/wt-branch\n").unwrap();
+
+        // An absolute pointer, as `git worktree add` writes.
+        std::fs::create_dir_all(dir.join("wt")).unwrap();
+        std::fs::write(dir.join("wt/.git"), format!("gitdir: {}\n", real.display())).unwrap();
+        assert_eq!(git_branch(&dir.join("wt")).as_deref(), Some("wt-branch"));
+
+        // A relative one, as in submodules.
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub/.git"), "gitdir: ../main/.git/worktrees/wt\n").unwrap();
+        assert_eq!(git_branch(&dir.join("sub")).as_deref(), Some("wt-branch"));
+
+        // A file that isn't a pointer gives no branch.
+        std::fs::write(dir.join("sub/.git"), "nonsense\n").unwrap();
+        assert_eq!(git_branch(&dir.join("sub")), None);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
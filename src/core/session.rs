@@ -0,0 +1,229 @@
+// src/core/session.rs
+// Restores the session's variables at startup and saves them automatically.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Result;
+use log::{debug, error, info};
+use tokio::sync::{oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, sleep_until, Instant};
+
+use crate::commands::save_load::{read_passphrase, resolve_path};
+use crate::core::config::ShellConfig;
+use crate::core::variables::VariableManager;
+use crate::storage::StorageBackend;
+
+/// How long variables must stay unchanged before an autosave runs, so a
+/// burst of assignments (a script, a loop) is saved once.
+const QUIET_PERIOD: Duration = Duration::from_secs(2);
+
+/// What `restore` loaded.
+pub struct Restored {
+    pub path: PathBuf,
+    pub count: usize,
+    /// The passphrase of an encrypted memory file, so autosave can keep it encrypted.
+    pub passphrase: Option<String>,
+    /// Whether the file held secret variables (saved with `--include-secrets`),
+    /// so autosave keeps writing them instead of dropping them.
+    pub secrets: bool,
+}
+
+/// Loads the memory file into the session if `storage.autoload` is set.
+/// Returns `None` if autoload is off or there is no memory file yet.
+pub async fn restore(var_manager: &VariableManager, config: &ShellConfig) -> Result<Option<Restored>> {
+    if !config.storage.autoload {
+        return Ok(None);
+    }
+    let (path, backend) = resolve_path(None, config)?;
+    if !path.exists() {
+        info!("No memory file at {:?} to restore.", path);
+        return Ok(None);
+    }
+
+    let passphrase = match backend.is_encrypted(&path).await? {
+        true => Some(read_passphrase(false).await?),
+        false => None,
+    };
+    let store = backend.load(&path, passphrase.as_deref()).await?;
+    let count = store.keys().count();
+    let secrets = store.has_secrets();
+    var_manager.set_all(store);
+    info!("Restored {} variables from {:?}", count, path);
+    Ok(Some(Restored { path, count, passphrase, secrets }))
+}
+
+/// Saves the session's variables to the memory file in the background
+/// whenever they change (once they have settled, and at least every
+/// `storage.autosave_interval_secs`), and one last time on `finish`.
+/// Secret variables are left out, as with a plain `save-memory`, unless the
+/// file was restored with secrets in it.
+pub struct Autosave {
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl Autosave {
+    /// Starts autosaving if `storage.autosave` is set. `passphrase` is the
+    /// one the memory file was restored with; if the file is encrypted and
+    /// none is given, it is asked for now. `secrets` keeps secret variables
+    /// in the file, for one that was restored with them.
+    pub async fn start(
+        var_manager: VariableManager,
+        config: &ShellConfig,
+        passphrase: Option<String>,
+        secrets: bool,
+    ) -> Result<Option<Self>> {
+        if !config.storage.autosave {
+            return Ok(None);
+        }
+        let (path, backend) = resolve_path(None, config)?;
+        let passphrase = match passphrase {
+            None if path.exists() && backend.is_encrypted(&path).await? => Some(read_passphrase(false).await?),
+            passphrase => passphrase,
+        };
+
+        let mut changes = var_manager.subscribe_changes();
+        // Whatever is in memory now (e.g. just restored) is what the file holds.
+        let saved = *changes.borrow_and_update();
+        let mut saver = Saver { var_manager, path, backend, passphrase, secrets, changes, saved };
+
+        let interval = Duration::from_secs(config.storage.autosave_interval_secs);
+        let (shutdown, mut shutdown_rx) = oneshot::channel();
+        info!("Autosaving variables to {:?}", saver.path);
+        let task = tokio::spawn(async move {
+            loop {
+                let stop = tokio::select! {
+                    _ = &mut shutdown_rx => true,
+                    _ = wait_for_changes(&mut saver.changes, interval) => false,
+                };
+                saver.save_if_changed().await;
+                if stop {
+                    break;
+                }
+            }
+        });
+        Ok(Some(Autosave { shutdown, task }))
+    }
+
+    /// Saves any pending changes and stops autosaving.
+    pub async fn finish(self) {
+        let _ = self.shutdown.send(());
+        if let Err(e) = self.task.await {
+            error!("Autosave task failed: {}", e);
+        }
+    }
+}
+
+struct Saver {
+    var_manager: VariableManager,
+    path: PathBuf,
+    backend: &'static dyn StorageBackend,
+    passphrase: Option<String>,
+    /// Whether secret variables are saved too.
+    secrets: bool,
+    changes: watch::Receiver<u64>,
+    /// The change count last written to the file.
+    saved: u64,
+}
+
+impl Saver {
+    async fn save_if_changed(&mut self) {
+        let current = *self.changes.borrow_and_update();
+        if current == self.saved {
+            return;
+        }
+        let store = match self.secrets {
+            true => self.var_manager.persisted_store(),
+            false => self.var_manager.persisted_store().without_secrets(),
+        };
+        match self.backend.save(&store, &self.path, self.passphrase.as_deref()).await {
+            Ok(()) => {
+                debug!("Autosaved variables to {:?}", self.path);
+                self.saved = current;
+            }
+            Err(e) => error!("Autosave to {:?} failed: {:#}", self.path, e),
+        }
+    }
+}
+
+/// Returns once variables have changed and then stayed unchanged for
+/// `QUIET_PERIOD`, or `interval` after the first change at the latest. With
+/// a zero interval it never returns: changes are only saved on exit.
+async fn wait_for_changes(changes: &mut watch::Receiver<u64>, interval: Duration) {
+    if interval.is_zero() || changes.changed().await.is_err() {
+        return std::future::pending().await;
+    }
+    let deadline = Instant::now() + interval;
+    loop {
+        tokio::select! {
+            _ = sleep(QUIET_PERIOD) => return,
+            _ = sleep_until(deadline) => return,
+            changed = changes.changed() => if changed.is_err() { return },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::VariableStore;
+    use crate::storage::JsonBackend;
+    use crate::util;
+    use std::path::Path;
+    use serde_json::json;
+
+    /// Autoload and autosave (on exit only) of a memory file in a fresh directory.
+    fn config(test: &str) -> (ShellConfig, PathBuf) {
+        let path = util::test_dir(&format!("session-{}", test)).join("memory.json");
+        let mut config = ShellConfig::default();
+        config.storage.memory_file = Some(path.display().to_string());
+        config.storage.autoload = true;
+        config.storage.autosave = true;
+        config.storage.autosave_interval_secs = 0;
+        (config, path)
+    }
+
+    /// Restores the memory file, runs `change` and autosaves, returning what was saved.
+    async fn restore_change_and_save(config: &ShellConfig, path: &Path, change: impl FnOnce(&VariableManager)) -> VariableStore {
+        let vars = VariableManager::new();
+        let restored = restore(&vars, config).await.unwrap().unwrap();
+        let autosave = Autosave::start(vars.clone(), config, restored.passphrase, restored.secrets).await.unwrap().unwrap();
+        change(&vars);
+        autosave.finish().await;
+        JsonBackend.load(path, None).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn autosave_keeps_secrets_the_file_was_restored_with() {
+        let (config, path) = config("keep-secrets");
+        let mut store = VariableStore::new();
+        store.insert("token".to_string(), json!("session-test-token"));
+        store.mark_secret("token".to_string());
+        store.insert("name".to_string(), json!("old"));
+        JsonBackend.save(&store, &path, None).await.unwrap();
+
+        let saved = restore_change_and_save(&config, &path, |vars| vars.set("name".to_string(), "new".to_string())).await;
+        assert_eq!(saved.get("name"), Some(&json!("new")));
+        assert_eq!(saved.get("token"), Some(&json!("session-test-token")));
+        assert!(saved.is_secret("token"));
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn autosave_leaves_out_secrets_if_the_file_had_none() {
+        let (config, path) = config("no-secrets");
+        let mut store = VariableStore::new();
+        store.insert("name".to_string(), json!("old"));
+        JsonBackend.save(&store, &path, None).await.unwrap();
+
+        let saved = restore_change_and_save(&config, &path, |vars| {
+            vars.set_secret("token".to_string(), json!("session-test-other"));
+        })
+        .await;
+        assert_eq!(saved.get("name"), Some(&json!("old")));
+        assert_eq!(saved.get("token"), None);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,111 @@
+// src/core/theme.rs
+// Turns the colors of `ThemeConfig` into terminal styling, and decides
+// whether styling is wanted at all.
+
+use std::io::IsTerminal;
+use std::str::FromStr;
+
+use colored::Color;
+
+/// The color names accepted by the theme settings.
+pub const COLOR_NAMES: &[&str] = &[
+    "black", "red", "green", "yellow", "blue", "magenta", "purple", "cyan", "white",
+    "bright black", "bright red", "bright green", "bright yellow", "bright blue",
+    "bright magenta", "bright cyan", "bright white",
+];
+
+/// A color as written in the theme settings: a name (`cyan`, `bright red`),
+/// a hex value (`#ff8800` or `#f80`) or an index into the 256-color palette (`208`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThemeColor {
+    /// A named or hex color. Hex colors fall back to the nearest named one
+    /// on terminals that don't advertise true color support.
+    Ansi(Color),
+    Indexed(u8),
+}
+
+impl FromStr for ThemeColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(hex) = s.strip_prefix('#') {
+            return parse_hex(hex)
+                .map(|(r, g, b)| ThemeColor::Ansi(Color::TrueColor { r, g, b }))
+                .ok_or_else(|| format!("invalid hex color '{}'; expected #rrggbb or #rgb", s));
+        }
+        if s.chars().all(|c| c.is_ascii_digit()) && !s.is_empty() {
+            return s
+                .parse::<u8>()
+                .map(ThemeColor::Indexed)
+                .map_err(|_| format!("color index {} is out of range; expected 0-255", s));
+        }
+        s.parse::<Color>().map(ThemeColor::Ansi).map_err(|_| {
+            format!("unknown color '{}'; expected one of {}, a #rrggbb value or 0-255", s, COLOR_NAMES.join(", "))
+        })
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<(u8, u8, u8)> {
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |digits: &str| u8::from_str_radix(digits, 16).ok();
+    match hex.len() {
+        6 => Some((channel(&hex[0..2])?, channel(&hex[2..4])?, channel(&hex[4..6])?)),
+        // `#f80` is shorthand for `#ff8800`.
+        3 => Some((channel(&hex[0..1])? * 17, channel(&hex[1..2])? * 17, channel(&hex[2..3])? * 17)),
+        _ => None,
+    }
+}
+
+impl ThemeColor {
+    /// The SGR parameters that select this color for the foreground.
+    pub fn sgr(&self) -> String {
+        match self {
+            ThemeColor::Ansi(color) => color.to_fg_str().into_owned(),
+            ThemeColor::Indexed(index) => format!("38;5;{}", index),
+        }
+    }
+}
+
+/// Where styled text is written; each is checked for being a terminal on its own.
+#[derive(Debug, Clone, Copy)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Whether text written to `stream` should be styled: not when `NO_COLOR`
+/// is set (to anything but the empty string), nor when the stream isn't a terminal.
+pub fn color_enabled(stream: Stream) -> bool {
+    if std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+        return false;
+    }
+    match stream {
+        Stream::Stdout => std::io::stdout().is_terminal(),
+        Stream::Stderr => std::io::stderr().is_terminal(),
+    }
+}
+
+/// The SGR parameters for `color` on `stream`, or `None` if it shouldn't be
+/// styled. An invalid color leaves text unstyled; validation reports it.
+pub fn style(color: &str, stream: Stream) -> Option<String> {
+    if !color_enabled(stream) {
+        return None;
+    }
+    color.parse::<ThemeColor>().ok().map(|color| color.sgr())
+}
+
+/// Wraps `text` in the escape sequences for `color`, if styling is wanted.
+pub fn paint(text: &str, color: &str, stream: Stream) -> String {
+    match style(color, stream) {
+        Some(sgr) => wrap(text, &sgr),
+        None => text.to_string(),
+    }
+}
+
+/// Wraps `text` in the escape sequences for the SGR parameters `sgr`.
+pub fn wrap(text: &str, sgr: &str) -> String {
+    format!("\x1b[{}m{}\x1b[0m", sgr, text)
+}
@@ -1,48 +1,197 @@
-use std::collections::HashMap;
+// src/core/types.rs
+// Shared types used across the dispatcher, parser and commands.
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use rustyline::completion::{Completer, Pair};
 use rustyline::highlight::Highlighter;
 use rustyline::hint::Hinter;
 use rustyline::validate::{Validator, ValidationContext, ValidationResult};
 use rustyline::{Helper, Context};
+use serde_json::Value as JsonValue;
+
+use crate::commands::command::Command;
+use crate::commands::function::FunctionCommand;
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+/// The in-memory key-value store backing `VariableManager`. Values are
+/// arbitrary JSON, so structured pipeline output keeps its shape.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
 pub struct VariableStore {
-    variables: HashMap<String, String>,
+    variables: HashMap<String, JsonValue>,
+    /// Names marked with `export`, passed to child processes.
+    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
+    exported: HashSet<String>,
+    /// Names of secret variables, whose values are masked when displayed.
+    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
+    secrets: HashSet<String>,
+    /// Names of variables saved from the global scope, which go back there
+    /// when the store is loaded.
+    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
+    globals: HashSet<String>,
 }
 
 impl VariableStore {
     pub fn new() -> Self {
         Self {
             variables: HashMap::new(),
+            exported: HashSet::new(),
+            secrets: HashSet::new(),
+            globals: HashSet::new(),
         }
     }
 
-    pub fn set(&mut self, key: &str, value: &str) {
-        self.variables.insert(key.to_string(), value.to_string());
+    pub fn insert(&mut self, key: String, value: JsonValue) -> Option<JsonValue> {
+        self.variables.insert(key, value)
     }
 
-    pub fn get(&self, key: &str) -> Option<&String> {
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
         self.variables.get(key)
     }
 
-    pub fn all(&self) -> &HashMap<String, String> {
+    pub fn remove(&mut self, key: &str) -> Option<JsonValue> {
+        self.exported.remove(key);
+        self.secrets.remove(key);
+        self.globals.remove(key);
+        self.variables.remove(key)
+    }
+
+    /// Marks a variable for inheritance by child processes.
+    pub fn export(&mut self, key: String) {
+        self.exported.insert(key);
+    }
+
+    pub fn is_exported(&self, key: &str) -> bool {
+        self.exported.contains(key)
+    }
+
+    pub fn exported(&self) -> impl Iterator<Item = &String> {
+        self.exported.iter()
+    }
+
+    /// Marks a variable as secret.
+    pub fn mark_secret(&mut self, key: String) {
+        self.secrets.insert(key);
+    }
+
+    pub fn is_secret(&self, key: &str) -> bool {
+        self.secrets.contains(key)
+    }
+
+    pub fn has_secrets(&self) -> bool {
+        !self.secrets.is_empty()
+    }
+
+    /// Marks a variable as belonging to the global scope.
+    pub fn mark_global(&mut self, key: String) {
+        self.globals.insert(key);
+    }
+
+    pub fn is_global(&self, key: &str) -> bool {
+        self.globals.contains(key)
+    }
+
+    /// Returns a copy of the store without its secret variables.
+    pub fn without_secrets(&self) -> VariableStore {
+        let mut store = self.clone();
+        for key in &self.secrets {
+            store.variables.remove(key);
+            store.exported.remove(key);
+            store.globals.remove(key);
+        }
+        store.secrets.clear();
+        store
+    }
+
+    /// Returns a copy holding only the given keys, with their flags.
+    pub fn select(&self, keys: &[String]) -> VariableStore {
+        VariableStore {
+            variables: self.variables.iter().filter(|(key, _)| keys.contains(key)).map(|(k, v)| (k.clone(), v.clone())).collect(),
+            exported: self.exported.iter().filter(|key| keys.contains(key)).cloned().collect(),
+            secrets: self.secrets.iter().filter(|key| keys.contains(key)).cloned().collect(),
+            globals: self.globals.iter().filter(|key| keys.contains(key)).cloned().collect(),
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.variables.keys()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.variables.is_empty()
+    }
+
+    pub fn all(&self) -> &HashMap<String, JsonValue> {
         &self.variables
     }
 }
 
-pub type CommandResult = Result<String, String>;
+impl<'a> IntoIterator for &'a VariableStore {
+    type Item = (&'a String, &'a JsonValue);
+    type IntoIter = std::collections::hash_map::Iter<'a, String, JsonValue>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.variables.iter()
+    }
+}
 
+/// The output produced by a successful command: human-readable text and/or
+/// structured data that can be handed to the next stage of a pipeline.
+#[derive(Debug, Clone)]
+pub struct CommandOutput {
+    pub text: Option<String>,
+    pub data: Option<JsonValue>,
+}
+
+/// The outcome of executing a command.
+#[derive(Debug, Clone)]
+pub struct CommandResult {
+    pub success: bool,
+    pub output: Option<CommandOutput>,
+    pub error_message: Option<String>,
+    /// Set by `exit`: the shell should stop once this result is reported.
+    pub exit_requested: bool,
+}
+
+impl CommandResult {
+    /// Creates a successful result with optional text and structured data.
+    pub fn success(text: Option<String>, data: Option<JsonValue>) -> Self {
+        CommandResult {
+            success: true,
+            output: Some(CommandOutput { text, data }),
+            error_message: None,
+            exit_requested: false,
+        }
+    }
+
+    /// Creates a successful result that asks the shell to exit.
+    pub fn exit(text: Option<String>) -> Self {
+        CommandResult { exit_requested: true, ..CommandResult::success(text, None) }
+    }
+
+    /// Creates a failed result with the given error message.
+    pub fn error(message: String) -> Self {
+        CommandResult {
+            success: false,
+            output: None,
+            error_message: Some(message),
+            exit_requested: false,
+        }
+    }
+}
+
+/// A single command within a pipeline, after parsing and variable resolution.
+#[derive(Debug, Clone)]
 pub struct PipelineCommand {
     pub name: String,
     pub args: Vec<String>,
 }
 
-pub enum CommandOutput {
-    Text(String),
-    None,
-}
+/// Maps command names to their implementations.
+pub type CommandRegistry = HashMap<String, Box<dyn Command>>;
 
-pub type CommandRegistry = HashMap<String, Box<dyn Fn(Vec<String>, &mut VariableStore) -> CommandResult + Send + Sync>>;
+/// Maps the names of user-defined functions to their definitions.
+pub type FunctionRegistry = HashMap<String, Arc<FunctionCommand>>;
 
 #[derive(Clone)]
 pub struct ShellFlowCompleter {
@@ -52,8 +201,11 @@ pub struct ShellFlowCompleter {
 impl Completer for ShellFlowCompleter {
     type Candidate = Pair;
     fn complete(&self, line: &str, _pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        // Functions can be defined at any time, so they are looked up on every completion.
+        let functions = crate::commands::function_names();
         let matches: Vec<Pair> = self.commands
             .iter()
+            .chain(functions.iter())
             .filter(|cmd| cmd.starts_with(line))
             .map(|cmd| Pair {
                 display: cmd.clone(),
@@ -66,6 +218,9 @@ impl Completer for ShellFlowCompleter {
 
 pub struct ShellFlowHelper {
     pub completer: ShellFlowCompleter,
+    /// SGR parameters the prompt is shown in, or `None` for a plain prompt.
+    /// Applied when drawing, so rustyline measures the prompt without them.
+    pub prompt_style: Option<String>,
 }
 
 impl Completer for ShellFlowHelper {
@@ -82,11 +237,23 @@ impl Hinter for ShellFlowHelper {
     }
 }
 
-impl Highlighter for ShellFlowHelper {}
+impl Highlighter for ShellFlowHelper {
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(&'s self, prompt: &'p str, _default: bool) -> Cow<'b, str> {
+        match &self.prompt_style {
+            Some(sgr) => Cow::Owned(crate::core::theme::wrap(prompt, sgr)),
+            None => Cow::Borrowed(prompt),
+        }
+    }
+}
 
 impl Validator for ShellFlowHelper {
-    fn validate(&self, _ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
-        Ok(ValidationResult::Valid(None))
+    /// Keeps reading lines while the input has an open block or quote, so
+    /// `if`, `while` and `for` can be typed over several lines.
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        match crate::parser::parse_command_list(ctx.input()) {
+            Err(e) if e.incomplete => Ok(ValidationResult::Incomplete),
+            _ => Ok(ValidationResult::Valid(None)),
+        }
     }
 }
 
@@ -2,74 +2,377 @@
 // Manages the in-memory variable store for Shellce.
 
 use crate::core::types::VariableStore;
+use crate::util;
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
 use std::sync::{Arc, Mutex};
-use log::{info, warn};
+use log::{debug, info, warn};
+use tokio::sync::watch;
 
-/// `VariableManager` holds and manages the in-memory key-value store.
-/// It uses `Arc<Mutex<...>>` for thread-safe access, as multiple commands
-/// might need to read/write variables concurrently in an async environment.
+/// The layers of the variable stack, from outermost to innermost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// Shared by everything; written with `global`.
+    Global,
+    /// The interactive session's variables.
+    Session,
+    /// The variables of a running script or function, discarded when it returns.
+    Local,
+}
+
+impl Scope {
+    pub fn label(self) -> &'static str {
+        match self {
+            Scope::Global => "global",
+            Scope::Session => "session",
+            Scope::Local => "local",
+        }
+    }
+}
+
+/// `VariableManager` holds and manages the in-memory key-value stores.
+/// Variables live in a stack of scopes: global, session, and one local scope
+/// per running script or function. Lookups walk the stack from the innermost
+/// scope outwards; plain assignments go to the innermost scope, so a script's
+/// temporaries never clobber the session's variables.
+///
+/// Each scope uses `Arc<Mutex<...>>` for thread-safe access, as multiple
+/// commands might need to read/write variables concurrently in an async
+/// environment. Clones share all scopes, and a local scope lives exactly as
+/// long as the `VariableManager` returned by `new_local_scope`.
 #[derive(Debug, Clone)]
 pub struct VariableManager {
-    store: Arc<Mutex<VariableStore>>,
+    /// Outermost first: global, session, then local scopes.
+    scopes: Vec<Arc<Mutex<VariableStore>>>,
+    /// Counts changes to the global and session scopes, the ones that are
+    /// persisted; see `subscribe_changes`.
+    changes: Arc<watch::Sender<u64>>,
 }
 
 impl VariableManager {
-    /// Creates a new, empty `VariableManager`.
+    /// Creates a new `VariableManager` with empty global and session scopes.
     pub fn new() -> Self {
         info!("Initializing VariableManager.");
         VariableManager {
-            store: Arc::new(Mutex::new(VariableStore::new())),
+            scopes: vec![
+                Arc::new(Mutex::new(VariableStore::new())),
+                Arc::new(Mutex::new(VariableStore::new())),
+            ],
+            changes: Arc::new(watch::Sender::new(0)),
         }
     }
 
-    /// Inserts or updates a variable in the store.
+    /// Returns a receiver that is notified whenever a global or session
+    /// variable changes. Its value is a counter of such changes.
+    pub fn subscribe_changes(&self) -> watch::Receiver<u64> {
+        self.changes.subscribe()
+    }
+
+    /// Records a change to the scope at `index`. Local scopes are never
+    /// persisted, so their changes don't count.
+    fn changed(&self, index: usize) {
+        if index <= 1 {
+            self.changes.send_modify(|count| *count += 1);
+        }
+    }
+
+    /// Returns a manager that sees the same variables, with a new, empty
+    /// local scope on top. The scope is popped when the returned manager
+    /// (and every clone of it) is dropped.
+    pub fn new_local_scope(&self) -> Self {
+        let mut scopes = self.scopes.clone();
+        scopes.push(Arc::new(Mutex::new(VariableStore::new())));
+        debug!("Pushed local scope (depth {}).", scopes.len());
+        VariableManager { scopes, changes: self.changes.clone() }
+    }
+
+    fn scope_kind(index: usize) -> Scope {
+        match index {
+            0 => Scope::Global,
+            1 => Scope::Session,
+            _ => Scope::Local,
+        }
+    }
+
+    /// Inserts or updates a string variable in the innermost scope.
     pub fn set(&self, key: String, value: String) {
-        let mut store = self.store.lock().unwrap();
-        store.insert(key.clone(), value.clone());
-        info!("Variable set: {} = {}", key, value);
+        self.set_value(key, JsonValue::String(value));
     }
 
-    /// Retrieves the value of a variable from the store.
-    pub fn get(&self, key: &str) -> Option<String> {
-        let store = self.store.lock().unwrap();
-        let value = store.get(key).cloned();
-        if value.is_some() {
-            info!("Variable retrieved: {}", key);
+    /// Inserts or updates a variable of any JSON type in the innermost scope.
+    pub fn set_value(&self, key: String, value: JsonValue) {
+        let index = self.scopes.len() - 1;
+        self.set_in(index, key, value, false);
+    }
+
+    /// Inserts or updates a variable in the global scope.
+    pub fn set_global(&self, key: String, value: JsonValue) {
+        self.set_in(0, key, value, false);
+    }
+
+    /// Inserts or updates a secret variable in the innermost scope. Its value
+    /// is masked in listings, logs, history and saved memory files.
+    pub fn set_secret(&self, key: String, value: JsonValue) {
+        let index = self.scopes.len() - 1;
+        self.set_in(index, key, value, true);
+    }
+
+    fn set_in(&self, index: usize, key: String, value: JsonValue, secret: bool) {
+        let mut store = self.scopes[index].lock().unwrap();
+        if store.is_secret(&key) {
+            if let Some(old) = store.get(&key) {
+                util::unregister_secret(old);
+            }
+        }
+        if secret {
+            store.mark_secret(key.clone());
+        }
+        // Updating a secret keeps it secret.
+        if store.is_secret(&key) {
+            util::register_secret(&value);
+            info!("Variable set ({}, secret): {} = {}", Self::scope_kind(index).label(), key, util::SECRET_MASK);
         } else {
-            warn!("Attempted to retrieve non-existent variable: {}", key);
+            info!("Variable set ({}): {} = {}", Self::scope_kind(index).label(), key, value);
         }
-        value
+        store.insert(key, value);
+        drop(store);
+        self.changed(index);
     }
 
-    /// Removes a variable from the store.
-    pub fn remove(&self, key: &str) -> Option<String> {
-        let mut store = self.store.lock().unwrap();
-        let value = store.remove(key);
-        if value.is_some() {
-            info!("Variable removed: {}", key);
-        } else {
-            warn!("Attempted to remove non-existent variable: {}", key);
+    /// Retrieves a variable as text from the innermost scope that has it.
+    /// Values that are not strings are rendered as JSON.
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.get_value(key).map(|value| util::format_value(&value))
+    }
+
+    /// Retrieves a variable's JSON value from the innermost scope that has it.
+    pub fn get_value(&self, key: &str) -> Option<JsonValue> {
+        match self.lookup(key) {
+            Some((scope, value)) => {
+                info!("Variable retrieved ({}): {}", scope.label(), key);
+                Some(value)
+            }
+            None => {
+                warn!("Attempted to retrieve non-existent variable: {}", key);
+                None
+            }
+        }
+    }
+
+    /// Returns `true` if the variable is secret in the scope it is read from.
+    pub fn is_secret(&self, key: &str) -> bool {
+        self.scopes.iter().rev().find_map(|scope| {
+            let store = scope.lock().unwrap();
+            store.get(key).map(|_| store.is_secret(key))
+        }) == Some(true)
+    }
+
+    /// Returns the scope a variable would be read from, if it is set.
+    pub fn scope_of(&self, key: &str) -> Option<Scope> {
+        self.lookup(key).map(|(scope, _)| scope)
+    }
+
+    fn lookup(&self, key: &str) -> Option<(Scope, JsonValue)> {
+        self.scopes.iter().enumerate().rev().find_map(|(index, scope)| {
+            let store = scope.lock().unwrap();
+            store.get(key).map(|value| (Self::scope_kind(index), value.clone()))
+        })
+    }
+
+    /// Removes a variable from the innermost scope. At the top level, where
+    /// that is the session scope, a global variable is removed if the
+    /// session has none of that name; a script or function can only remove
+    /// its own local variables.
+    pub fn remove(&self, key: &str) -> Option<JsonValue> {
+        let innermost = self.scopes.len() - 1;
+        let outermost = if innermost == 1 { 0 } else { innermost };
+        for index in (outermost..=innermost).rev() {
+            let mut store = self.scopes[index].lock().unwrap();
+            let secret = store.is_secret(key);
+            let removed = store.remove(key);
+            drop(store);
+            if let Some(value) = removed {
+                if secret {
+                    util::unregister_secret(&value);
+                }
+                info!("Variable removed ({}): {}", Self::scope_kind(index).label(), key);
+                self.changed(index);
+                return Some(value);
+            }
+        }
+        warn!("Attempted to remove non-existent variable: {}", key);
+        None
+    }
+
+    /// Marks a variable for inheritance by child processes started with
+    /// `exec`, in the innermost scope that has it. Returns `false` if the
+    /// variable is not set.
+    pub fn export(&self, key: &str) -> bool {
+        for (index, scope) in self.scopes.iter().enumerate().rev() {
+            let mut store = scope.lock().unwrap();
+            if store.get(key).is_some() {
+                store.export(key.to_string());
+                drop(store);
+                info!("Variable exported: {}", key);
+                self.changed(index);
+                return true;
+            }
+        }
+        warn!("Attempted to export non-existent variable: {}", key);
+        false
+    }
+
+    /// Returns the exported variables with their visible values as text, as
+    /// they should appear in a child process's environment. A variable is
+    /// exported if the scope it is read from marks it so.
+    pub fn exported_vars(&self) -> Vec<(String, String)> {
+        let mut names: Vec<String> = Vec::new();
+        for scope in &self.scopes {
+            names.extend(scope.lock().unwrap().exported().cloned());
+        }
+        names.sort_unstable();
+        names.dedup();
+
+        names
+            .into_iter()
+            .filter(|name| self.is_exported(name))
+            .filter_map(|name| self.get(&name).map(|value| (name, value)))
+            .collect()
+    }
+
+    /// Looks up a variable of the environment child processes see: an
+    /// exported shell variable, or else the process environment.
+    pub fn env_var(&self, name: &str) -> Option<String> {
+        if self.is_exported(name) {
+            return self.get(name);
         }
-        value
+        std::env::var(name).ok()
     }
 
-    /// Returns a clone of the entire variable store.
+    /// Returns the environment child processes see, sorted by name, with a
+    /// flag telling whether each entry comes from an exported shell variable.
+    pub fn effective_env(&self) -> Vec<(String, String, bool)> {
+        let mut env: BTreeMap<String, (String, bool)> = std::env::vars()
+            .map(|(name, value)| (name, (value, false)))
+            .collect();
+        for (name, value) in self.exported_vars() {
+            env.insert(name, (value, true));
+        }
+        env.into_iter().map(|(name, (value, exported))| (name, value, exported)).collect()
+    }
+
+    fn is_exported(&self, key: &str) -> bool {
+        self.scopes.iter().rev().find_map(|scope| {
+            let store = scope.lock().unwrap();
+            store.get(key).map(|_| store.is_exported(key))
+        }) == Some(true)
+    }
+
+    /// Returns every visible variable, with inner scopes shadowing outer ones.
     pub fn get_all(&self) -> VariableStore {
-        let store = self.store.lock().unwrap();
-        store.clone()
+        let mut merged = VariableStore::new();
+        for scope in &self.scopes {
+            for (key, value) in &*scope.lock().unwrap() {
+                merged.insert(key.clone(), value.clone());
+            }
+        }
+        for key in self.exported_vars().into_iter().map(|(key, _)| key) {
+            merged.export(key);
+        }
+        let secrets: Vec<String> = merged.keys().filter(|key| self.is_secret(key)).cloned().collect();
+        for key in secrets {
+            merged.mark_secret(key);
+        }
+        merged
+    }
+
+    /// Returns the global and session scopes as they are saved: the session's
+    /// variables, plus the global ones it doesn't shadow, marked as global.
+    pub fn persisted_store(&self) -> VariableStore {
+        let mut store = self.scopes[1].lock().unwrap().clone();
+        let global = self.scopes[0].lock().unwrap();
+        for (key, value) in &*global {
+            if store.get(key).is_some() {
+                continue;
+            }
+            if global.is_exported(key) {
+                store.export(key.clone());
+            }
+            if global.is_secret(key) {
+                store.mark_secret(key.clone());
+            }
+            store.mark_global(key.clone());
+            store.insert(key.clone(), value.clone());
+        }
+        store
     }
 
-    /// Replaces the entire variable store with a new one.
+    /// Replaces the global and session scopes with a store as returned by
+    /// `persisted_store`: variables marked as global go to the global scope,
+    /// the rest to the session. A global shadowed by a session variable
+    /// isn't in the store, so it is kept as it is.
     pub fn set_all(&self, new_store: VariableStore) {
-        let mut store = self.store.lock().unwrap();
-        *store = new_store;
-        info!("Variable store replaced.");
+        let mut global = VariableStore::new();
+        let mut session = VariableStore::new();
+        for (key, value) in &new_store {
+            let scope = if new_store.is_global(key) { &mut global } else { &mut session };
+            copy_flags(&new_store, key, scope);
+            scope.insert(key.clone(), value.clone());
+        }
+
+        let mut global_scope = self.scopes[0].lock().unwrap();
+        for (key, value) in &*global_scope {
+            if session.get(key).is_some() {
+                copy_flags(&global_scope, key, &mut global);
+                global.insert(key.clone(), value.clone());
+            }
+        }
+        let mut session_scope = self.scopes[1].lock().unwrap();
+        for store in [&*global_scope, &*session_scope] {
+            for_each_secret(store, util::unregister_secret);
+        }
+        for store in [&global, &session] {
+            for_each_secret(store, util::register_secret);
+        }
+        *global_scope = global;
+        *session_scope = session;
+        drop((global_scope, session_scope));
+        info!("Global and session variable stores replaced.");
+        self.changed(1);
+    }
+}
+
+impl Drop for VariableManager {
+    /// Pops a local scope once the last manager holding it is dropped, so
+    /// the values of its secrets are no longer masked.
+    fn drop(&mut self) {
+        if self.scopes.len() <= 2 {
+            return;
+        }
+        if let Some(store) = self.scopes.pop().and_then(Arc::into_inner) {
+            for_each_secret(&store.into_inner().unwrap(), util::unregister_secret);
+            debug!("Popped local scope (depth {}).", self.scopes.len() + 1);
+        }
+    }
+}
+
+fn for_each_secret(store: &VariableStore, mut f: impl FnMut(&JsonValue)) {
+    for (key, value) in store {
+        if store.is_secret(key) {
+            f(value);
+        }
     }
+}
 
-    /// Returns a list of all variable keys.
-    pub fn keys(&self) -> Vec<String> {
-        let store = self.store.lock().unwrap();
-        store.keys().cloned().collect()
+/// Copies the export and secret flags of `key` from one store to another.
+/// The global flag only says which scope a stored variable belongs to, so
+/// it isn't kept in the scopes themselves.
+fn copy_flags(from: &VariableStore, key: &str, to: &mut VariableStore) {
+    if from.is_exported(key) {
+        to.export(key.to_string());
+    }
+    if from.is_secret(key) {
+        to.mark_secret(key.to_string());
     }
 }
 
@@ -78,3 +381,87 @@ impl Default for VariableManager {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn remove_in_a_local_scope_leaves_outer_variables_alone() {
+        let vars = VariableManager::new();
+        vars.set("name".to_string(), "session".to_string());
+        let local = vars.new_local_scope();
+
+        assert_eq!(local.remove("name"), None);
+        assert_eq!(vars.get("name").as_deref(), Some("session"));
+
+        local.set("name".to_string(), "local".to_string());
+        assert_eq!(local.remove("name"), Some(json!("local")));
+        assert_eq!(local.get("name").as_deref(), Some("session"));
+    }
+
+    #[test]
+    fn remove_at_the_top_level_reaches_globals() {
+        let vars = VariableManager::new();
+        vars.set_global("name".to_string(), json!("global"));
+        vars.set("name".to_string(), "session".to_string());
+
+        assert_eq!(vars.remove("name"), Some(json!("session")));
+        assert_eq!(vars.remove("name"), Some(json!("global")));
+        assert_eq!(vars.remove("name"), None);
+    }
+
+    #[test]
+    fn persisted_store_round_trips_global_and_session_scopes() {
+        let vars = VariableManager::new();
+        vars.set_global("shared".to_string(), json!(1));
+        vars.set("mine".to_string(), "2".to_string());
+        let store = vars.persisted_store();
+        assert!(store.is_global("shared"));
+        assert!(!store.is_global("mine"));
+
+        let restored = VariableManager::new();
+        restored.set_all(store);
+        assert_eq!(restored.scope_of("shared"), Some(Scope::Global));
+        assert_eq!(restored.scope_of("mine"), Some(Scope::Session));
+    }
+
+    #[test]
+    fn set_all_keeps_a_shadowed_global() {
+        let vars = VariableManager::new();
+        vars.set_global("name".to_string(), json!("global"));
+        vars.set("name".to_string(), "session".to_string());
+
+        vars.set_all(vars.persisted_store());
+        assert_eq!(vars.remove("name"), Some(json!("session")));
+        assert_eq!(vars.get("name").as_deref(), Some("global"));
+    }
+
+    #[test]
+    fn changed_and_removed_secrets_are_no_longer_masked() {
+        let vars = VariableManager::new();
+        vars.set_secret("token".to_string(), json!("first-token-value"));
+        assert_eq!(util::redact("first-token-value"), util::SECRET_MASK);
+
+        vars.set_value("token".to_string(), json!("second-token-value"));
+        assert_eq!(util::redact("first-token-value"), "first-token-value");
+        assert_eq!(util::redact("second-token-value"), util::SECRET_MASK);
+
+        vars.remove("token");
+        assert_eq!(util::redact("second-token-value"), "second-token-value");
+    }
+
+    #[test]
+    fn secrets_of_a_popped_local_scope_are_no_longer_masked() {
+        let vars = VariableManager::new();
+        let local = vars.new_local_scope();
+        local.set_secret("token".to_string(), json!("local-token-value"));
+        let clone = local.clone();
+
+        drop(local);
+        assert_eq!(util::redact("local-token-value"), util::SECRET_MASK);
+        drop(clone);
+        assert_eq!(util::redact("local-token-value"), "local-token-value");
+    }
+}
@@ -1,13 +1,19 @@
 use std::path::PathBuf;
+use std::time::Instant;
 
 use rustyline::{Editor};
 use rustyline::error::ReadlineError;
 use rustyline::history::DefaultHistory;
 use colored::Colorize;
-use log::{info, error};
+use log::{info, error, warn};
 
 use crate::core::config::ShellConfig;
+use crate::core::config_validation;
+use crate::core::live_config;
+use crate::core::prompt::{self, PromptState};
+use crate::core::theme::{self, Stream};
 use crate::core::dispatcher::CommandDispatcher;
+use crate::core::session::{self, Autosave};
 use crate::core::variables::VariableManager;
 use crate::core::types::{ShellFlowHelper, ShellFlowCompleter};
 
@@ -24,27 +30,80 @@ async fn main() -> anyhow::Result<()> {
     util::init_logging();
     info!("Shellce application starting...");
 
-    let config_path = PathBuf::from("config.json");
-    let config = match ShellConfig::load(config_path) {
+    // `--no-restore` starts with empty memory and leaves the memory file alone.
+    let no_restore = std::env::args().skip(1).any(|arg| arg == "--no-restore");
+    // `--strict` refuses to start on any configuration problem instead of
+    // warning about it.
+    let strict = std::env::args().skip(1).any(|arg| arg == "--strict");
+
+    let command_registry = get_command_registry();
+    let config = match ShellConfig::load_layered() {
         Ok(cfg) => cfg,
+        Err(e) if strict => anyhow::bail!("Failed to load config: {:#}", e),
         Err(e) => {
-            error!("Failed to load config: {}. Using default config.", e);
+            error!("Failed to load config: {:#}. Using default config.", e);
+            eprintln!("{}", format!("Failed to load config: {:#}. Using default config.", e).red());
             ShellConfig::default()
         }
     };
+    let problems = config_validation::validate(&config, command_registry);
+    for problem in &problems {
+        let message = config_validation::describe(problem);
+        match problem.is_error() {
+            true => error!("{}", message),
+            false => warn!("{}", message),
+        }
+        eprintln!("{}", if strict && problem.is_error() { message.red() } else { message.yellow() });
+    }
+    let errors = problems.iter().filter(|problem| problem.is_error()).count();
+    if strict && errors > 0 {
+        anyhow::bail!("Refusing to start with {} configuration problem(s) in --strict mode", errors);
+    }
+    live_config::replace(config.clone());
+    let config_watcher = live_config::watch(command_registry, strict);
 
     let var_manager = VariableManager::new();
-    let command_registry = get_command_registry();
-    let dispatcher = CommandDispatcher::new(command_registry.clone());
+    let mut passphrase = None;
+    let mut secrets = false;
+    // Autosaving over a memory file that couldn't be read would replace
+    // everything in it with just this session's variables.
+    let mut restore_failed = false;
+    if no_restore {
+        info!("--no-restore given, not restoring or autosaving memory.");
+    } else {
+        match session::restore(&var_manager, &config).await {
+            Ok(Some(restored)) => {
+                println!("{}", format!("Restored {} variables from {:?}", restored.count, restored.path).yellow());
+                passphrase = restored.passphrase;
+                secrets = restored.secrets;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!("Failed to restore memory: {:#}", e);
+                eprintln!("{}", format!("Failed to restore memory: {:#}", e).red());
+                eprintln!("{}", "Autosave disabled so the memory file isn't overwritten.".red());
+                restore_failed = true;
+            }
+        }
+    }
+    let autosave = if no_restore || restore_failed {
+        None
+    } else {
+        Autosave::start(var_manager.clone(), &config, passphrase, secrets).await.unwrap_or_else(|e| {
+            error!("Failed to start autosave: {:#}", e);
+            eprintln!("{}", format!("Autosave disabled: {:#}", e).red());
+            None
+        })
+    };
+    let dispatcher = CommandDispatcher::new(command_registry);
 
     let history_path = PathBuf::from(&config.history_file);
 
     let completer = ShellFlowCompleter {
         commands: command_registry.keys().cloned().collect(),
-        variables: vec![],
     };
 
-    let helper = ShellFlowHelper { completer };
+    let helper = ShellFlowHelper { completer, prompt_style: None };
     let mut rl = Editor::<ShellFlowHelper, DefaultHistory>::new()?;
     rl.set_helper(Some(helper));
 
@@ -52,16 +111,48 @@ async fn main() -> anyhow::Result<()> {
         info!("No history file found, starting fresh.");
     }
 
-    println!("{}", "Welcome to Shellce!".cyan().bold());
-    println!("{}", "Type 'help' or 'exit'.".yellow());
+    println!("{}", theme::paint("Welcome to Shellce!", &config.theme.success_color, Stream::Stdout));
+    println!("{}", theme::paint("Type 'help' or 'exit'.", &config.theme.prompt_color, Stream::Stdout));
 
+    let mut prompt_state = PromptState::default();
     loop {
-        let readline = rl.readline("shellce> ");
+        live_config::print_pending_messages();
+        let current = live_config::current();
+        if let Some(helper) = rl.helper_mut() {
+            helper.prompt_style = theme::style(&current.theme.prompt_color, Stream::Stdout);
+        }
+        let prompt = prompt::render(&current.prompt, &var_manager, &prompt_state);
+        let readline = rl.readline(&prompt);
         match readline {
             Ok(line) => {
-                rl.add_history_entry(line.as_str())?;
-                if let Err(e) = dispatcher.handle_command(&line, &var_manager).await {
-                    error!("Command error: {:?}", e);
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                // Taken afresh for every line, so `config set` and edits to
+                // the config files apply from the next line on.
+                let config = live_config::current();
+
+                // Ctrl-C while a command is running cancels it: dropping the dispatch
+                // future aborts every stage of the pipeline, including child processes.
+                // Results are reported by the dispatcher as each pipeline completes.
+                let started = Instant::now();
+                let (status, exit_requested) = tokio::select! {
+                    result = dispatcher.dispatch_command(&line, &var_manager, &config) => {
+                        (if result.success { 0 } else { 1 }, result.exit_requested)
+                    }
+                    _ = tokio::signal::ctrl_c() => {
+                        println!("^C");
+                        (130, false)
+                    }
+                };
+                prompt_state = PromptState { last_status: Some(status), last_duration: Some(started.elapsed()) };
+
+                // Added after running, so the values of secrets the line itself
+                // defined (`remember --secret ...`) are masked too.
+                rl.add_history_entry(util::redact(&line))?;
+                if exit_requested {
+                    break;
                 }
             }
             Err(ReadlineError::Interrupted) => {
@@ -79,6 +170,11 @@ async fn main() -> anyhow::Result<()> {
         }
     }
 
+    config_watcher.abort();
+    if let Some(autosave) = autosave {
+        autosave.finish().await;
+    }
+
     rl.append_history(&history_path)?;
 
     Ok(())
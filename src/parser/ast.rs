@@ -0,0 +1,214 @@
+// src/parser/ast.rs
+// Syntax tree produced by the parser. Every node carries the byte span of the
+// source text it was parsed from, so later stages can point at it in errors.
+
+/// A half-open byte range `start..end` into the parsed source text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// Returns the smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+/// A piece of a word, before expansion.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WordPart {
+    /// Plain text taken verbatim from the input.
+    Literal(String),
+    /// A quoted string. Double-quoted strings may contain variable
+    /// references; a single-quoted string is always one `Literal`.
+    Quoted(Vec<WordPart>),
+    /// A `{name}` variable reference, holding the text between the braces
+    /// (including any modifier, e.g. `name:-default`).
+    Variable(String),
+    /// A `$(...)` command substitution, replaced by the text output of the
+    /// inner command list, or by the field of its data selected by `path`.
+    Substitution { list: Box<CommandList>, path: Option<String> },
+}
+
+/// A single shell word (command name or argument).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+    pub span: Span,
+}
+
+impl Word {
+    /// Returns the word's text if it is entirely unquoted literal text.
+    /// Keywords and block braces are only recognised in this form, so
+    /// quoting them (`"if"`, `'{'`) turns them back into plain words.
+    pub fn as_bare(&self) -> Option<&str> {
+        match self.parts.as_slice() {
+            [WordPart::Literal(text)] => Some(text),
+            _ => None,
+        }
+    }
+}
+
+/// A command name followed by its arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimpleCommand {
+    pub words: Vec<Word>,
+    pub span: Span,
+}
+
+/// One or more commands connected by `|`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pipeline {
+    pub commands: Vec<SimpleCommand>,
+    pub span: Span,
+}
+
+/// How a statement in a `CommandList` is joined to the one before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListOperator {
+    /// `;` or a newline - run the next statement unconditionally.
+    Sequence,
+    /// `&&` - run the next statement only if the previous one succeeded.
+    And,
+    /// `||` - run the next statement only if the previous one failed.
+    Or,
+}
+
+impl ListOperator {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            ListOperator::Sequence => ";",
+            ListOperator::And => "&&",
+            ListOperator::Or => "||",
+        }
+    }
+
+    /// Whether the statement after this operator runs, given whether the
+    /// previous statement succeeded.
+    pub fn should_run(self, previous_success: bool) -> bool {
+        match self {
+            ListOperator::Sequence => true,
+            ListOperator::And => previous_success,
+            ListOperator::Or => !previous_success,
+        }
+    }
+}
+
+/// A sequence of statements joined by `;`, newlines, `&&` and `||`. The
+/// operator of the first item is always `Sequence`. The body of a block
+/// may be empty.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CommandList {
+    pub items: Vec<(ListOperator, Statement)>,
+}
+
+/// A single executable unit of a command list.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Pipeline(Pipeline),
+    /// `if <condition> { ... } else { ... }`
+    If(IfStatement),
+    /// `while <condition> { ... }`
+    While(WhileLoop),
+    /// `for <name> in <items> { ... }`
+    For(ForLoop),
+    /// `fn <name>(<params>) { ... }`
+    Function(FunctionDef),
+}
+
+impl Statement {
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::Pipeline(pipeline) => pipeline.span,
+            Statement::If(statement) => statement.span,
+            Statement::While(statement) => statement.span,
+            Statement::For(statement) => statement.span,
+            Statement::Function(statement) => statement.span,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IfStatement {
+    /// Statements joined by `&&`/`||`; the branch is chosen by whether the
+    /// last one that ran succeeded.
+    pub condition: CommandList,
+    pub then_block: CommandList,
+    pub else_branch: Option<ElseBranch>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ElseBranch {
+    /// `else { ... }`
+    Block(CommandList),
+    /// `else if ...`
+    If(Box<IfStatement>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WhileLoop {
+    pub condition: CommandList,
+    pub body: CommandList,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForLoop {
+    pub variable: String,
+    /// The words to iterate over. A single `$(...)` word iterates over the
+    /// records produced by the substituted command instead of its text.
+    pub items: Vec<Word>,
+    pub body: CommandList,
+    pub span: Span,
+}
+
+/// The definition of a user-defined function. Running it registers the
+/// function; calling the function runs `body` with `params` bound to the
+/// call's arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionDef {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: CommandList,
+    pub span: Span,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn span_to_covers_both_spans_in_either_order() {
+        assert_eq!(Span::new(2, 4).to(Span::new(7, 9)), Span::new(2, 9));
+        assert_eq!(Span::new(7, 9).to(Span::new(2, 4)), Span::new(2, 9));
+        assert_eq!(Span::new(1, 9).to(Span::new(3, 4)), Span::new(1, 9));
+    }
+
+    #[test]
+    fn list_operators_decide_whether_the_next_statement_runs() {
+        assert!(ListOperator::Sequence.should_run(true));
+        assert!(ListOperator::Sequence.should_run(false));
+        assert!(ListOperator::And.should_run(true));
+        assert!(!ListOperator::And.should_run(false));
+        assert!(!ListOperator::Or.should_run(true));
+        assert!(ListOperator::Or.should_run(false));
+    }
+
+    #[test]
+    fn as_bare_only_accepts_unquoted_literal_text() {
+        let span = Span::new(0, 2);
+        let bare = Word { parts: vec![WordPart::Literal("if".to_string())], span };
+        let quoted = Word { parts: vec![WordPart::Quoted(vec![WordPart::Literal("if".to_string())])], span };
+        let variable = Word { parts: vec![WordPart::Variable("x".to_string())], span };
+        assert_eq!(bare.as_bare(), Some("if"));
+        assert_eq!(quoted.as_bare(), None);
+        assert_eq!(variable.as_bare(), None);
+    }
+}
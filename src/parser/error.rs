@@ -0,0 +1,116 @@
+// src/parser/error.rs
+// Parse errors that know where in the input they occurred.
+
+use std::fmt;
+
+use crate::parser::ast::Span;
+
+/// An error found while tokenizing or parsing a command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+    /// Set when the input ended before a construct was closed, e.g. an open
+    /// quote or block. The interactive prompt uses it to ask for more lines.
+    pub incomplete: bool,
+}
+
+impl ParseError {
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        ParseError { message: message.into(), span, incomplete: false }
+    }
+
+    /// Creates an error for input that ended before a construct was closed.
+    pub fn incomplete(message: impl Into<String>, span: Span) -> Self {
+        ParseError { message: message.into(), span, incomplete: true }
+    }
+
+    /// Renders the error together with the offending line of `source` and a
+    /// caret marker under the problem position:
+    ///
+    /// ```text
+    /// Unterminated quote.
+    ///   echo "hello
+    ///        ^
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        let start = self.span.start.min(source.len());
+        let line_start = source[..start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = source[start..].find('\n').map(|i| start + i).unwrap_or(source.len());
+        let line = &source[line_start..line_end];
+
+        // Columns are counted in characters so the caret lines up with multi-byte text.
+        let column = source[line_start..start].chars().count();
+        let end = self.span.end.clamp(start, line_end);
+        let width = source[start..end].chars().count().max(1);
+
+        let location = if source.contains('\n') {
+            let line_number = source[..line_start].matches('\n').count() + 1;
+            format!(" (line {})", line_number)
+        } else {
+            String::new()
+        };
+
+        format!(
+            "{}{}\n  {}\n  {}{}",
+            self.message,
+            location,
+            line,
+            " ".repeat(column),
+            "^".repeat(width)
+        )
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_command_list;
+
+    #[test]
+    fn render_points_a_caret_at_the_span() {
+        let error = ParseError::new("Bad word.", Span::new(5, 8));
+        assert_eq!(error.render("echo bad word"), "Bad word.\n  echo bad word\n       ^^^");
+    }
+
+    #[test]
+    fn render_shows_at_least_one_caret_at_the_end_of_input() {
+        let error = ParseError::new("Expected more.", Span::new(4, 4));
+        assert_eq!(error.render("echo"), "Expected more.\n  echo\n      ^");
+    }
+
+    #[test]
+    fn render_names_the_line_of_multiline_input() {
+        let error = ParseError::new("Bad.", Span::new(9, 12));
+        assert_eq!(error.render("echo a\n  foo bar\nls"), "Bad. (line 2)\n    foo bar\n    ^^^");
+    }
+
+    #[test]
+    fn render_counts_columns_in_characters() {
+        let source = "echo é \"x";
+        let error = parse_command_list(source).unwrap_err();
+        assert_eq!(error.render(source), "Unterminated double quote.\n  echo é \"x\n         ^");
+    }
+
+    #[test]
+    fn render_keeps_a_span_past_the_line_on_that_line() {
+        let error = ParseError::new("Bad.", Span::new(2, 20));
+        assert_eq!(error.render("ab cd\nef"), "Bad. (line 1)\n  ab cd\n    ^^^");
+    }
+
+    #[test]
+    fn incomplete_is_only_set_for_input_that_ended_early() {
+        assert!(parse_command_list("echo \"abc").unwrap_err().incomplete);
+        assert!(parse_command_list("echo $(ls").unwrap_err().incomplete);
+        assert!(!parse_command_list("echo {abc").unwrap_err().incomplete);
+        assert!(!parse_command_list("| echo").unwrap_err().incomplete);
+    }
+}
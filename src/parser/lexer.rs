@@ -0,0 +1,529 @@
+// src/parser/lexer.rs
+// Splits a command line into words and operators, recording the span of each.
+
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use crate::parser::ast::{Span, Word, WordPart};
+use crate::parser::error::ParseError;
+use crate::parser::{self, variable_resolver};
+
+/// The kinds of token produced by `tokenize`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Word(Word),
+    /// `|`
+    Pipe,
+    /// `&&`
+    AndIf,
+    /// `||`
+    OrIf,
+    /// `;`
+    Semi,
+    /// An unquoted line break. Separates statements like `;`.
+    Newline,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+/// Tokenizes a command line using POSIX-like quoting rules.
+///
+/// Words are separated by unquoted whitespace and operators, and a word may
+/// be made of several adjacent fragments (`"a"b'c'` is the single word `abc`):
+/// - inside single quotes everything is literal, including `{` and `\`;
+/// - inside double quotes `{name}` is a variable reference, and `\` escapes
+///   `"`, `\`, `{`, `}` and `$`;
+/// - outside quotes `\` makes the next character literal.
+pub fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
+    tokenize_range(input, 0, input.len())
+}
+
+/// Splits `input` into words with the quoting rules of `tokenize`, keeping
+/// their parts for expansion. Operators are kept as plain words, as written.
+/// Used for alias targets, which replace a command name after its line has
+/// been expanded and are expanded on their own.
+pub fn words(input: &str) -> Result<Vec<Word>, ParseError> {
+    let words = tokenize(input)?
+        .into_iter()
+        .filter(|token| token.kind != TokenKind::Newline)
+        .map(|token| match token.kind {
+            TokenKind::Word(word) => word,
+            _ => Word {
+                parts: vec![WordPart::Literal(input[token.span.start..token.span.end].to_string())],
+                span: token.span,
+            },
+        })
+        .collect();
+    Ok(words)
+}
+
+/// Like `words`, but without expanding anything: quotes are removed, while
+/// variable references and substitutions are kept as written.
+pub fn split_words(input: &str) -> Result<Vec<String>, ParseError> {
+    let words = words(input)?
+        .into_iter()
+        .map(|word| literal_text(&word.parts).unwrap_or_else(|| input[word.span.start..word.span.end].to_string()))
+        .collect();
+    Ok(words)
+}
+
+/// The text of word parts made of literal and quoted text only.
+fn literal_text(parts: &[WordPart]) -> Option<String> {
+    let mut text = String::new();
+    for part in parts {
+        match part {
+            WordPart::Literal(literal) => text.push_str(literal),
+            WordPart::Quoted(inner) => text.push_str(&literal_text(inner)?),
+            WordPart::Variable(_) | WordPart::Substitution { .. } => return None,
+        }
+    }
+    Some(text)
+}
+
+/// Tokenizes `input[start..end]`, keeping spans relative to the whole input.
+fn tokenize_range(input: &str, start: usize, end: usize) -> Result<Vec<Token>, ParseError> {
+    let input = &input[..end];
+    let mut chars = input.char_indices().peekable();
+    while chars.next_if(|&(pos, _)| pos < start).is_some() {}
+    Lexer { input, chars, tokens: Vec::new() }.run()
+}
+
+struct Lexer<'a> {
+    input: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    tokens: Vec<Token>,
+}
+
+impl<'a> Lexer<'a> {
+    fn run(mut self) -> Result<Vec<Token>, ParseError> {
+        while let Some(&(pos, c)) = self.chars.peek() {
+            match c {
+                ' ' | '\t' | '\r' => {
+                    self.chars.next();
+                },
+                '\n' => {
+                    self.chars.next();
+                    self.push(TokenKind::Newline, pos, pos + 1);
+                },
+                '|' => {
+                    self.chars.next();
+                    if self.eat('|') {
+                        self.push(TokenKind::OrIf, pos, pos + 2);
+                    } else {
+                        self.push(TokenKind::Pipe, pos, pos + 1);
+                    }
+                },
+                '&' if self.input[pos..].starts_with("&&") => {
+                    self.chars.next();
+                    self.chars.next();
+                    self.push(TokenKind::AndIf, pos, pos + 2);
+                },
+                ';' => {
+                    self.chars.next();
+                    self.push(TokenKind::Semi, pos, pos + 1);
+                },
+                '#' => {
+                    // A `#` at the start of a word comments out the rest of the line.
+                    while self.chars.next_if(|&(_, c)| c != '\n').is_some() {}
+                },
+                _ => self.word(pos)?,
+            }
+        }
+        Ok(self.tokens)
+    }
+
+    /// Reads a word: adjacent unquoted, quoted and variable fragments up to
+    /// the next unquoted whitespace or operator.
+    fn word(&mut self, start: usize) -> Result<(), ParseError> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+
+        while let Some(&(pos, c)) = self.chars.peek() {
+            match c {
+                ' ' | '\t' | '\r' | '\n' | '|' | ';' => break,
+                '&' if self.input[pos..].starts_with("&&") => break,
+                '"' | '\'' => {
+                    if !literal.is_empty() {
+                        parts.push(WordPart::Literal(std::mem::take(&mut literal)));
+                    }
+                    let part = if c == '"' { self.double_quoted(pos)? } else { self.single_quoted(pos)? };
+                    parts.push(part);
+                },
+                '{' if self.starts_variable(pos) => {
+                    if !literal.is_empty() {
+                        parts.push(WordPart::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(self.variable(pos)?);
+                },
+                '$' if self.input[pos..].starts_with("$(") => {
+                    if !literal.is_empty() {
+                        parts.push(WordPart::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(self.substitution(pos)?);
+                },
+                '\\' => {
+                    self.chars.next();
+                    // A trailing backslash has nothing to escape and stays literal.
+                    match self.chars.next() {
+                        Some((_, escaped)) => literal.push(escaped),
+                        None => literal.push('\\'),
+                    }
+                },
+                _ => {
+                    self.chars.next();
+                    literal.push(c);
+                },
+            }
+        }
+
+        if !literal.is_empty() {
+            parts.push(WordPart::Literal(literal));
+        }
+        let end = self.chars.peek().map(|&(pos, _)| pos).unwrap_or(self.input.len());
+        self.push_word(parts, start, end);
+        Ok(())
+    }
+
+    /// Reads a double-quoted string starting at the opening quote.
+    fn double_quoted(&mut self, start: usize) -> Result<WordPart, ParseError> {
+        self.chars.next(); // Opening quote
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+
+        while let Some(&(pos, c)) = self.chars.peek() {
+            match c {
+                '"' => {
+                    self.chars.next();
+                    if !literal.is_empty() {
+                        parts.push(WordPart::Literal(literal));
+                    }
+                    return Ok(WordPart::Quoted(parts));
+                },
+                '{' if self.starts_variable(pos) => {
+                    if !literal.is_empty() {
+                        parts.push(WordPart::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(self.variable(pos)?);
+                },
+                '$' if self.input[pos..].starts_with("$(") => {
+                    if !literal.is_empty() {
+                        parts.push(WordPart::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(self.substitution(pos)?);
+                },
+                '\\' => {
+                    self.chars.next();
+                    match self.chars.peek() {
+                        Some(&(_, escaped @ ('"' | '\\' | '{' | '}' | '$'))) => {
+                            self.chars.next();
+                            literal.push(escaped);
+                        },
+                        // Any other backslash is kept as-is, like in POSIX shells.
+                        _ => literal.push('\\'),
+                    }
+                },
+                _ => {
+                    self.chars.next();
+                    literal.push(c);
+                },
+            }
+        }
+
+        Err(ParseError::incomplete("Unterminated double quote.", Span::new(start, start + 1)))
+    }
+
+    /// Reads a single-quoted string starting at the opening quote. Its
+    /// contents are taken literally.
+    fn single_quoted(&mut self, start: usize) -> Result<WordPart, ParseError> {
+        self.chars.next(); // Opening quote
+        let mut literal = String::new();
+
+        for (_, c) in self.chars.by_ref() {
+            if c == '\'' {
+                return Ok(WordPart::Quoted(vec![WordPart::Literal(literal)]));
+            }
+            literal.push(c);
+        }
+
+        Err(ParseError::incomplete("Unterminated single quote.", Span::new(start, start + 1)))
+    }
+
+    /// Reads a `{name}` variable reference starting at the opening brace.
+    /// Braces nest, so a modifier may itself refer to variables (`{a:-{b}}`).
+    fn variable(&mut self, start: usize) -> Result<WordPart, ParseError> {
+        self.chars.next(); // Opening brace
+        let mut expr = String::new();
+        let mut depth = 1;
+
+        for (_, c) in self.chars.by_ref() {
+            match c {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(WordPart::Variable(expr));
+                    }
+                },
+                _ => {},
+            }
+            expr.push(c);
+        }
+
+        Err(ParseError::new("Unclosed variable reference, expected '}'.", Span::new(start, start + 1)))
+    }
+
+    /// Reads a `$(...)` command substitution starting at the `$`, and the
+    /// optional `[path]` field selector right after it.
+    fn substitution(&mut self, start: usize) -> Result<WordPart, ParseError> {
+        let body_start = start + 2;
+        let body_end = self.find_closing_paren(body_start).ok_or_else(|| {
+            ParseError::incomplete("Unclosed command substitution, expected ')'.", Span::new(start, start + 2))
+        })?;
+
+        let tokens = tokenize_range(self.input, body_start, body_end)?;
+        let list = parser::parse_tokens(tokens)?;
+        if list.items.is_empty() {
+            return Err(ParseError::new("Empty command substitution.", Span::new(start, body_end + 1)));
+        }
+        while self.chars.next_if(|&(pos, _)| pos <= body_end).is_some() {}
+
+        let mut path = None;
+        if self.eat('[') {
+            let mut selector = String::new();
+            loop {
+                match self.chars.next() {
+                    Some((_, ']')) => break,
+                    Some((_, c)) => selector.push(c),
+                    None => {
+                        return Err(ParseError::new(
+                            "Unclosed field selector, expected ']'.",
+                            Span::new(body_end + 1, body_end + 2),
+                        ));
+                    },
+                }
+            }
+            path = Some(selector);
+        }
+
+        Ok(WordPart::Substitution { list: Box::new(list), path })
+    }
+
+    /// Finds the `)` matching the `(` just before `from`, skipping over quoted
+    /// text, escaped characters and nested parentheses.
+    fn find_closing_paren(&self, from: usize) -> Option<usize> {
+        let mut depth = 1;
+        let mut quote: Option<char> = None;
+        let mut chars = self.input[from..].char_indices();
+
+        while let Some((offset, c)) = chars.next() {
+            match (quote, c) {
+                (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+                (Some('\''), _) => {},
+                (_, '\\') => {
+                    chars.next();
+                },
+                (Some(_), _) => {},
+                (None, '\'' | '"') => quote = Some(c),
+                (None, '(') => depth += 1,
+                (None, ')') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(from + offset);
+                    }
+                },
+                _ => {},
+            }
+        }
+        None
+    }
+
+    /// A `{` only starts a variable reference when a name (or the `#` of a
+    /// length expression) follows it directly, so that literal braces (e.g. in
+    /// JSON) pass through untouched.
+    fn starts_variable(&self, pos: usize) -> bool {
+        variable_resolver::starts_variable(&self.input[pos + 1..])
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.chars.peek().map(|&(_, c)| c) == Some(expected) {
+            self.chars.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn push(&mut self, kind: TokenKind, start: usize, end: usize) {
+        self.tokens.push(Token { kind, span: Span::new(start, end) });
+    }
+
+    fn push_word(&mut self, parts: Vec<WordPart>, start: usize, end: usize) {
+        let span = Span::new(start, end);
+        self.push(TokenKind::Word(Word { parts, span }), start, end);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn literal(text: &str) -> WordPart {
+        WordPart::Literal(text.to_string())
+    }
+
+    fn word(parts: Vec<WordPart>, start: usize, end: usize) -> Token {
+        let span = Span::new(start, end);
+        Token { kind: TokenKind::Word(Word { parts, span }), span }
+    }
+
+    fn spans(input: &str) -> Vec<(usize, usize)> {
+        tokenize(input).unwrap().iter().map(|token| (token.span.start, token.span.end)).collect()
+    }
+
+    #[test]
+    fn words_and_operators_carry_their_spans() {
+        let tokens = tokenize("ls -l | count && echo ok || echo no; x").unwrap();
+        let kinds: Vec<&TokenKind> = tokens.iter().map(|token| &token.kind).collect();
+        assert_eq!(kinds[2], &TokenKind::Pipe);
+        assert_eq!(kinds[4], &TokenKind::AndIf);
+        assert_eq!(kinds[7], &TokenKind::OrIf);
+        assert_eq!(kinds[10], &TokenKind::Semi);
+        assert_eq!(
+            spans("ls -l | count && echo ok || echo no; x"),
+            vec![(0, 2), (3, 5), (6, 7), (8, 13), (14, 16), (17, 21), (22, 24), (25, 27), (28, 32), (33, 35), (35, 36), (37, 38)]
+        );
+    }
+
+    #[test]
+    fn operators_need_no_surrounding_spaces() {
+        let tokens = tokenize("a|b&&c||d;e").unwrap();
+        let operators: Vec<&TokenKind> =
+            tokens.iter().map(|token| &token.kind).filter(|kind| !matches!(kind, TokenKind::Word(_))).collect();
+        assert_eq!(operators, vec![&TokenKind::Pipe, &TokenKind::AndIf, &TokenKind::OrIf, &TokenKind::Semi]);
+    }
+
+    #[test]
+    fn a_single_ampersand_is_part_of_a_word() {
+        let tokens = tokenize("a&b").unwrap();
+        assert_eq!(tokens, vec![word(vec![literal("a&b")], 0, 3)]);
+    }
+
+    #[test]
+    fn newlines_are_tokens_and_comments_are_dropped() {
+        let tokens = tokenize("echo a # note\necho b").unwrap();
+        let kinds: Vec<&TokenKind> = tokens.iter().map(|token| &token.kind).collect();
+        assert_eq!(kinds.len(), 5);
+        assert_eq!(kinds[2], &TokenKind::Newline);
+        assert_eq!(tokens[2].span, Span::new(13, 14));
+    }
+
+    #[test]
+    fn spans_are_byte_offsets() {
+        assert_eq!(spans("echo héllo x"), vec![(0, 4), (5, 11), (12, 13)]);
+    }
+
+    #[test]
+    fn substitution_spans_are_relative_to_the_whole_line() {
+        let tokens = tokenize("echo $(ls . | count)").unwrap();
+        let TokenKind::Word(word) = &tokens[1].kind else {
+            panic!("expected a word, got {:?}", tokens[1].kind);
+        };
+        let [WordPart::Substitution { list, path: None }] = word.parts.as_slice() else {
+            panic!("expected a substitution, got {:?}", word.parts);
+        };
+        assert_eq!(list.items[0].1.span(), Span::new(7, 19));
+    }
+
+    #[test]
+    fn split_words_removes_quotes_only() {
+        assert_eq!(split_words("echo 'a  b' \"c d\"e").unwrap(), vec!["echo", "a  b", "c de"]);
+        assert_eq!(split_words("echo {name} $(ls) |").unwrap(), vec!["echo", "{name}", "$(ls)", "|"]);
+        assert!(split_words("").unwrap().is_empty());
+        assert!(split_words("echo 'open").is_err());
+    }
+
+    fn words(input: &str) -> Vec<Vec<WordPart>> {
+        tokenize(input)
+            .unwrap()
+            .into_iter()
+            .filter_map(|token| match token.kind {
+                TokenKind::Word(word) => Some(word.parts),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn quoted(parts: Vec<WordPart>) -> WordPart {
+        WordPart::Quoted(parts)
+    }
+
+    #[test]
+    fn adjacent_fragments_make_one_word() {
+        assert_eq!(
+            words("\"a\"b'c'"),
+            vec![vec![quoted(vec![literal("a")]), literal("b"), quoted(vec![literal("c")])]]
+        );
+    }
+
+    #[test]
+    fn single_quotes_keep_everything_literal() {
+        assert_eq!(words("'{x} \\ $(ls) | ;'"), vec![vec![quoted(vec![literal("{x} \\ $(ls) | ;")])]]);
+        assert_eq!(words("''"), vec![vec![quoted(vec![literal("")])]]);
+    }
+
+    #[test]
+    fn double_quotes_expand_variables_and_keep_operators() {
+        assert_eq!(
+            words("\"hi {name} | x\""),
+            vec![vec![quoted(vec![literal("hi "), WordPart::Variable("name".to_string()), literal(" | x")])]]
+        );
+        assert_eq!(words("\"\""), vec![vec![quoted(vec![])]]);
+    }
+
+    #[test]
+    fn double_quotes_only_unescape_special_characters() {
+        assert_eq!(words(r#""\" \\ \{ \} \$ \n""#), vec![vec![quoted(vec![literal(r#"" \ { } $ \n"#)])]]);
+    }
+
+    #[test]
+    fn backslash_outside_quotes_escapes_any_character() {
+        assert_eq!(words(r"a\ b \| \{x\}"), vec![vec![literal("a b")], vec![literal("|")], vec![literal("{x}")]]);
+        assert_eq!(words(r"\'a"), vec![vec![literal("'a")]]);
+    }
+
+    #[test]
+    fn a_trailing_backslash_stays_literal() {
+        assert_eq!(words("echo a\\"), vec![vec![literal("echo")], vec![literal("a\\")]]);
+    }
+
+    #[test]
+    fn unterminated_quotes_point_at_the_opening_quote() {
+        let error = tokenize("echo 'abc").unwrap_err();
+        assert_eq!(error.message, "Unterminated single quote.");
+        assert_eq!(error.span, Span::new(5, 6));
+        assert!(error.incomplete);
+
+        let error = tokenize("echo x\"ab\\\"").unwrap_err();
+        assert_eq!(error.message, "Unterminated double quote.");
+        assert_eq!(error.span, Span::new(6, 7));
+        assert!(error.incomplete);
+    }
+
+    #[test]
+    fn quotes_may_span_lines() {
+        assert_eq!(words("'a\nb'"), vec![vec![quoted(vec![literal("a\nb")])]]);
+    }
+
+    #[test]
+    fn quoted_operators_inside_substitutions_do_not_end_them() {
+        let parts = &words("$(echo ')' \"(\")")[0];
+        let [WordPart::Substitution { list, .. }] = parts.as_slice() else {
+            panic!("expected a substitution, got {:?}", parts);
+        };
+        assert_eq!(list.items.len(), 1);
+    }
+}
@@ -1,113 +1,624 @@
 // src/parser/mod.rs
-// Contains the main parsing logic for command lines, including pipelines.
+// Contains the main parsing logic for command lines: turning tokens into a
+// syntax tree. Expansion of the tree's words happens in the dispatcher.
 
+pub mod ast;
+pub mod error;
+pub mod lexer;
 pub mod variable_resolver;
 
-use anyhow::{Result, Context};
-use crate::core::variables::VariableManager;
-use crate::core::types::PipelineCommand;
 use log::debug;
 
-/// Parses a raw command line string into a vector of `PipelineCommand`s,
-/// handling quoted strings, variable resolution, and pipe (`|`) operators.
+use ast::{
+    CommandList, ElseBranch, ForLoop, FunctionDef, IfStatement, ListOperator, Pipeline, SimpleCommand,
+    Span, Statement, WhileLoop, Word,
+};
+use error::ParseError;
+use lexer::{Token, TokenKind};
+
+/// Parses a command line or a whole script into a `CommandList`: statements
+/// joined by `;`, newlines, `&&` and `||`. A statement is a pipeline of
+/// commands or one of the control-flow constructs:
+///
+/// ```text
+/// if <condition> { ... } else if <condition> { ... } else { ... }
+/// while <condition> { ... }
+/// for <name> in <words> { ... }
+/// fn <name>(<param>, ...) { ... }
+/// ```
+///
+/// A condition is a pipeline, or several joined by `&&`/`||`; it holds when
+/// the command that ran last succeeded. Blocks may span several lines, and
+/// their braces must be separate words (`{ echo hi }`, not `{echo hi}`,
+/// which is a variable reference). Elsewhere, `{` and `}` are plain words.
+///
+/// Parsing is purely syntactic; variable references and command
+/// substitutions are kept in the tree and only expanded by the dispatcher
+/// right before a pipeline runs, so that it sees the effects of the pipelines
+/// before it.
 ///
 /// # Arguments
-/// * `command_line` - The raw string entered by the user.
-/// * `var_manager` - A reference to the `VariableManager` for variable resolution.
+/// * `command_line` - The raw string entered by the user, or a script.
 ///
 /// # Returns
-/// A `Result` containing a `Vec<PipelineCommand>` or an `anyhow::Error`.
-pub fn parse_pipeline(command_line: &str, var_manager: &VariableManager) -> Result<Vec<PipelineCommand>> {
-    let mut pipeline = Vec::new();
-    let mut current_command_str = String::new();
-    let mut chars = command_line.chars().peekable();
-    let mut in_quote = false;
-
-    while let Some(c) = chars.next() {
-        match c {
-            '|' if !in_quote => {
-                // End of a command in the pipeline
-                if !current_command_str.trim().is_empty() {
-                    pipeline.push(parse_single_command(&current_command_str, var_manager)?);
-                }
-                current_command_str.clear();
-            },
-            '"' => {
-                in_quote = !in_quote;
-                current_command_str.push(c); // Keep quotes for internal parsing
+/// A `Result` containing the `CommandList` (empty if the input only holds
+/// blanks and comments), or a `ParseError` pointing at the offending part of
+/// the input.
+pub fn parse_command_list(command_line: &str) -> Result<CommandList, ParseError> {
+    parse_tokens(lexer::tokenize(command_line)?)
+}
+
+/// Builds a `CommandList` from already tokenized input.
+/// Also used by the lexer for the body of a `$(...)` substitution.
+pub(crate) fn parse_tokens(tokens: Vec<Token>) -> Result<CommandList, ParseError> {
+    let mut parser = Parser { tokens, pos: 0, block_depth: 0, in_header: false };
+    let list = parser.list(false)?;
+    debug!("Parsed command list: {:?}", list);
+    Ok(list)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// How many blocks enclose the current position; a bare `}` only ends a
+    /// command inside one.
+    block_depth: usize,
+    /// Whether an `if`/`while` condition or `for` item list is being parsed,
+    /// where a bare `{` starts the block.
+    in_header: bool,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    /// The text of the next token if it is a bare word, e.g. a keyword or brace.
+    fn peek_bare(&self) -> Option<&str> {
+        match self.peek() {
+            Some(Token { kind: TokenKind::Word(word), .. }) => word.as_bare(),
+            _ => None,
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while matches!(self.peek(), Some(Token { kind: TokenKind::Newline, .. })) {
+            self.pos += 1;
+        }
+    }
+
+    /// The span of the next token, or an empty span at the end of the input.
+    fn next_span(&self) -> Span {
+        match self.peek() {
+            Some(token) => token.span,
+            None => {
+                let end = self.tokens.last().map_or(0, |token| token.span.end);
+                Span::new(end, end)
             },
-            _ => {
-                current_command_str.push(c);
+        }
+    }
+
+    /// list := (statement (('&&' | '||') statement)* (';' | newline)*)*
+    ///
+    /// Stops at the end of the input, or at a `}` when parsing a block.
+    fn list(&mut self, in_block: bool) -> Result<CommandList, ParseError> {
+        let mut items = Vec::new();
+
+        loop {
+            while matches!(self.peek(), Some(Token { kind: TokenKind::Semi | TokenKind::Newline, .. })) {
+                self.pos += 1;
+            }
+            match self.peek_bare() {
+                None if self.peek().is_none() => break,
+                Some("}") if in_block => break,
+                Some("}") => return Err(ParseError::new("Unexpected '}' without an open block.", self.next_span())),
+                Some("{") => return Err(ParseError::new("Unexpected '{' outside of if, while or for.", self.next_span())),
+                _ => {},
+            }
+
+            let chain = self.and_or()?;
+            let mut chain = chain.items.into_iter();
+            if let Some((_, first)) = chain.next() {
+                items.push((ListOperator::Sequence, first));
+                items.extend(chain);
+            }
+
+            match self.peek() {
+                None | Some(Token { kind: TokenKind::Semi | TokenKind::Newline, .. }) => {},
+                Some(_) if in_block && self.peek_bare() == Some("}") => {},
+                Some(token) => {
+                    return Err(ParseError::new("Expected ';' or a new line before this.", token.span));
+                },
             }
         }
+
+        Ok(CommandList { items })
     }
 
-    // Add the last command in the pipeline
-    if !current_command_str.trim().is_empty() {
-        pipeline.push(parse_single_command(&current_command_str, var_manager)?);
+    /// and_or := statement (('&&' | '||') newline* statement)*
+    fn and_or(&mut self) -> Result<CommandList, ParseError> {
+        let mut items = vec![(ListOperator::Sequence, self.statement()?)];
+
+        while let Some(token) = self.peek() {
+            let op = match token.kind {
+                TokenKind::AndIf => ListOperator::And,
+                TokenKind::OrIf => ListOperator::Or,
+                _ => break,
+            };
+            let op_span = token.span;
+            self.pos += 1;
+            self.skip_newlines();
+            if self.peek().is_none() {
+                return Err(ParseError::incomplete(format!("Missing command after '{}'.", op.symbol()), op_span));
+            }
+            items.push((op, self.statement()?));
+        }
+
+        Ok(CommandList { items })
     }
 
-    if pipeline.is_empty() {
-        anyhow::bail!("No command entered.");
+    /// statement := if | while | for | fn | pipeline
+    fn statement(&mut self) -> Result<Statement, ParseError> {
+        match self.peek_bare() {
+            Some("if") => Ok(Statement::If(self.if_statement()?)),
+            Some("while") => self.while_loop(),
+            Some("for") => self.for_loop(),
+            Some("fn") => self.function(),
+            _ => Ok(Statement::Pipeline(self.pipeline()?)),
+        }
     }
 
-    debug!("Parsed pipeline: {:?}", pipeline);
-    Ok(pipeline)
-}
+    /// if := 'if' and_or block (newline* 'else' (if | block))?
+    fn if_statement(&mut self) -> Result<IfStatement, ParseError> {
+        let keyword = self.next_span();
+        self.pos += 1;
+        let condition = self.condition("if", keyword)?;
+        let (then_block, mut end) = self.block("if")?;
 
-/// Parses a single command string (which may contain quoted arguments and variables)
-/// into a command name and its arguments.
-fn parse_single_command(command_str: &str, var_manager: &VariableManager) -> Result<PipelineCommand> {
-    let mut args = Vec::new();
-    let mut chars = command_str.chars().peekable();
-    let mut current_arg = String::new();
-    let mut in_quote = false;
-
-    while let Some(c) = chars.next() {
-        match c {
-            '"' => {
-                in_quote = !in_quote;
-                // If ending a quote, and the current_arg is not empty, add it.
-                // This handles cases like `echo "hello world"` where the quote ends the arg.
-                // If starting a quote, we don't add the quote char to current_arg.
-                if !in_quote && !current_arg.is_empty() {
-                    args.push(current_arg.clone());
-                    current_arg.clear();
-                }
-            },
-            ' ' if !in_quote => {
-                // Space outside quotes, end of argument
-                if !current_arg.is_empty() {
-                    args.push(current_arg.clone());
-                    current_arg.clear();
-                }
+        let before_else = self.pos;
+        self.skip_newlines();
+        let else_branch = if self.peek_bare() == Some("else") {
+            self.pos += 1;
+            if self.peek_bare() == Some("if") {
+                let nested = self.if_statement()?;
+                end = nested.span;
+                Some(ElseBranch::If(Box::new(nested)))
+            } else {
+                let (block, block_end) = self.block("else")?;
+                end = block_end;
+                Some(ElseBranch::Block(block))
+            }
+        } else {
+            // The newlines separate this statement from the next one.
+            self.pos = before_else;
+            None
+        };
+
+        Ok(IfStatement { condition, then_block, else_branch, span: keyword.to(end) })
+    }
+
+    /// while := 'while' and_or block
+    fn while_loop(&mut self) -> Result<Statement, ParseError> {
+        let keyword = self.next_span();
+        self.pos += 1;
+        let condition = self.condition("while", keyword)?;
+        let (body, end) = self.block("while")?;
+        Ok(Statement::While(WhileLoop { condition, body, span: keyword.to(end) }))
+    }
+
+    /// for := 'for' name 'in' word* block
+    fn for_loop(&mut self) -> Result<Statement, ParseError> {
+        let keyword = self.next_span();
+        self.pos += 1;
+
+        let variable = match self.peek_bare() {
+            Some(name) if is_identifier(name) && !KEYWORDS.contains(&name) => name.to_string(),
+            _ => return Err(ParseError::new("Expected a variable name after 'for'.", self.next_span())),
+        };
+        self.pos += 1;
+        if self.peek_bare() != Some("in") {
+            return Err(ParseError::new(format!("Expected 'in' after 'for {}'.", variable), self.next_span()));
+        }
+        self.pos += 1;
+
+        let in_header = std::mem::replace(&mut self.in_header, true);
+        let mut items = Vec::new();
+        while let Some(word) = self.block_word() {
+            items.push(word);
+            self.pos += 1;
+        }
+        self.in_header = in_header;
+        let (body, end) = self.block("for")?;
+        Ok(Statement::For(ForLoop { variable, items, body, span: keyword.to(end) }))
+    }
+
+    /// fn := 'fn' name ('(' (param (',' param)*)? ')')? block
+    ///
+    /// The signature may be written with or without spaces, e.g.
+    /// `fn deploy(env, region)` or `fn deploy ( env region )`.
+    fn function(&mut self) -> Result<Statement, ParseError> {
+        let keyword = self.next_span();
+        self.pos += 1;
+
+        let in_header = std::mem::replace(&mut self.in_header, true);
+        let mut signature = String::new();
+        let mut signature_span = None::<Span>;
+        while let Some(word) = self.block_word() {
+            let Some(text) = word.as_bare() else {
+                self.in_header = in_header;
+                return Err(ParseError::new("Function signatures cannot be quoted or contain variables.", word.span));
+            };
+            signature.push_str(text);
+            signature.push(' ');
+            signature_span = Some(signature_span.map_or(word.span, |span| span.to(word.span)));
+            self.pos += 1;
+        }
+        self.in_header = in_header;
+
+        let Some(signature_span) = signature_span else {
+            return Err(ParseError::new("Expected a function name after 'fn'.", self.next_span()));
+        };
+        let (name, params) = parse_signature(&signature)
+            .map_err(|message| ParseError::new(message, signature_span))?;
+
+        let (body, end) = self.block("fn")?;
+        Ok(Statement::Function(FunctionDef { name, params, body, span: keyword.to(end) }))
+    }
+
+    /// Parses the condition of an `if` or `while`, which ends at the `{` of its block.
+    fn condition(&mut self, keyword: &str, keyword_span: Span) -> Result<CommandList, ParseError> {
+        if self.peek().is_none() || self.peek_bare() == Some("{") {
+            return Err(ParseError::new(format!("Missing condition after '{}'.", keyword), keyword_span));
+        }
+        let in_header = std::mem::replace(&mut self.in_header, true);
+        let condition = self.and_or();
+        self.in_header = in_header;
+        condition
+    }
+
+    /// block := newline* '{' list '}'
+    ///
+    /// Returns the block's statements and the span of its closing brace.
+    fn block(&mut self, keyword: &str) -> Result<(CommandList, Span), ParseError> {
+        self.skip_newlines();
+        if self.peek_bare() != Some("{") {
+            let message = format!("Expected '{{' to start the block of '{}'.", keyword);
+            return Err(match self.peek() {
+                Some(_) => ParseError::new(message, self.next_span()),
+                None => ParseError::incomplete(message, self.next_span()),
+            });
+        }
+        let open = self.next_span();
+        self.pos += 1;
+
+        let in_header = std::mem::replace(&mut self.in_header, false);
+        self.block_depth += 1;
+        let body = self.list(true);
+        self.block_depth -= 1;
+        self.in_header = in_header;
+        let body = body?;
+        if self.peek_bare() != Some("}") {
+            return Err(ParseError::incomplete("Unclosed block, expected '}'.", open));
+        }
+        let close = self.next_span();
+        self.pos += 1;
+        Ok((body, close))
+    }
+
+    /// The next token if it is a word that can be part of a command, i.e.
+    /// not a brace that opens or closes a block here.
+    fn block_word(&self) -> Option<Word> {
+        let Some(Token { kind: TokenKind::Word(word), .. }) = self.peek() else {
+            return None;
+        };
+        match word.as_bare() {
+            Some("{") if self.in_header => None,
+            Some("}") if self.block_depth > 0 => None,
+            _ => Some(word.clone()),
+        }
+    }
+
+    /// pipeline := command ('|' newline* command)*
+    fn pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let mut commands = vec![self.command()?];
+
+        while let Some(token) = self.peek() {
+            if token.kind != TokenKind::Pipe {
+                break;
+            }
+            let pipe_span = token.span;
+            self.pos += 1;
+            self.skip_newlines();
+            if self.peek().is_none() {
+                return Err(ParseError::incomplete("Missing command after '|'.", pipe_span));
+            }
+            if self.block_word().is_none() {
+                return Err(ParseError::new("Missing command after '|'.", pipe_span));
+            }
+            commands.push(self.command()?);
+        }
+
+        let span = commands[0].span.to(commands[commands.len() - 1].span);
+        Ok(Pipeline { commands, span })
+    }
+
+    /// command := word+
+    ///
+    /// A bare `{` after a condition and a bare `}` inside a block end the
+    /// command, as they delimit blocks.
+    fn command(&mut self) -> Result<SimpleCommand, ParseError> {
+        let mut words = Vec::new();
+        while let Some(word) = self.block_word() {
+            words.push(word);
+            self.pos += 1;
+        }
+
+        match (words.first(), words.last()) {
+            (Some(first), Some(last)) => {
+                let span = first.span.to(last.span);
+                Ok(SimpleCommand { words, span })
             },
             _ => {
-                current_arg.push(c);
-            }
+                let token = self.peek().expect("command() is only called before a token");
+                let message = match &token.kind {
+                    TokenKind::Word(word) => format!("Missing command before '{}'.", word.as_bare().unwrap_or("{")),
+                    kind => format!("Missing command before '{}'.", operator_text(kind)),
+                };
+                Err(ParseError::new(message, token.span))
+            },
+        }
+    }
+}
+
+/// Splits a function signature such as `deploy(env, region)` into the name
+/// and parameter names.
+fn parse_signature(signature: &str) -> Result<(String, Vec<String>), String> {
+    let (name, params) = match signature.split_once('(') {
+        Some((name, rest)) => {
+            let Some(params) = rest.trim_end().strip_suffix(')') else {
+                return Err("Expected ')' to close the parameter list.".to_string());
+            };
+            (name.trim(), Some(params))
+        },
+        None => (signature.trim(), None),
+    };
+
+    if !is_identifier(name) {
+        return Err(format!("Invalid function name '{}'.", name));
+    }
+    if KEYWORDS.contains(&name) {
+        return Err(format!("'{}' is a keyword and cannot be used as a function name.", name));
+    }
+
+    let mut names = Vec::new();
+    for param in params.unwrap_or_default().split(|c: char| c == ',' || c.is_whitespace()) {
+        if param.is_empty() {
+            continue;
+        }
+        if !is_identifier(param) {
+            return Err(format!("Invalid parameter name '{}'.", param));
+        }
+        if names.iter().any(|existing| existing == param) {
+            return Err(format!("Duplicate parameter '{}'.", param));
+        }
+        names.push(param.to_string());
+    }
+    Ok((name.to_string(), names))
+}
+
+/// Words with a meaning of their own at the start of a statement.
+const KEYWORDS: &[&str] = &["if", "else", "while", "for", "in", "fn"];
+
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn operator_text(kind: &TokenKind) -> &'static str {
+    match kind {
+        TokenKind::Pipe => "|",
+        TokenKind::AndIf => "&&",
+        TokenKind::OrIf => "||",
+        TokenKind::Semi => ";",
+        TokenKind::Newline => "new line",
+        TokenKind::Word(_) => "word",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::WordPart;
+
+    fn operators(input: &str) -> Vec<ListOperator> {
+        parse_command_list(input).unwrap().items.iter().map(|(op, _)| *op).collect()
+    }
+
+    fn pipeline(statement: &Statement) -> &Pipeline {
+        match statement {
+            Statement::Pipeline(pipeline) => pipeline,
+            other => panic!("expected a pipeline, got {:?}", other),
         }
     }
 
-    // Add the last argument if any
-    if !current_arg.is_empty() {
-        args.push(current_arg);
+    #[test]
+    fn lists_record_how_each_statement_is_joined() {
+        use ListOperator::{And, Or, Sequence};
+        assert_eq!(operators("a && b || c; d"), vec![Sequence, And, Or, Sequence]);
+        assert_eq!(operators("a\nb;c"), vec![Sequence, Sequence, Sequence]);
+    }
+
+    #[test]
+    fn blank_lines_comments_and_trailing_separators_add_no_statements() {
+        assert!(parse_command_list("").unwrap().items.is_empty());
+        assert!(parse_command_list("  # just a comment").unwrap().items.is_empty());
+        assert_eq!(parse_command_list("\n\na;\n\nb;").unwrap().items.len(), 2);
+    }
+
+    #[test]
+    fn pipelines_hold_their_commands_and_spans() {
+        let list = parse_command_list("echo hi | count ; ls").unwrap();
+        let first = pipeline(&list.items[0].1);
+        assert_eq!(first.span, Span::new(0, 15));
+        assert_eq!(first.commands.len(), 2);
+        assert_eq!(first.commands[1].span, Span::new(10, 15));
+        assert_eq!(first.commands[0].words[1].parts, vec![WordPart::Literal("hi".to_string())]);
+        assert_eq!(list.items[1].1.span(), Span::new(18, 20));
     }
 
-    if args.is_empty() {
-        anyhow::bail!("Empty command in pipeline.");
+    #[test]
+    fn misplaced_operators_are_errors_at_the_operator() {
+        let cases = [("| echo", Span::new(0, 1)), ("echo a | | b", Span::new(7, 8)), ("a && && b", Span::new(5, 7))];
+        for (input, span) in cases {
+            let error = parse_command_list(input).unwrap_err();
+            assert_eq!(error.span, span, "for {:?}: {}", input, error);
+            assert!(!error.incomplete);
+        }
+    }
+
+    #[test]
+    fn a_trailing_operator_asks_for_more_input() {
+        for input in ["echo a |", "echo a &&", "echo a ||"] {
+            let error = parse_command_list(input).unwrap_err();
+            assert!(error.incomplete, "for {:?}: {}", input, error);
+        }
     }
 
-    let command_name = args[0].clone();
-    let raw_args = args[1..].to_vec();
+    fn single(input: &str) -> Statement {
+        let mut list = parse_command_list(input).unwrap();
+        assert_eq!(list.items.len(), 1, "expected one statement in {:?}", input);
+        list.items.remove(0).1
+    }
+
+    fn names(list: &CommandList) -> Vec<String> {
+        list.items
+            .iter()
+            .map(|(_, statement)| match &pipeline(statement).commands[0].words[0].parts[..] {
+                [WordPart::Literal(name)] => name.clone(),
+                parts => panic!("expected a bare command name, got {:?}", parts),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn if_with_else_if_and_else_chains() {
+        let Statement::If(statement) = single("if a && b { c } else if d { e; f } else { g }") else {
+            panic!("expected an if statement");
+        };
+        assert_eq!(names(&statement.condition), vec!["a", "b"]);
+        assert_eq!(statement.condition.items[1].0, ListOperator::And);
+        assert_eq!(names(&statement.then_block), vec!["c"]);
+        assert_eq!(statement.span, Span::new(0, 45));
+
+        let Some(ElseBranch::If(nested)) = &statement.else_branch else {
+            panic!("expected an else-if branch");
+        };
+        assert_eq!(names(&nested.then_block), vec!["e", "f"]);
+        let Some(ElseBranch::Block(block)) = &nested.else_branch else {
+            panic!("expected an else block");
+        };
+        assert_eq!(names(block), vec!["g"]);
+    }
+
+    #[test]
+    fn blocks_may_span_lines() {
+        let input = "if a\n{\n  b\n  c\n}\nelse\n{\n  d\n}\necho after";
+        let list = parse_command_list(input).unwrap();
+        assert_eq!(list.items.len(), 2);
+        let Statement::If(statement) = &list.items[0].1 else {
+            panic!("expected an if statement");
+        };
+        assert_eq!(names(&statement.then_block), vec!["b", "c"]);
+        assert!(matches!(&statement.else_branch, Some(ElseBranch::Block(block)) if names(block) == ["d"]));
+    }
 
-    let mut resolved_args = Vec::new();
-    for arg in raw_args {
-        let resolved_arg = variable_resolver::resolve_variables(&arg, var_manager)?;
-        resolved_args.push(resolved_arg);
+    #[test]
+    fn if_without_else_leaves_the_next_line_alone() {
+        let list = parse_command_list("if a { b }\nc").unwrap();
+        assert_eq!(list.items.len(), 2);
+        assert!(matches!(&list.items[0].1, Statement::If(statement) if statement.else_branch.is_none()));
     }
 
-    Ok(PipelineCommand {
-        name: command_name,
-        args: resolved_args,
-    })
+    #[test]
+    fn while_and_for_loops() {
+        let Statement::While(statement) = single("while a || b { c }") else {
+            panic!("expected a while loop");
+        };
+        assert_eq!(names(&statement.condition), vec!["a", "b"]);
+        assert_eq!(names(&statement.body), vec!["c"]);
+
+        let Statement::For(statement) = single("for x in 1 'two' {y} { echo {x} }") else {
+            panic!("expected a for loop");
+        };
+        assert_eq!(statement.variable, "x");
+        assert_eq!(statement.items.len(), 3);
+        assert_eq!(statement.items[2].parts, vec![WordPart::Variable("y".to_string())]);
+        assert_eq!(names(&statement.body), vec!["echo"]);
+
+        let Statement::For(statement) = single("for x in { }") else {
+            panic!("expected a for loop");
+        };
+        assert!(statement.items.is_empty() && statement.body.items.is_empty());
+    }
+
+    #[test]
+    fn blocks_nest() {
+        let Statement::While(outer) = single("while a { for x in 1 2 { if b { c } } }") else {
+            panic!("expected a while loop");
+        };
+        let Statement::For(inner) = &outer.body.items[0].1 else {
+            panic!("expected a for loop");
+        };
+        assert!(matches!(&inner.body.items[0].1, Statement::If(_)));
+    }
+
+    #[test]
+    fn functions_take_their_signature_with_or_without_spaces() {
+        for input in ["fn deploy(env, region) { a }", "fn deploy ( env region ) { a }"] {
+            let Statement::Function(function) = single(input) else {
+                panic!("expected a function in {:?}", input);
+            };
+            assert_eq!(function.name, "deploy");
+            assert_eq!(function.params, vec!["env", "region"]);
+        }
+        let Statement::Function(function) = single("fn hello { a }") else {
+            panic!("expected a function");
+        };
+        assert!(function.params.is_empty());
+    }
+
+    #[test]
+    fn quoted_keywords_and_braces_are_plain_words() {
+        assert!(matches!(single("'if' a"), Statement::Pipeline(_)));
+        assert!(matches!(single("echo } {"), Statement::Pipeline(_)));
+    }
+
+    #[test]
+    fn unfinished_blocks_ask_for_more_input() {
+        for input in ["if a {", "if a {\n b", "while a", "for x in 1 2", "if a { b } else", "fn f {\n if b {\n c\n }"] {
+            let error = parse_command_list(input).unwrap_err();
+            assert!(error.incomplete, "for {:?}: {}", input, error);
+        }
+        let error = parse_command_list("if a {\n b").unwrap_err();
+        assert_eq!(error.message, "Unclosed block, expected '}'.");
+        assert_eq!(error.span, Span::new(5, 6));
+    }
+
+    #[test]
+    fn malformed_blocks_are_errors() {
+        let cases = [
+            ("if { a }", "Missing condition after 'if'."),
+            ("for a-b in c { d }", "Expected a variable name after 'for'."),
+            ("for x of a { b }", "Expected 'in' after 'for x'."),
+            ("fn if { a }", "'if' is a keyword and cannot be used as a function name."),
+            ("fn f(a, a) { b }", "Duplicate parameter 'a'."),
+            ("fn f(a { b }", "Expected ')' to close the parameter list."),
+            ("if a { b } c", "Expected ';' or a new line before this."),
+            ("}", "Unexpected '}' without an open block."),
+        ];
+        for (input, message) in cases {
+            let error = parse_command_list(input).unwrap_err();
+            assert_eq!(error.message, message, "for {:?}", input);
+            assert!(!error.incomplete, "for {:?}", input);
+        }
+    }
 }
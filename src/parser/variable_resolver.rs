@@ -2,57 +2,454 @@
 // Handles dynamic variable resolution within command arguments.
 
 use crate::core::variables::VariableManager;
-use anyhow::Result;
+use crate::util;
+use anyhow::{Result, Context};
 use log::{debug, warn};
+use serde_json::Value as JsonValue;
 
-/// Resolves variables in a given string.
-/// Variables are identified by `{variable_name}` syntax.
+/// What to do with a variable's value once it has been looked up.
+#[derive(Debug, PartialEq)]
+enum Modifier<'a> {
+    /// `{name}`
+    None,
+    /// `{#name}` - the length of the value in characters, or the number of
+    /// elements of an array or object.
+    Length,
+    /// `{name:-default}` - the default when unset or empty.
+    Default(&'a str),
+    /// `{name:=default}` - like `Default`, but also stores the default.
+    Assign(&'a str),
+    /// `{name:?message}` - fails with `message` when unset or empty.
+    Required(&'a str),
+    /// `{name:offset}` / `{name:offset:length}` - a substring, in characters.
+    Substring { offset: i64, length: Option<i64> },
+    /// `{name/pattern/replacement}` (first match) or `{name//pattern/replacement}` (all).
+    Replace { pattern: &'a str, replacement: &'a str, all: bool },
+}
+
+/// Returns `true` if `text` (the input right after a `{`) starts a variable
+/// reference: a name, or `#` followed by a name.
+pub fn starts_variable(text: &str) -> bool {
+    let text = text.strip_prefix('#').unwrap_or(text);
+    text.chars().next().is_some_and(|c| is_name_char(c) || c == '@')
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Prefix of references to the process environment, e.g. `{env:HOME}`.
+const ENV_PREFIX: &str = "env:";
+
+/// Finds where the variable name at the start of `expr` ends. Besides regular
+/// names this accepts `@`, the list of a script's positional arguments, and
+/// `env:NAME` for environment variables.
+fn name_end(expr: &str) -> usize {
+    if expr.starts_with('@') {
+        return 1;
+    }
+    if let Some(env_name) = expr.strip_prefix(ENV_PREFIX) {
+        if env_name.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+            return ENV_PREFIX.len() + name_end(env_name);
+        }
+    }
+    expr.find(|c: char| !is_name_char(c)).unwrap_or(expr.len())
+}
+
+/// Finds where the field path at the start of `rest` (right after a
+/// variable name) ends: any number of `.field` and `[index]` segments.
+fn path_end(rest: &str) -> usize {
+    let mut end = 0;
+    loop {
+        let tail = &rest[end..];
+        let segment = if let Some(field) = tail.strip_prefix('.') {
+            match name_end(field) {
+                0 => 0,
+                len => len + 1,
+            }
+        } else if let Some(index) = tail.strip_prefix('[') {
+            let digits = index.find(|c: char| !c.is_ascii_digit()).unwrap_or(index.len());
+            if digits > 0 && index[digits..].starts_with(']') { digits + 2 } else { 0 }
+        } else {
+            0
+        };
+        if segment == 0 {
+            return end;
+        }
+        end += segment;
+    }
+}
+
+/// Resolves a single variable reference.
+/// The lexer recognises references by their `{...}` syntax and passes the
+/// text between the braces here. Besides a bare `{name}`, the following
+/// bash-style forms are supported:
+///
+/// * `{name:-default}` - `default` if `name` is unset or empty.
+/// * `{name:=default}` - as above, and `name` is set to `default`.
+/// * `{name:?message}` - fails with `message` if `name` is unset or empty.
+/// * `{#name}` - the length of the value.
+/// * `{name:offset}`, `{name:offset:length}` - a substring. A negative offset
+///   (written with a space, `{name: -3}`) counts from the end, as does a
+///   negative length.
+/// * `{name/pattern/replacement}`, `{name//pattern/replacement}` - replaces
+///   the first or every occurrence of `pattern`.
+///
+/// `{env:NAME}` reads the environment that `exec` children see: the process
+/// environment, overridden by exported shell variables. Modifiers work as
+/// usual, e.g. `{env:EDITOR:-vi}`.
+///
+/// The name may be followed by a path into a structured value, e.g.
+/// `{files[0].name}` or `{cfg.db.host}`; a missing field counts as unset.
+/// A variable whose name itself contains dots (`{ns.key}`) takes precedence.
+/// Values that are not strings expand to compact JSON.
+///
+/// Defaults, messages and replacements may themselves contain `{...}` references.
 ///
 /// # Arguments
-/// * `input` - The string potentially containing variables.
+/// * `expr` - The reference, without the surrounding braces.
 /// * `var_manager` - A reference to the `VariableManager` to look up variable values.
 ///
 /// # Returns
-/// A `Result` containing the string with all variables resolved, or an error if
-/// a variable cannot be found.
-pub fn resolve_variables(input: &str, var_manager: &VariableManager) -> Result<String> {
-    let mut resolved_string = String::new();
-    let mut chars = input.chars().peekable();
-
-    while let Some(c) = chars.next() {
-        if c == '{' {
-            // Check if it's the start of a variable
-            let mut var_name = String::new();
-            let mut in_variable = false;
-            while let Some(&next_c) = chars.peek() {
-                if next_c == '}' {
-                    chars.next(); // Consume '}'
-                    in_variable = true;
-                    break;
-                }
-                var_name.push(chars.next().unwrap());
+/// A `Result` containing the resolved value, or an error if the variable
+/// cannot be found or the expression is malformed.
+pub fn resolve_variable(expr: &str, var_manager: &VariableManager) -> Result<String> {
+    let (name, path, modifier) = parse_expression(expr)?;
+    let json = lookup(name, path, var_manager);
+    let value = json.as_ref().map(util::format_value);
+
+    let is_unset_or_empty = value.as_deref().is_none_or(str::is_empty);
+    let resolved = match modifier {
+        Modifier::Default(default) if is_unset_or_empty => expand_nested(default, var_manager)?,
+        Modifier::Assign(_) if !path.is_empty() => {
+            anyhow::bail!("Cannot assign a default to a field: {{{}}}", expr);
+        },
+        Modifier::Assign(_) if name.starts_with(ENV_PREFIX) => {
+            anyhow::bail!("Cannot assign a default to an environment variable: {{{}}}", expr);
+        },
+        Modifier::Assign(default) if is_unset_or_empty => {
+            let default = expand_nested(default, var_manager)?;
+            var_manager.set(name.to_string(), default.clone());
+            default
+        },
+        Modifier::Required(message) if is_unset_or_empty => {
+            let message = expand_nested(message, var_manager)?;
+            let message = if message.is_empty() { "parameter not set".to_string() } else { message };
+            anyhow::bail!("{}: {}", name, message);
+        },
+        Modifier::Default(_) | Modifier::Assign(_) | Modifier::Required(_) => value.unwrap_or_default(),
+        _ => {
+            let Some(value) = value else {
+                warn!("Unresolved variable: {{{}{}}}", name, path);
+                // For a production-grade shell, returning an error is safer than
+                // silently substituting an empty string.
+                anyhow::bail!("Variable not found: {{{}{}}}", name, path);
+            };
+            match modifier {
+                Modifier::Length => match &json {
+                    Some(JsonValue::Array(items)) => items.len().to_string(),
+                    Some(JsonValue::Object(fields)) => fields.len().to_string(),
+                    _ => value.chars().count().to_string(),
+                },
+                Modifier::Substring { offset, length } => substring(&value, offset, length),
+                Modifier::Replace { pattern, replacement, all } => {
+                    let pattern = expand_nested(pattern, var_manager)?;
+                    let replacement = expand_nested(replacement, var_manager)?;
+                    if pattern.is_empty() {
+                        value
+                    } else if all {
+                        value.replace(&pattern, &replacement)
+                    } else {
+                        value.replacen(&pattern, &replacement, 1)
+                    }
+                },
+                _ => value,
             }
+        },
+    };
+
+    debug!("Resolved variable: {{{}}} -> {}", expr, resolved);
+    Ok(resolved)
+}
+
+/// Resolves a reference to the variable's JSON value, keeping its type when
+/// there is no modifier (`{files}`, `{cfg.db}`). With a modifier the result is
+/// the expanded text. Used where structure matters, such as `for` items.
+pub fn resolve_value(expr: &str, var_manager: &VariableManager) -> Result<JsonValue> {
+    let (name, path, modifier) = parse_expression(expr)?;
+    if modifier != Modifier::None {
+        return resolve_variable(expr, var_manager).map(JsonValue::String);
+    }
+    lookup(name, path, var_manager)
+        .with_context(|| format!("Variable not found: {{{}{}}}", name, path))
+}
+
+/// Looks up a variable and the field selected by `path` (empty for the whole value).
+/// Looks up a plain `{name}` or `{name.path}` reference (including
+/// `{env:NAME}`) without expanding anything or logging. Returns `None` if the
+/// variable is unset or `expr` has a modifier, so it never has side effects.
+pub fn peek(expr: &str, var_manager: &VariableManager) -> Option<JsonValue> {
+    let (name, path) = expr.split_at(name_end(expr));
+    if name.is_empty() || path_end(path) != path.len() {
+        return None;
+    }
+    lookup(name, path, var_manager)
+}
+
+fn lookup(name: &str, path: &str, var_manager: &VariableManager) -> Option<JsonValue> {
+    if let Some(env_name) = name.strip_prefix(ENV_PREFIX) {
+        return var_manager.env_var(env_name).map(JsonValue::String);
+    }
+    // Names may themselves contain dots (e.g. keys loaded with
+    // `load-memory --prefix ns.`), so the longest name that is set wins and
+    // the rest of the expression is the field path.
+    let full = format!("{}{}", name, path);
+    let splits = path.match_indices(['.', '[']).map(|(i, _)| i).chain([path.len()]);
+    let (value, rest) = splits
+        .rev()
+        .find_map(|i| Some((var_manager.get_value(&full[..name.len() + i])?, &path[i..])))?;
+    if rest.is_empty() {
+        return Some(value);
+    }
+    util::select_path(&value, rest).cloned()
+}
+
+/// Splits a variable expression into the variable name, its field path
+/// (possibly empty) and its modifier.
+fn parse_expression(expr: &str) -> Result<(&str, &str, Modifier<'_>)> {
+    if let Some(reference) = expr.strip_prefix('#') {
+        let name_len = name_end(reference);
+        let (name, path) = reference.split_at(name_len);
+        if name.is_empty() || path_end(path) != path.len() {
+            anyhow::bail!("Invalid length expression: {{{}}}", expr);
+        }
+        return Ok((name, path, Modifier::Length));
+    }
+
+    let (name, rest) = expr.split_at(name_end(expr));
+    if name.is_empty() {
+        anyhow::bail!("Invalid variable expression: {{{}}}", expr);
+    }
+    let (path, rest) = rest.split_at(path_end(rest));
+
+    let modifier = if rest.is_empty() {
+        Modifier::None
+    } else if let Some(default) = rest.strip_prefix(":-") {
+        Modifier::Default(default)
+    } else if let Some(default) = rest.strip_prefix(":=") {
+        Modifier::Assign(default)
+    } else if let Some(message) = rest.strip_prefix(":?") {
+        Modifier::Required(message)
+    } else if let Some(range) = rest.strip_prefix(':') {
+        let (offset, length) = match range.split_once(':') {
+            Some((offset, length)) => (offset, Some(length)),
+            None => (range, None),
+        };
+        let offset = offset.trim().parse::<i64>()
+            .with_context(|| format!("Invalid substring offset in {{{}}}", expr))?;
+        let length = length
+            .map(|l| l.trim().parse::<i64>())
+            .transpose()
+            .with_context(|| format!("Invalid substring length in {{{}}}", expr))?;
+        Modifier::Substring { offset, length }
+    } else if let Some(spec) = rest.strip_prefix('/') {
+        let (all, spec) = match spec.strip_prefix('/') {
+            Some(spec) => (true, spec),
+            None => (false, spec),
+        };
+        let (pattern, replacement) = spec.split_once('/').unwrap_or((spec, ""));
+        Modifier::Replace { pattern, replacement, all }
+    } else {
+        anyhow::bail!("Invalid variable expression: {{{}}}", expr);
+    };
+
+    Ok((name, path, modifier))
+}
+
+/// Takes a substring by character positions, bash style: a negative offset
+/// counts from the end, and a negative length stops that many characters
+/// before the end.
+fn substring(value: &str, offset: i64, length: Option<i64>) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    let len = chars.len() as i64;
+
+    // Saturating, so offsets and lengths near the limits of i64 clamp to the
+    // ends of the value instead of overflowing.
+    let start = if offset < 0 { len.saturating_add(offset).max(0) } else { offset.min(len) };
+    let end = match length {
+        Some(l) if l < 0 => len.saturating_add(l).max(start),
+        Some(l) => start.saturating_add(l).min(len),
+        None => len,
+    };
+
+    chars[start as usize..end as usize].iter().collect()
+}
+
+/// Expands `{...}` references inside the text of a modifier.
+fn expand_nested(text: &str, var_manager: &VariableManager) -> Result<String> {
+    let mut expanded = String::new();
+    let mut rest = text;
 
-            if in_variable {
-                // Look up the variable in the store
-                if let Some(value) = var_manager.get(&var_name) {
-                    resolved_string.push_str(&value);
-                    debug!("Resolved variable: {} -> {}", var_name, value);
-                } else {
-                    warn!("Unresolved variable: {{{}}}", var_name);
-                    // If a variable is not found, return an error or keep it as is.
-                    // For a production-grade shell, returning an error is safer.
-                    anyhow::bail!("Variable not found: {{{}}}", var_name);
-                }
-            } else {
-                // Not a variable, just a literal '{'
-                resolved_string.push(c);
-                resolved_string.push_str(&var_name);
+    while let Some(open) = rest.find('{') {
+        expanded.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        if !starts_variable(after) {
+            expanded.push('{');
+            rest = after;
+            continue;
+        }
+
+        let mut depth = 1;
+        let close = after.char_indices().find_map(|(i, c)| {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                _ => {},
             }
-        } else {
-            resolved_string.push(c);
+            (depth == 0).then_some(i)
+        });
+        let Some(close) = close else {
+            anyhow::bail!("Unclosed variable reference in '{}'", text);
+        };
+
+        expanded.push_str(&resolve_variable(&after[..close], var_manager)?);
+        rest = &after[close + 1..];
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn substring_clamps_extreme_offsets_and_lengths() {
+        assert_eq!(substring("hello", 1, Some(i64::MAX)), "ello");
+        assert_eq!(substring("hello", i64::MAX, Some(i64::MAX)), "");
+        assert_eq!(substring("hello", i64::MIN, None), "hello");
+        assert_eq!(substring("hello", 0, Some(i64::MIN)), "");
+        assert_eq!(substring("hello", i64::MIN, Some(i64::MIN)), "");
+    }
+
+    #[test]
+    fn substring_reference_with_huge_length_does_not_panic() {
+        let vars = VariableManager::new();
+        vars.set("x".to_string(), "hello".to_string());
+        assert_eq!(resolve_variable("x:1:9223372036854775807", &vars).unwrap(), "ello");
+    }
+
+    fn vars(pairs: &[(&str, JsonValue)]) -> VariableManager {
+        let vars = VariableManager::new();
+        for (name, value) in pairs {
+            vars.set_value(name.to_string(), value.clone());
         }
+        vars
+    }
+
+    fn resolve(expr: &str, vars: &VariableManager) -> String {
+        resolve_variable(expr, vars).unwrap()
     }
 
-    Ok(resolved_string)
+    fn error(expr: &str, vars: &VariableManager) -> String {
+        resolve_variable(expr, vars).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn defaults_apply_when_unset_or_empty() {
+        let vars = vars(&[("set", json!("value")), ("empty", json!(""))]);
+        assert_eq!(resolve("set:-fallback", &vars), "value");
+        assert_eq!(resolve("empty:-fallback", &vars), "fallback");
+        assert_eq!(resolve("missing:-fallback", &vars), "fallback");
+        assert_eq!(resolve("missing:-", &vars), "");
+    }
+
+    #[test]
+    fn assign_stores_the_default() {
+        let vars = vars(&[("set", json!("value"))]);
+        assert_eq!(resolve("set:=other", &vars), "value");
+        assert_eq!(resolve("missing:=stored", &vars), "stored");
+        assert_eq!(vars.get("missing").as_deref(), Some("stored"));
+        assert_eq!(vars.get("set").as_deref(), Some("value"));
+    }
+
+    #[test]
+    fn assign_rejects_fields_and_environment_variables() {
+        let vars = vars(&[("cfg", json!({ "port": "" }))]);
+        assert_eq!(error("cfg.port:=80", &vars), "Cannot assign a default to a field: {cfg.port:=80}");
+        assert_eq!(
+            error("env:SHELLCE_TEST_UNSET_VAR:=x", &vars),
+            "Cannot assign a default to an environment variable: {env:SHELLCE_TEST_UNSET_VAR:=x}"
+        );
+    }
+
+    #[test]
+    fn required_fails_with_the_message() {
+        let vars = vars(&[("set", json!("value")), ("empty", json!(""))]);
+        assert_eq!(resolve("set:?must be set", &vars), "value");
+        assert_eq!(error("empty:?must be set", &vars), "empty: must be set");
+        assert_eq!(error("missing:?", &vars), "missing: parameter not set");
+    }
+
+    #[test]
+    fn unknown_variables_and_bad_expressions_are_errors() {
+        let vars = vars(&[("x", json!("hello"))]);
+        assert_eq!(error("missing", &vars), "Variable not found: {missing}");
+        assert_eq!(error("x:a", &vars), "Invalid substring offset in {x:a}");
+        assert_eq!(error("x:1:b", &vars), "Invalid substring length in {x:1:b}");
+        assert_eq!(error("x!", &vars), "Invalid variable expression: {x!}");
+        assert_eq!(error("#x:-1", &vars), "Invalid length expression: {#x:-1}");
+    }
+
+    #[test]
+    fn length_counts_characters_or_elements() {
+        let vars = vars(&[("word", json!("héllo")), ("list", json!([1, 2, 3])), ("map", json!({ "a": 1 }))]);
+        assert_eq!(resolve("#word", &vars), "5");
+        assert_eq!(resolve("#list", &vars), "3");
+        assert_eq!(resolve("#map", &vars), "1");
+    }
+
+    #[test]
+    fn substrings_count_characters_from_either_end() {
+        let vars = vars(&[("x", json!("héllo world"))]);
+        assert_eq!(resolve("x:6", &vars), "world");
+        assert_eq!(resolve("x:1:4", &vars), "éllo");
+        assert_eq!(resolve("x: -5", &vars), "world");
+        assert_eq!(resolve("x: -5:2", &vars), "wo");
+        assert_eq!(resolve("x:0:-6", &vars), "héllo");
+        assert_eq!(resolve("x:20", &vars), "");
+        assert_eq!(resolve("x:8:-6", &vars), "");
+    }
+
+    #[test]
+    fn replacements_change_the_first_or_every_match() {
+        let vars = vars(&[("x", json!("a-b-c"))]);
+        assert_eq!(resolve("x/-/+", &vars), "a+b-c");
+        assert_eq!(resolve("x//-/+", &vars), "a+b+c");
+        assert_eq!(resolve("x//-", &vars), "abc");
+        assert_eq!(resolve("x/z/y", &vars), "a-b-c");
+        assert_eq!(resolve("x//", &vars), "a-b-c");
+    }
+
+    #[test]
+    fn modifier_text_may_contain_references() {
+        let vars = vars(&[("x", json!("a-b")), ("sep", json!("-")), ("with", json!("+")), ("fallback", json!("fb"))]);
+        assert_eq!(resolve("missing:-{fallback}", &vars), "fb");
+        assert_eq!(resolve("missing:-{other:-{fallback}!}", &vars), "fb!");
+        assert_eq!(resolve("x//{sep}/{with}", &vars), "a+b");
+        assert_eq!(error("missing:?{fallback} is required", &vars), "missing: fb is required");
+        assert_eq!(resolve("missing:-{ literal }", &vars), "{ literal }");
+        assert_eq!(error("missing:-{nope}", &vars), "Variable not found: {nope}");
+    }
+
+    #[test]
+    fn fields_of_structured_values_are_selected_by_path() {
+        let vars = vars(&[("cfg", json!({ "db": { "hosts": ["a", "b"] } })), ("ns.key", json!("dotted"))]);
+        assert_eq!(resolve("cfg.db.hosts[1]", &vars), "b");
+        assert_eq!(resolve("cfg.db.port:-5432", &vars), "5432");
+        assert_eq!(resolve("#cfg.db.hosts", &vars), "2");
+        assert_eq!(resolve("ns.key", &vars), "dotted");
+    }
 }
@@ -0,0 +1,168 @@
+// src/storage/backend.rs
+// The `StorageBackend` trait and the text-file (JSON and TOML) backends.
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use log::error;
+use serde_json::Value as JsonValue;
+use std::path::Path;
+
+use crate::core::types::VariableStore;
+use super::{crypto, format, write_atomic};
+
+/// A way of persisting a `VariableStore` to a file.
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    /// The name used for `storage.backend` in the config.
+    fn name(&self) -> &'static str;
+
+    /// The file extension of files this backend writes, without the dot.
+    fn extension(&self) -> &'static str;
+
+    /// Saves `store` to `path`, encrypting it if a passphrase is given.
+    async fn save(&self, store: &VariableStore, path: &Path, passphrase: Option<&str>) -> Result<()>;
+
+    /// Loads the whole store from `path`.
+    async fn load(&self, path: &Path, passphrase: Option<&str>) -> Result<VariableStore>;
+
+    /// Loads only the given keys from `path`. Backends that can read single
+    /// entries override this to avoid loading the whole store.
+    async fn load_keys(&self, path: &Path, keys: &[String], passphrase: Option<&str>) -> Result<VariableStore> {
+        let store = self.load(path, passphrase).await?;
+        Ok(store.select(keys))
+    }
+
+    /// Returns `true` if the file at `path` is encrypted and `load` needs a passphrase.
+    async fn is_encrypted(&self, _path: &Path) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+/// A memory file stored as a single text document: the versioned header
+/// (see `format`) in some syntax, optionally encrypted as a whole.
+trait TextSyntax {
+    fn serialize(document: &JsonValue) -> Result<String>;
+    fn deserialize(text: &str) -> Result<JsonValue>;
+}
+
+async fn save_text<S: TextSyntax>(store: &VariableStore, path: &Path, passphrase: Option<&str>) -> Result<()> {
+    let text = S::serialize(&format::to_document(store)?)?;
+    let contents = match passphrase {
+        Some(passphrase) => {
+            // Key derivation is deliberately slow; keep it off the async workers.
+            let passphrase = passphrase.to_string();
+            tokio::task::spawn_blocking(move || crypto::encrypt(text.as_bytes(), &passphrase))
+                .await
+                .context("Encryption task failed")??
+        }
+        None => text,
+    };
+    write_atomic(path, contents.as_bytes())
+        .await
+        .with_context(|| format!("Failed to write memory file: {:?}", path))
+}
+
+async fn load_text<S: TextSyntax>(path: &Path, passphrase: Option<&str>) -> Result<VariableStore> {
+    // Use tokio::fs for async file operations
+    let mut text = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("Failed to read memory file: {:?}", path))?;
+
+    if crypto::is_encrypted(&text) {
+        let Some(passphrase) = passphrase else {
+            error!("Memory file {:?} is encrypted and no passphrase was given", path);
+            bail!("Memory file {:?} is encrypted; a passphrase is required", path);
+        };
+        let passphrase = passphrase.to_string();
+        let plaintext = tokio::task::spawn_blocking(move || crypto::decrypt(&text, &passphrase))
+            .await
+            .context("Decryption task failed")??;
+        text = String::from_utf8(plaintext).context("Decrypted memory file is not valid UTF-8")?;
+    }
+
+    S::deserialize(&text)
+        .and_then(format::from_document)
+        .with_context(|| format!("Failed to parse memory file: {:?}", path))
+}
+
+async fn is_encrypted_text(path: &Path) -> Result<bool> {
+    let text = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("Failed to read memory file: {:?}", path))?;
+    Ok(crypto::is_encrypted(&text))
+}
+
+/// Pretty-printed JSON; the default backend.
+pub struct JsonBackend;
+
+impl TextSyntax for JsonBackend {
+    fn serialize(document: &JsonValue) -> Result<String> {
+        serde_json::to_string_pretty(document).context("Failed to serialize variable store to JSON")
+    }
+
+    fn deserialize(text: &str) -> Result<JsonValue> {
+        serde_json::from_str(text).context("Invalid JSON")
+    }
+}
+
+#[async_trait]
+impl StorageBackend for JsonBackend {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn extension(&self) -> &'static str {
+        "json"
+    }
+
+    async fn save(&self, store: &VariableStore, path: &Path, passphrase: Option<&str>) -> Result<()> {
+        save_text::<Self>(store, path, passphrase).await
+    }
+
+    async fn load(&self, path: &Path, passphrase: Option<&str>) -> Result<VariableStore> {
+        load_text::<Self>(path, passphrase).await
+    }
+
+    async fn is_encrypted(&self, path: &Path) -> Result<bool> {
+        is_encrypted_text(path).await
+    }
+}
+
+/// TOML, for memory files meant to be read and edited by hand. TOML has no
+/// null, so stores holding `null` values can't be saved with it.
+pub struct TomlBackend;
+
+impl TextSyntax for TomlBackend {
+    fn serialize(document: &JsonValue) -> Result<String> {
+        toml::to_string_pretty(document)
+            .context("Failed to serialize variable store to TOML (TOML cannot hold null values)")
+    }
+
+    fn deserialize(text: &str) -> Result<JsonValue> {
+        let value: toml::Value = toml::from_str(text).context("Invalid TOML")?;
+        serde_json::to_value(value).context("Invalid TOML")
+    }
+}
+
+#[async_trait]
+impl StorageBackend for TomlBackend {
+    fn name(&self) -> &'static str {
+        "toml"
+    }
+
+    fn extension(&self) -> &'static str {
+        "toml"
+    }
+
+    async fn save(&self, store: &VariableStore, path: &Path, passphrase: Option<&str>) -> Result<()> {
+        save_text::<Self>(store, path, passphrase).await
+    }
+
+    async fn load(&self, path: &Path, passphrase: Option<&str>) -> Result<VariableStore> {
+        load_text::<Self>(path, passphrase).await
+    }
+
+    async fn is_encrypted(&self, path: &Path) -> Result<bool> {
+        is_encrypted_text(path).await
+    }
+}
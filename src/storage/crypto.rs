@@ -0,0 +1,252 @@
+// src/storage/crypto.rs
+// Passphrase-based encryption of memory files.
+
+use anyhow::{anyhow, bail, Context, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+
+/// Version of the encrypted file format, stored in every file.
+const FORMAT_VERSION: u32 = 1;
+const CIPHER: &str = "xchacha20poly1305";
+const KDF: &str = "argon2id";
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+
+/// An encrypted memory file. The plaintext is the regular JSON memory file;
+/// the key is derived from a passphrase with Argon2id and the data sealed
+/// with XChaCha20-Poly1305. Every header field is authenticated along with
+/// the ciphertext, so any modification is detected on load.
+#[derive(Debug, Serialize, Deserialize)]
+struct EncryptedFile {
+    shellce_encrypted: u32,
+    cipher: String,
+    kdf: KdfParams,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct KdfParams {
+    algorithm: String,
+    salt: String,
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+}
+
+impl KdfParams {
+    /// Rejects parameters above the ones `encrypt` writes. The header is only
+    /// authenticated after the key is derived, so without a limit a crafted
+    /// file could make loading it use any amount of memory and time.
+    fn check_limits(&self) -> Result<()> {
+        let defaults = Params::default();
+        if self.memory_kib > defaults.m_cost()
+            || self.iterations > defaults.t_cost()
+            || self.parallelism > defaults.p_cost()
+        {
+            bail!(
+                "Encrypted memory file asks for key derivation parameters above the limit \
+                 (memory {} KiB, {} iterations, parallelism {})",
+                self.memory_kib,
+                self.iterations,
+                self.parallelism
+            );
+        }
+        Ok(())
+    }
+
+    fn derive_key(&self, passphrase: &str) -> Result<Key> {
+        let salt = BASE64.decode(&self.salt).context("Invalid salt")?;
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, Some(KEY_LEN))
+            .map_err(|e| anyhow!("Invalid key derivation parameters: {}", e))?;
+        let mut key = Key::default();
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
+        Ok(key)
+    }
+}
+
+impl EncryptedFile {
+    /// The header fields, bound to the ciphertext as associated data.
+    fn associated_data(&self) -> Vec<u8> {
+        format!(
+            "shellce-memory:{}:{}:{}:{}:{}:{}:{}",
+            self.shellce_encrypted,
+            self.cipher,
+            self.kdf.algorithm,
+            self.kdf.salt,
+            self.kdf.memory_kib,
+            self.kdf.iterations,
+            self.kdf.parallelism
+        )
+        .into_bytes()
+    }
+}
+
+/// Returns `true` if `contents` is an encrypted memory file.
+pub fn is_encrypted(contents: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(contents)
+        .map(|value| value.get("shellce_encrypted").is_some())
+        .unwrap_or(false)
+}
+
+/// Encrypts `plaintext` with a key derived from `passphrase`, returning the
+/// contents of the encrypted file.
+pub fn encrypt(plaintext: &[u8], passphrase: &str) -> Result<String> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let defaults = Params::default();
+
+    let mut file = EncryptedFile {
+        shellce_encrypted: FORMAT_VERSION,
+        cipher: CIPHER.to_string(),
+        kdf: KdfParams {
+            algorithm: KDF.to_string(),
+            salt: BASE64.encode(salt),
+            memory_kib: defaults.m_cost(),
+            iterations: defaults.t_cost(),
+            parallelism: defaults.p_cost(),
+        },
+        nonce: String::new(),
+        ciphertext: String::new(),
+    };
+
+    let key = file.kdf.derive_key(passphrase)?;
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let aad = file.associated_data();
+    let ciphertext = XChaCha20Poly1305::new(&key)
+        .encrypt(&nonce, Payload { msg: plaintext, aad: &aad })
+        .map_err(|_| anyhow!("Encryption failed"))?;
+
+    file.nonce = BASE64.encode(nonce);
+    file.ciphertext = BASE64.encode(ciphertext);
+    serde_json::to_string_pretty(&file).context("Failed to serialize encrypted memory file")
+}
+
+/// Decrypts the contents of an encrypted file with `passphrase`.
+pub fn decrypt(contents: &str, passphrase: &str) -> Result<Vec<u8>> {
+    let file: EncryptedFile = serde_json::from_str(contents)
+        .context("Encrypted memory file is corrupted (malformed header)")?;
+
+    if file.shellce_encrypted != FORMAT_VERSION {
+        bail!(
+            "Unsupported encrypted memory file version {} (this Shellce reads version {})",
+            file.shellce_encrypted,
+            FORMAT_VERSION
+        );
+    }
+    if file.cipher != CIPHER || file.kdf.algorithm != KDF {
+        bail!(
+            "Unsupported encryption '{}' with key derivation '{}'",
+            file.cipher,
+            file.kdf.algorithm
+        );
+    }
+
+    let nonce = BASE64.decode(&file.nonce).ok().filter(|n| n.len() == 24)
+        .context("Encrypted memory file is corrupted (invalid nonce)")?;
+    let ciphertext = BASE64.decode(&file.ciphertext)
+        .context("Encrypted memory file is corrupted (invalid ciphertext encoding)")?;
+
+    file.kdf.check_limits()?;
+    let key = file.kdf.derive_key(passphrase)?;
+    let aad = file.associated_data();
+    XChaCha20Poly1305::new(&key)
+        .decrypt(XNonce::from_slice(&nonce), Payload { msg: &ciphertext, aad: &aad })
+        .map_err(|_| anyhow!("Wrong passphrase, or the memory file has been tampered with"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value as JsonValue;
+    use std::sync::OnceLock;
+
+    const PLAINTEXT: &[u8] = br#"{"format":"shellce-memory","store":{"variables":{"a":1}}}"#;
+
+    /// `PLAINTEXT` encrypted with "passphrase", shared as key derivation is slow.
+    fn encrypted() -> &'static str {
+        static ENCRYPTED: OnceLock<String> = OnceLock::new();
+        ENCRYPTED.get_or_init(|| encrypt(PLAINTEXT, "passphrase").unwrap())
+    }
+
+    /// Lets `tamper` edit the encrypted file as JSON, and tries to decrypt it.
+    fn decrypt_tampered(tamper: impl FnOnce(&mut JsonValue)) -> Result<Vec<u8>> {
+        let mut file: JsonValue = serde_json::from_str(encrypted()).unwrap();
+        tamper(&mut file);
+        decrypt(&file.to_string(), "passphrase")
+    }
+
+    #[test]
+    fn round_trip() {
+        assert!(is_encrypted(encrypted()));
+        assert!(!encrypted().contains("shellce-memory"));
+        assert_eq!(decrypt(encrypted(), "passphrase").unwrap(), PLAINTEXT);
+    }
+
+    #[test]
+    fn every_file_gets_its_own_salt_and_nonce() {
+        assert_ne!(encrypt(PLAINTEXT, "same").unwrap(), encrypt(PLAINTEXT, "same").unwrap());
+    }
+
+    #[test]
+    fn wrong_passphrase_is_rejected() {
+        let error = decrypt(encrypted(), "wrong passphrase").unwrap_err();
+        assert_eq!(error.to_string(), "Wrong passphrase, or the memory file has been tampered with");
+    }
+
+    #[test]
+    fn tampered_header_is_rejected() {
+        let error = decrypt_tampered(|file| file["kdf"]["iterations"] = JsonValue::from(1)).unwrap_err();
+        assert_eq!(error.to_string(), "Wrong passphrase, or the memory file has been tampered with");
+
+        let other_salt = BASE64.encode([7u8; SALT_LEN]);
+        let error = decrypt_tampered(|file| file["kdf"]["salt"] = JsonValue::from(other_salt)).unwrap_err();
+        assert_eq!(error.to_string(), "Wrong passphrase, or the memory file has been tampered with");
+    }
+
+    #[test]
+    fn oversized_key_derivation_parameters_are_rejected() {
+        for field in ["memory_kib", "iterations", "parallelism"] {
+            let error = decrypt_tampered(|file| file["kdf"][field] = JsonValue::from(u32::MAX)).unwrap_err();
+            assert!(error.to_string().contains("above the limit"), "{}: {}", field, error);
+        }
+    }
+
+    #[test]
+    fn tampered_ciphertext_is_rejected() {
+        let error = decrypt_tampered(|file| {
+            let mut ciphertext = BASE64.decode(file["ciphertext"].as_str().unwrap()).unwrap();
+            ciphertext[0] ^= 1;
+            file["ciphertext"] = JsonValue::from(BASE64.encode(ciphertext));
+        })
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Wrong passphrase, or the memory file has been tampered with");
+    }
+
+    #[test]
+    fn unsupported_or_malformed_headers_are_reported() {
+        let error = decrypt_tampered(|file| file["shellce_encrypted"] = JsonValue::from(2)).unwrap_err();
+        assert_eq!(error.to_string(), "Unsupported encrypted memory file version 2 (this Shellce reads version 1)");
+
+        let error = decrypt_tampered(|file| file["cipher"] = JsonValue::from("aes")).unwrap_err();
+        assert_eq!(error.to_string(), "Unsupported encryption 'aes' with key derivation 'argon2id'");
+
+        let error = decrypt_tampered(|file| file["nonce"] = JsonValue::from("AAAA")).unwrap_err();
+        assert_eq!(error.to_string(), "Encrypted memory file is corrupted (invalid nonce)");
+
+        let error = decrypt("{\"shellce_encrypted\": 1}", "passphrase").unwrap_err();
+        assert_eq!(error.to_string(), "Encrypted memory file is corrupted (malformed header)");
+    }
+
+    #[test]
+    fn plain_memory_files_are_not_encrypted() {
+        assert!(!is_encrypted(std::str::from_utf8(PLAINTEXT).unwrap()));
+        assert!(!is_encrypted("not json"));
+    }
+}
@@ -0,0 +1,205 @@
+// src/storage/format.rs
+// The versioned memory file format and migrations from older versions.
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+
+use crate::core::types::VariableStore;
+
+/// Identifies a memory file, so unrelated JSON is not mistaken for one.
+pub const FORMAT_NAME: &str = "shellce-memory";
+
+/// The schema version written by this build. Bump it whenever the shape of
+/// `VariableStore` changes in a way old files can't be read as-is, and add a
+/// step to `MIGRATIONS`.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Upgrades a document from version `n` (its index here) to `n + 1`.
+type Migration = fn(JsonValue) -> Result<JsonValue>;
+
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize] = [
+    migrate_v0_to_v1,
+];
+
+/// A memory file: the variable store under a header saying which schema it
+/// follows and when it was written.
+#[derive(Debug, Serialize, Deserialize)]
+struct MemoryFile {
+    format: String,
+    schema_version: u32,
+    saved_at: String,
+    store: VariableStore,
+}
+
+/// Builds the current version of the memory file document for `store`.
+/// Backends serialize this document in their own syntax.
+pub fn to_document(store: &VariableStore) -> Result<JsonValue> {
+    let file = MemoryFile {
+        format: FORMAT_NAME.to_string(),
+        schema_version: SCHEMA_VERSION,
+        saved_at: timestamp(),
+        store: store.clone(),
+    };
+    serde_json::to_value(&file).context("Failed to serialize variable store")
+}
+
+/// Reads a memory file document of any supported version, migrating it to
+/// the current one first.
+pub fn from_document(mut document: JsonValue) -> Result<VariableStore> {
+    let version = schema_version(&document)?;
+    check_version(version)?;
+    for (from, migrate) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        info!("Migrating memory file from schema version {} to {}", from, from + 1);
+        document = migrate(document).with_context(|| format!("Migration from schema version {} failed", from))?;
+    }
+
+    let file: MemoryFile = serde_json::from_value(document).context("Unexpected memory file contents")?;
+    info!("Memory file saved at {} (schema version {})", file.saved_at, version);
+    Ok(file.store)
+}
+
+/// Fails if `version` is newer than this build understands.
+pub fn check_version(version: u32) -> Result<()> {
+    if version > SCHEMA_VERSION {
+        return Err(newer_version(version.into()));
+    }
+    Ok(())
+}
+
+fn newer_version(version: u64) -> anyhow::Error {
+    anyhow!(
+        "Memory file uses schema version {}, but this Shellce only reads up to version {}",
+        version,
+        SCHEMA_VERSION
+    )
+}
+
+/// The time of a save, as recorded in the header.
+pub fn timestamp() -> String {
+    chrono::Utc::now().to_rfc3339()
+}
+
+/// Files written before the header existed are version 0.
+fn schema_version(document: &JsonValue) -> Result<u32> {
+    match document.get("format") {
+        None => Ok(0),
+        Some(format) if format == FORMAT_NAME => {
+            let version = document
+                .get("schema_version")
+                .and_then(JsonValue::as_u64)
+                .context("Memory file header has no valid schema_version")?;
+            // Too large for a u32 is still just a version from a newer build.
+            u32::try_from(version).map_err(|_| newer_version(version))
+        },
+        Some(format) => bail!("Not a memory file (format {})", format),
+    }
+}
+
+/// Version 0 is the bare `VariableStore` (`{"variables": {...}}`, possibly
+/// with `exported` and `secrets`); version 1 wraps it in the header. The
+/// original save time is unknown.
+fn migrate_v0_to_v1(document: JsonValue) -> Result<JsonValue> {
+    if document.get("variables").is_none_or(|v| !v.is_object()) {
+        bail!("Expected a 'variables' object");
+    }
+    Ok(json!({
+        "format": FORMAT_NAME,
+        "schema_version": 1,
+        "saved_at": "unknown",
+        "store": document,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn current_documents_round_trip() {
+        let mut store = VariableStore::new();
+        store.insert("name".to_string(), json!("value"));
+        store.export("name".to_string());
+        let document = to_document(&store).unwrap();
+        assert_eq!(document["format"], FORMAT_NAME);
+        assert_eq!(document["schema_version"], SCHEMA_VERSION);
+
+        let loaded = from_document(document).unwrap();
+        assert_eq!(loaded.get("name"), Some(&json!("value")));
+        assert!(loaded.is_exported("name"));
+    }
+
+    #[test]
+    fn version_0_files_are_migrated() {
+        let document = json!({
+            "variables": { "a": 1, "token": "x" },
+            "exported": ["a"],
+            "secrets": ["token"],
+        });
+        let store = from_document(document).unwrap();
+        assert_eq!(store.get("a"), Some(&json!(1)));
+        assert!(store.is_exported("a"));
+        assert!(store.is_secret("token"));
+    }
+
+    #[test]
+    fn migration_wraps_the_store_in_the_header() {
+        let migrated = migrate_v0_to_v1(json!({ "variables": {} })).unwrap();
+        assert_eq!(
+            migrated,
+            json!({
+                "format": FORMAT_NAME,
+                "schema_version": 1,
+                "saved_at": "unknown",
+                "store": { "variables": {} },
+            })
+        );
+        assert_eq!(
+            migrate_v0_to_v1(json!({ "variables": [] })).unwrap_err().to_string(),
+            "Expected a 'variables' object"
+        );
+    }
+
+    #[test]
+    fn invalid_version_0_files_are_rejected_with_the_failed_step() {
+        let error = from_document(json!({ "something": "else" })).unwrap_err();
+        assert_eq!(format!("{:#}", error), "Migration from schema version 0 failed: Expected a 'variables' object");
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let document = json!({
+            "format": FORMAT_NAME,
+            "schema_version": SCHEMA_VERSION + 1,
+            "saved_at": "later",
+            "store": { "variables": {} },
+        });
+        let error = from_document(document).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Memory file uses schema version {}, but this Shellce only reads up to version {}",
+                SCHEMA_VERSION + 1,
+                SCHEMA_VERSION
+            )
+        );
+        assert!(check_version(SCHEMA_VERSION).is_ok());
+
+        let huge = u64::from(u32::MAX) + 1;
+        let document = json!({ "format": FORMAT_NAME, "schema_version": huge, "store": { "variables": {} } });
+        let error = from_document(document).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("Memory file uses schema version {}, but this Shellce only reads up to version {}", huge, SCHEMA_VERSION)
+        );
+    }
+
+    #[test]
+    fn other_documents_are_not_memory_files() {
+        let error = from_document(json!({ "format": "something-else" })).unwrap_err();
+        assert_eq!(error.to_string(), "Not a memory file (format \"something-else\")");
+        let error = from_document(json!({ "format": FORMAT_NAME })).unwrap_err();
+        assert_eq!(error.to_string(), "Memory file header has no valid schema_version");
+    }
+}
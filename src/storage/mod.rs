@@ -2,54 +2,136 @@
 // Handles file-based persistence for variables.
 
 use anyhow::{Result, Context};
-use serde_json;
-use std::fs;
-use std::path::Path;
-use log::{info, error};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use log::info;
 
-use crate::core::types::VariableStore;
+use crate::core::config::ShellConfig;
 
-/// `MemoryStorage` provides methods to save and load the `VariableStore` to/from a file.
+pub mod backend;
+pub mod crypto;
+pub mod format;
+pub mod snapshots;
+pub mod sqlite;
+
+pub use backend::{JsonBackend, StorageBackend, TomlBackend};
+pub use sqlite::SqliteBackend;
+
+/// `MemoryStorage` picks the `StorageBackend` for a memory file: by the
+/// file's extension (`.json`, `.toml`, `.db`/`.sqlite`), or else the
+/// `storage.backend` setting of the config.
 pub struct MemoryStorage;
 
+/// Every backend, by name.
+const BACKENDS: [&dyn StorageBackend; 3] = [&JsonBackend, &TomlBackend, &SqliteBackend];
+
+/// Base name of the memory file used when no path is given.
+const DEFAULT_FILE_STEM: &str = "shellce_memory";
+
 impl MemoryStorage {
-    /// Saves the given `VariableStore` to a JSON file.
-    ///
-    /// # Arguments
-    /// * `store` - The `VariableStore` to save.
-    /// * `path` - The file path to save to.
-    pub async fn save(store: &VariableStore, path: &Path) -> Result<()> {
-        info!("Attempting to save memory to: {:?}", path);
-        let json_string = serde_json::to_string_pretty(store)
-            .context("Failed to serialize variable store to JSON")?;
-
-        // Use tokio::fs for async file operations
-        tokio::fs::write(path, json_string)
-            .await
-            .with_context(|| format!("Failed to write memory file: {:?}", path))?;
-
-        info!("Memory saved successfully to: {:?}", path);
-        Ok(())
+    /// Looks up a backend by its name, as used by `storage.backend`.
+    pub fn backend_named(name: &str) -> Result<&'static dyn StorageBackend> {
+        BACKENDS
+            .into_iter()
+            .find(|backend| backend.name() == name)
+            .with_context(|| {
+                let names: Vec<&str> = BACKENDS.iter().map(|b| b.name()).collect();
+                format!("Unknown storage backend '{}' (expected one of: {})", name, names.join(", "))
+            })
+    }
+
+    /// The backend implied by the extension of `path`, if it has a known one.
+    pub fn backend_by_extension(path: &Path) -> Option<&'static dyn StorageBackend> {
+        let name = match path.extension().and_then(|e| e.to_str())? {
+            "json" => "json",
+            "toml" => "toml",
+            "db" | "sqlite" | "sqlite3" => "sqlite",
+            _ => return None,
+        };
+        Self::backend_named(name).ok()
+    }
+
+    /// Chooses the backend for `path`.
+    pub fn backend_for(path: &Path, config: &ShellConfig) -> Result<&'static dyn StorageBackend> {
+        let backend = match Self::backend_by_extension(path) {
+            Some(backend) => backend,
+            None => Self::backend_named(&config.storage.backend)?,
+        };
+        info!("Using the {} storage backend for {:?}", backend.name(), path);
+        Ok(backend)
+    }
+
+    /// The memory file used when no path is given: `storage.memory_file`, or
+    /// else e.g. `shellce_memory.json` with the default `json` backend.
+    pub fn default_path(config: &ShellConfig) -> Result<PathBuf> {
+        if let Some(path) = &config.storage.memory_file {
+            return Ok(PathBuf::from(path));
+        }
+        let backend = Self::backend_named(&config.storage.backend)?;
+        Ok(PathBuf::from(format!("{}.{}", DEFAULT_FILE_STEM, backend.extension())))
+    }
+}
+
+/// Numbers the temporary files of `write_atomic`, so concurrent writes from
+/// one process (an autosave and a `save`, say) never share one.
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Writes `contents` to `path` via a temporary file in the same directory,
+/// which is flushed to disk and then renamed over `path`. Readers see either
+/// the old file or the new one, never a partial write.
+pub(crate) async fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let file_name = path.file_name().context("Path has no file name")?.to_string_lossy();
+    let unique = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let temp_path = path.with_file_name(format!(".{}.{}.{}.tmp", file_name, std::process::id(), unique));
+
+    let write = async {
+        let mut file = tokio::fs::OpenOptions::new().write(true).create_new(true).open(&temp_path).await?;
+        file.write_all(contents).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&temp_path, path).await
+    };
+    if let Err(e) = write.await {
+        let _ = tokio::fs::remove_file(&temp_path).await;
+        return Err(e.into());
     }
 
-    /// Loads a `VariableStore` from a JSON file.
-    ///
-    /// # Arguments
-    /// * `path` - The file path to load from.
-    ///
-    /// # Returns
-    /// A `Result` containing the loaded `VariableStore` or an `anyhow::Error`.
-    pub async fn load(path: &Path) -> Result<VariableStore> {
-        info!("Attempting to load memory from: {:?}", path);
-        // Use tokio::fs for async file operations
-        let json_string = tokio::fs::read_to_string(path)
-            .await
-            .with_context(|| format!("Failed to read memory file: {:?}", path))?;
-
-        let store: VariableStore = serde_json::from_str(&json_string)
-            .with_context(|| format!("Failed to parse memory file: {:?}", path))?;
-
-        info!("Memory loaded successfully from: {:?}", path);
-        Ok(store)
+    // Persist the rename itself; directories can only be synced on Unix.
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        tokio::fs::File::open(dir).await?.sync_all().await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn concurrent_atomic_writes_leave_one_whole_file() {
+        let dir = crate::util::test_dir("write-atomic");
+        let path = dir.join("memory.json");
+
+        let payloads: Vec<Vec<u8>> = (0..16u8).map(|i| vec![b'a' + i; 64 * 1024]).collect();
+        let mut tasks = tokio::task::JoinSet::new();
+        for payload in payloads.clone() {
+            let path = path.clone();
+            tasks.spawn(async move { write_atomic(&path, &payload).await });
+        }
+        while let Some(joined) = tasks.join_next().await {
+            joined.unwrap().unwrap();
+        }
+
+        let written = std::fs::read(&path).unwrap();
+        let leftovers = std::fs::read_dir(&dir).unwrap().count();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(payloads.contains(&written));
+        assert_eq!(leftovers, 1, "temporary files were left behind");
     }
 }
@@ -0,0 +1,107 @@
+// src/storage/snapshots.rs
+// Numbered, timestamped copies of the variable store, kept in a directory.
+
+use anyhow::{bail, Context, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::core::config::ShellConfig;
+use crate::core::types::VariableStore;
+use super::{write_atomic, MemoryStorage, StorageBackend};
+
+/// Name of the index file listing the snapshots of a directory.
+const INDEX_FILE: &str = "index.json";
+
+/// One entry of the snapshot index.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotInfo {
+    pub id: u32,
+    pub created_at: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Number of variables in the snapshot.
+    pub count: usize,
+    /// The snapshot's file, relative to the snapshot directory.
+    pub file: String,
+}
+
+/// The snapshots in `storage.snapshot_dir`. Each snapshot is a memory file
+/// written with the configured backend; `index.json` lists them in order.
+pub struct Snapshots {
+    dir: PathBuf,
+    backend: &'static dyn StorageBackend,
+    max: usize,
+}
+
+impl Snapshots {
+    pub fn open(config: &ShellConfig) -> Result<Self> {
+        Ok(Snapshots {
+            dir: PathBuf::from(&config.storage.snapshot_dir),
+            backend: MemoryStorage::backend_named(&config.storage.backend)?,
+            max: config.storage.max_snapshots,
+        })
+    }
+
+    /// Lists the snapshots, oldest first.
+    pub async fn list(&self) -> Result<Vec<SnapshotInfo>> {
+        let path = self.dir.join(INDEX_FILE);
+        match tokio::fs::read_to_string(&path).await {
+            Ok(text) => serde_json::from_str(&text).with_context(|| format!("Invalid snapshot index: {:?}", path)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read snapshot index: {:?}", path)),
+        }
+    }
+
+    async fn write_index(&self, index: &[SnapshotInfo]) -> Result<()> {
+        let text = serde_json::to_string_pretty(index).context("Failed to serialize snapshot index")?;
+        write_atomic(&self.dir.join(INDEX_FILE), text.as_bytes())
+            .await
+            .context("Failed to write snapshot index")
+    }
+
+    /// Saves `store` as a new snapshot, removing the oldest ones beyond
+    /// `storage.max_snapshots`.
+    pub async fn create(&self, store: &VariableStore, label: Option<String>) -> Result<SnapshotInfo> {
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .with_context(|| format!("Failed to create snapshot directory: {:?}", self.dir))?;
+
+        let mut index = self.list().await?;
+        let id = index.last().map_or(1, |last| last.id + 1);
+        let info = SnapshotInfo {
+            id,
+            created_at: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            label,
+            count: store.keys().count(),
+            file: format!("snapshot-{}.{}", id, self.backend.extension()),
+        };
+        self.backend.save(store, &self.dir.join(&info.file), None).await?;
+        index.push(info.clone());
+
+        let excess = index.len().saturating_sub(self.max.max(1));
+        for old in index.drain(..excess) {
+            info!("Pruning snapshot {}", old.id);
+            let _ = tokio::fs::remove_file(self.dir.join(&old.file)).await;
+        }
+        self.write_index(&index).await?;
+
+        info!("Created snapshot {} ({} variables) in {:?}", id, info.count, self.dir);
+        Ok(info)
+    }
+
+    /// Loads the snapshot with the given id.
+    pub async fn load(&self, id: u32) -> Result<(SnapshotInfo, VariableStore)> {
+        let Some(info) = self.list().await?.into_iter().find(|info| info.id == id) else {
+            bail!("No snapshot with id {}", id);
+        };
+        let path = self.dir.join(&info.file);
+        let backend = MemoryStorage::backend_by_extension(&path).unwrap_or(self.backend);
+        let store = backend.load(&path, None).await?;
+        Ok((info, store))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
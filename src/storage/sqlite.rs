@@ -0,0 +1,341 @@
+// src/storage/sqlite.rs
+// The embedded single-file database backend, for large memory stores.
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use log::info;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::core::types::VariableStore;
+use super::backend::StorageBackend;
+use super::format;
+
+/// Stores one row per variable in an SQLite file, so saving only writes the
+/// rows that changed and `load_keys` reads only the rows asked for. The
+/// header fields of the other formats live in a `meta` table.
+pub struct SqliteBackend;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS meta (
+        key   TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS variables (
+        name     TEXT PRIMARY KEY,
+        value    TEXT NOT NULL,
+        exported INTEGER NOT NULL DEFAULT 0,
+        secret   INTEGER NOT NULL DEFAULT 0,
+        global   INTEGER NOT NULL DEFAULT 0
+    );";
+
+/// A variable as stored in a row: its value as JSON text, and its export,
+/// secret and global flags.
+type Row = (String, bool, bool, bool);
+
+/// Opens an existing memory database and checks its header.
+fn open_existing(path: &Path) -> Result<Connection> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_WRITE)
+        .with_context(|| format!("Failed to open memory database: {:?}", path))?;
+    let meta = |key: &str| -> Result<Option<String>> {
+        conn.query_row("SELECT value FROM meta WHERE key = ?1", [key], |row| row.get(0))
+            .optional()
+            .with_context(|| format!("Not a memory database: {:?}", path))
+    };
+    if meta("format")?.as_deref() != Some(format::FORMAT_NAME) {
+        bail!("Not a memory database: {:?}", path);
+    }
+    let version: u32 = meta("schema_version")?
+        .and_then(|v| v.parse().ok())
+        .context("Memory database header has no valid schema_version")?;
+    // The first database schema is version 1; migrations go here once it changes.
+    format::check_version(version)?;
+    add_global_column(&conn)?;
+    Ok(conn)
+}
+
+/// Databases written before variables kept their scope have no `global`
+/// column; their variables all belong to the session.
+fn add_global_column(conn: &Connection) -> Result<()> {
+    let has_column = conn
+        .prepare("SELECT 1 FROM pragma_table_info('variables') WHERE name = 'global'")?
+        .exists([])?;
+    if !has_column {
+        conn.execute("ALTER TABLE variables ADD COLUMN global INTEGER NOT NULL DEFAULT 0", [])?;
+        info!("Added the global column to the memory database");
+    }
+    Ok(())
+}
+
+/// Reads every variable, or only those named in `keys`.
+fn read_rows(conn: &Connection, keys: Option<&[String]>) -> Result<VariableStore> {
+    let to_entry = |row: &rusqlite::Row| -> rusqlite::Result<(String, Row)> {
+        Ok((row.get(0)?, (row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)))
+    };
+    let entries: Vec<(String, Row)> = match keys {
+        None => conn
+            .prepare("SELECT name, value, exported, secret, global FROM variables")?
+            .query_map([], to_entry)?
+            .collect::<rusqlite::Result<_>>()?,
+        Some(keys) => {
+            let mut statement = conn.prepare("SELECT name, value, exported, secret, global FROM variables WHERE name = ?1")?;
+            let mut entries = Vec::new();
+            for key in keys {
+                entries.extend(statement.query_row([key], to_entry).optional()?);
+            }
+            entries
+        }
+    };
+
+    let mut store = VariableStore::new();
+    for (name, (value, exported, secret, global)) in entries {
+        let value = serde_json::from_str(&value).with_context(|| format!("Invalid value for '{}'", name))?;
+        if exported {
+            store.export(name.clone());
+        }
+        if secret {
+            store.mark_secret(name.clone());
+        }
+        if global {
+            store.mark_global(name.clone());
+        }
+        store.insert(name, value);
+    }
+    Ok(store)
+}
+
+fn write_rows(path: &Path, store: &VariableStore) -> Result<()> {
+    let mut conn = Connection::open(path).with_context(|| format!("Failed to open memory database: {:?}", path))?;
+    conn.execute_batch(SCHEMA)?;
+    let tx = conn.transaction()?;
+
+    let existing: HashMap<String, Row> = {
+        let mut statement = tx.prepare("SELECT name, value, exported, secret, global FROM variables")?;
+        let rows = statement
+            .query_map([], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))))?;
+        rows.collect::<rusqlite::Result<_>>()?
+    };
+
+    let mut written = 0;
+    for (name, value) in store {
+        let row: Row = (value.to_string(), store.is_exported(name), store.is_secret(name), store.is_global(name));
+        if existing.get(name) == Some(&row) {
+            continue;
+        }
+        tx.execute(
+            "INSERT INTO variables (name, value, exported, secret, global) VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(name) DO UPDATE SET value = ?2, exported = ?3, secret = ?4, global = ?5",
+            params![name, row.0, row.1, row.2, row.3],
+        )?;
+        written += 1;
+    }
+    let mut deleted = 0;
+    for name in existing.keys().filter(|name| store.get(name).is_none()) {
+        tx.execute("DELETE FROM variables WHERE name = ?1", [name])?;
+        deleted += 1;
+    }
+
+    for (key, value) in [
+        ("format", format::FORMAT_NAME.to_string()),
+        ("schema_version", format::SCHEMA_VERSION.to_string()),
+        ("saved_at", format::timestamp()),
+    ] {
+        tx.execute("INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)", [key, value.as_str()])?;
+    }
+    tx.commit()?;
+
+    info!("Memory database {:?} updated: {} written, {} deleted", path, written, deleted);
+    Ok(())
+}
+
+#[async_trait]
+impl StorageBackend for SqliteBackend {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn extension(&self) -> &'static str {
+        "db"
+    }
+
+    async fn save(&self, store: &VariableStore, path: &Path, passphrase: Option<&str>) -> Result<()> {
+        if passphrase.is_some() {
+            bail!("The sqlite backend does not support encryption; use a .json or .toml file");
+        }
+        let (store, path) = (store.clone(), path.to_path_buf());
+        tokio::task::spawn_blocking(move || {
+            if path.exists() {
+                open_existing(&path)?;
+            }
+            write_rows(&path, &store)
+        })
+        .await
+        .context("Database task failed")?
+    }
+
+    async fn load(&self, path: &Path, _passphrase: Option<&str>) -> Result<VariableStore> {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || read_rows(&open_existing(&path)?, None))
+            .await
+            .context("Database task failed")?
+    }
+
+    async fn load_keys(&self, path: &Path, keys: &[String], _passphrase: Option<&str>) -> Result<VariableStore> {
+        let (path, keys): (PathBuf, Vec<String>) = (path.to_path_buf(), keys.to_vec());
+        tokio::task::spawn_blocking(move || read_rows(&open_existing(&path)?, Some(&keys)))
+            .await
+            .context("Database task failed")?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{JsonBackend, StorageBackend, TomlBackend};
+    use crate::util;
+    use serde_json::json;
+
+    fn sample() -> VariableStore {
+        let mut store = VariableStore::new();
+        store.insert("text".to_string(), json!("héllo \"quoted\""));
+        store.insert("number".to_string(), json!(42));
+        store.insert("float".to_string(), json!(1.5));
+        store.insert("flag".to_string(), json!(true));
+        store.insert("list".to_string(), json!(["a", 1, [2]]));
+        store.insert("nested".to_string(), json!({ "db": { "host": "localhost", "ports": [5432] } }));
+        store.insert("ns.dotted".to_string(), json!("key with a dot"));
+        store.export("text".to_string());
+        store.mark_secret("number".to_string());
+        store.mark_global("flag".to_string());
+        store
+    }
+
+    fn assert_same(actual: &VariableStore, expected: &VariableStore) {
+        assert_eq!(actual.all(), expected.all());
+        for key in expected.keys() {
+            assert_eq!(actual.is_exported(key), expected.is_exported(key), "exported flag of {}", key);
+            assert_eq!(actual.is_secret(key), expected.is_secret(key), "secret flag of {}", key);
+            assert_eq!(actual.is_global(key), expected.is_global(key), "global flag of {}", key);
+        }
+    }
+
+    #[tokio::test]
+    async fn every_backend_round_trips_values_and_flags() {
+        let dir = util::test_dir("sqlite-round-trip");
+        let backends: [&dyn StorageBackend; 3] = [&JsonBackend, &TomlBackend, &SqliteBackend];
+        for backend in backends {
+            let path = dir.join(format!("memory.{}", backend.extension()));
+            backend.save(&sample(), &path, None).await.unwrap();
+            assert_same(&backend.load(&path, None).await.unwrap(), &sample());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn stores_move_between_backends_unchanged() {
+        let dir = util::test_dir("sqlite-between");
+        let mut store = sample();
+        let chain: [&dyn StorageBackend; 4] = [&JsonBackend, &SqliteBackend, &TomlBackend, &JsonBackend];
+        for (i, backend) in chain.into_iter().enumerate() {
+            let path = dir.join(format!("memory{}.{}", i, backend.extension()));
+            backend.save(&store, &path, None).await.unwrap();
+            store = backend.load(&path, None).await.unwrap();
+        }
+        assert_same(&store, &sample());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn saving_again_updates_and_deletes_rows() {
+        let dir = util::test_dir("sqlite-update");
+        let path = dir.join("memory.db");
+        SqliteBackend.save(&sample(), &path, None).await.unwrap();
+
+        let mut changed = sample();
+        changed.remove("list");
+        changed.insert("number".to_string(), json!(43));
+        changed.insert("new".to_string(), json!(null));
+        SqliteBackend.save(&changed, &path, None).await.unwrap();
+        assert_same(&SqliteBackend.load(&path, None).await.unwrap(), &changed);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn load_keys_reads_only_the_given_keys() {
+        let dir = util::test_dir("sqlite-load-keys");
+        let path = dir.join("memory.db");
+        SqliteBackend.save(&sample(), &path, None).await.unwrap();
+
+        let keys = ["number".to_string(), "ns.dotted".to_string(), "missing".to_string()];
+        let loaded = SqliteBackend.load_keys(&path, &keys, None).await.unwrap();
+        let mut names: Vec<&String> = loaded.keys().collect();
+        names.sort();
+        assert_eq!(names, ["ns.dotted", "number"]);
+        assert_eq!(loaded.get("number"), Some(&json!(42)));
+        assert!(loaded.is_secret("number"));
+        assert!(SqliteBackend.load_keys(&path, &[], None).await.unwrap().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn load_keys_matches_the_default_for_text_backends() {
+        let dir = util::test_dir("sqlite-load-keys-text");
+        let keys = ["flag".to_string(), "text".to_string()];
+        let path = dir.join("memory.json");
+        JsonBackend.save(&sample(), &path, None).await.unwrap();
+        let from_json = JsonBackend.load_keys(&path, &keys, None).await.unwrap();
+        let path = dir.join("memory.db");
+        SqliteBackend.save(&sample(), &path, None).await.unwrap();
+        let from_sqlite = SqliteBackend.load_keys(&path, &keys, None).await.unwrap();
+        assert_same(&from_sqlite, &from_json);
+        assert_same(&from_json, &sample().select(&keys));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn databases_without_the_global_column_are_upgraded() {
+        let dir = util::test_dir("sqlite-upgrade");
+        let path = dir.join("memory.db");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+             CREATE TABLE variables (name TEXT PRIMARY KEY, value TEXT NOT NULL,
+                                     exported INTEGER NOT NULL DEFAULT 0, secret INTEGER NOT NULL DEFAULT 0);
+             INSERT INTO meta VALUES ('format', 'shellce-memory'), ('schema_version', '1');
+             INSERT INTO variables (name, value, exported) VALUES ('a', '\"x\"', 1);",
+        )
+        .unwrap();
+        drop(conn);
+
+        let loaded = SqliteBackend.load(&path, None).await.unwrap();
+        assert_eq!(loaded.get("a"), Some(&json!("x")));
+        assert!(loaded.is_exported("a") && !loaded.is_global("a"));
+        SqliteBackend.save(&sample(), &path, None).await.unwrap();
+        assert_same(&SqliteBackend.load(&path, None).await.unwrap(), &sample());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unrelated_or_newer_databases_are_rejected() {
+        let dir = util::test_dir("sqlite-reject");
+        let path = dir.join("other.db");
+        Connection::open(&path).unwrap().execute_batch("CREATE TABLE meta (key TEXT, value TEXT);").unwrap();
+        let error = SqliteBackend.load(&path, None).await.unwrap_err();
+        assert!(error.to_string().starts_with("Not a memory database"), "{}", error);
+
+        let path = dir.join("newer.db");
+        SqliteBackend.save(&sample(), &path, None).await.unwrap();
+        let newer = (format::SCHEMA_VERSION + 1).to_string();
+        Connection::open(&path)
+            .unwrap()
+            .execute("UPDATE meta SET value = ?1 WHERE key = 'schema_version'", [newer])
+            .unwrap();
+        let error = SqliteBackend.load(&path, None).await.unwrap_err();
+        assert!(error.to_string().starts_with("Memory file uses schema version"), "{}", error);
+
+        let error = SqliteBackend.save(&sample(), &dir.join("secret.db"), Some("passphrase")).await.unwrap_err();
+        assert!(error.to_string().contains("does not support encryption"), "{}", error);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,14 +1,197 @@
 // src/util/mod.rs
 // Contains general utility functions for the ShellFlow application.
 
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::RwLock;
+
+use lazy_static::lazy_static;
 use log::LevelFilter;
 use env_logger::{Builder, Target};
 
+/// What secret values are replaced with wherever they would be displayed.
+pub const SECRET_MASK: &str = "********";
+
+lazy_static! {
+    // Values of the secret variables currently set, for `redact`, with the
+    // number of variables holding each.
+    static ref SECRET_VALUES: RwLock<HashMap<String, usize>> = RwLock::new(HashMap::new());
+}
+
 /// Initializes the logging system.
 /// This should be called once at the start of the application.
+/// Every message is passed through `redact`, so secret values never reach the log.
 pub fn init_logging() {
     Builder::new()
         .filter_level(LevelFilter::Info) // Set default log level
         .target(Target::Stdout) // Log to standard output
+        .format(|buf, record| {
+            writeln!(
+                buf,
+                "[{} {:<5} {}] {}",
+                buf.timestamp(),
+                record.level(),
+                record.target(),
+                redact(&record.args().to_string())
+            )
+        })
         .init();
 }
+
+/// Registers the value of a secret variable, so `redact` masks it until
+/// every variable holding it is unregistered again.
+pub fn register_secret(value: &serde_json::Value) {
+    let text = format_value(value);
+    if !text.is_empty() {
+        *SECRET_VALUES.write().unwrap().entry(text).or_insert(0) += 1;
+    }
+}
+
+/// Unregisters the value of a secret variable that was changed or removed.
+pub fn unregister_secret(value: &serde_json::Value) {
+    let text = format_value(value);
+    let mut secrets = SECRET_VALUES.write().unwrap();
+    if let Some(count) = secrets.get_mut(&text) {
+        *count -= 1;
+        if *count == 0 {
+            secrets.remove(&text);
+        }
+    }
+}
+
+/// Replaces every known secret value in `text` with `SECRET_MASK`. A value
+/// is only masked where it stands as a whole word, so a secret `42` doesn't
+/// mask the digits of `1425`.
+pub fn redact(text: &str) -> String {
+    let secrets = SECRET_VALUES.read().unwrap();
+    // Longest first, so a secret containing another one is masked whole.
+    let mut values: Vec<&String> = secrets.keys().filter(|value| text.contains(value.as_str())).collect();
+    if values.is_empty() {
+        return text.to_string();
+    }
+    values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut before = None;
+    while let Some(c) = rest.chars().next() {
+        let secret = values.iter().find(|value| {
+            rest.starts_with(value.as_str())
+                && !(is_word_char(value.chars().next()) && is_word_char(before))
+                && !(is_word_char(value.chars().last()) && is_word_char(rest[value.len()..].chars().next()))
+        });
+        match secret {
+            Some(value) => {
+                out.push_str(SECRET_MASK);
+                before = value.chars().last();
+                rest = &rest[value.len()..];
+            }
+            None => {
+                out.push(c);
+                before = Some(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    out
+}
+
+fn is_word_char(c: Option<char>) -> bool {
+    c.is_some_and(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Renders a piece of pipeline data as plain text.
+///
+/// Strings are returned as-is, and objects holding a single string field
+/// (such as `echo`'s `{"output_text": ...}`) collapse to that field so that
+/// text flows naturally between stages. Anything else is rendered as compact JSON.
+pub fn value_to_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Object(map) if map.len() == 1 => match map.values().next() {
+            Some(serde_json::Value::String(s)) => s.clone(),
+            _ => value.to_string(),
+        },
+        _ => value.to_string(),
+    }
+}
+
+/// Collapses an object holding a single string field (such as `echo`'s
+/// `{"output_text": ...}`) to that string, the same way `value_to_text`
+/// renders it. Other values are returned unchanged.
+pub fn collapse_text(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) if map.len() == 1 && map.values().all(|v| v.is_string()) => {
+            map.into_iter().next().map(|(_, v)| v).unwrap_or_default()
+        }
+        other => other,
+    }
+}
+
+/// Renders a variable's value as text: strings as-is, anything else
+/// (numbers, booleans, arrays, objects) as compact JSON.
+pub fn format_value(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        _ => value.to_string(),
+    }
+}
+
+/// Looks up a field of a JSON value by path, e.g. `count`, `items.0.name`
+/// or `items[0].name`. Numeric segments index into arrays.
+pub fn select_path<'a>(value: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
+    path.split(['.', '['])
+        .map(|segment| segment.trim_end_matches(']'))
+        .filter(|segment| !segment.is_empty())
+        .try_fold(value, |current, segment| match current {
+            serde_json::Value::Array(items) => items.get(segment.parse::<usize>().ok()?),
+            serde_json::Value::Object(map) => map.get(segment),
+            _ => None,
+        })
+}
+
+/// A fresh, empty directory for the files of the test named `test`.
+#[cfg(test)]
+pub fn test_dir(test: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("shellce-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn redact_masks_whole_words_only() {
+        register_secret(&json!("s3cr3t-word"));
+        register_secret(&json!(4711));
+
+        assert_eq!(redact("token=s3cr3t-word;"), format!("token={};", SECRET_MASK));
+        assert_eq!(redact("xs3cr3t-words"), "xs3cr3t-words");
+        assert_eq!(redact("4711 but not 47110 or 14711"), format!("{} but not 47110 or 14711", SECRET_MASK));
+    }
+
+    #[test]
+    fn redact_masks_the_longest_secret_first() {
+        register_secret(&json!("outer inner-secret"));
+        register_secret(&json!("inner-secret"));
+
+        assert_eq!(redact("[outer inner-secret]"), format!("[{}]", SECRET_MASK));
+        assert_eq!(redact("inner-secret"), SECRET_MASK);
+    }
+
+    #[test]
+    fn unregistered_secrets_are_no_longer_masked() {
+        let value = json!("short-lived-secret");
+        register_secret(&value);
+        register_secret(&value);
+
+        unregister_secret(&value);
+        assert_eq!(redact("short-lived-secret"), SECRET_MASK);
+        unregister_secret(&value);
+        assert_eq!(redact("short-lived-secret"), "short-lived-secret");
+    }
+}